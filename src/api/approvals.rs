@@ -0,0 +1,135 @@
+use crate::api::invoices::generate_pdf;
+use crate::audit::{Action, AuditLog, ClientIp, ADMIN};
+use crate::auth::Admin;
+use crate::error::Error;
+use crate::jobs::RenderPool;
+use crate::signing::Signer;
+use crate::storage::{Approval, Storage, Version};
+
+use axum::{body::Bytes, extract::Path, http::StatusCode, response::Response};
+use axum_valid::Garde;
+use garde::Validate;
+use serde_derive::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// Body for the request for approving an invoice
+#[derive(Clone, Debug, Serialize, Deserialize, Validate)]
+pub struct ApprovalForm {
+    /// The date of the board meeting
+    #[garde(skip)]
+    #[serde(with = "iso_date")]
+    pub date: time::Date,
+    /// The number of the meeting, e.g. `12` for the meeting 12/2024
+    #[garde(byte_length(min = 1, max = 16))]
+    pub meeting: String,
+    #[garde(byte_length(min = 1, max = 128))]
+    pub approver: String,
+    /// The account the invoice is booked to
+    #[garde(byte_length(min = 1, max = 64))]
+    pub account: String,
+}
+
+/// Render a stored invoice again with the approval and store it as a new version
+///
+/// The earlier versions are kept as they are.
+#[allow(clippy::too_many_arguments)]
+pub async fn approve(
+    _: Admin,
+    storage: Storage,
+    signer: Signer,
+    audit: AuditLog,
+    render_pool: RenderPool,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<Uuid>,
+    Garde(axum::Json(form)): Garde<axum::Json<ApprovalForm>>,
+) -> Result<(StatusCode, [(&'static str, String); 1], axum::Json<Version>), Error> {
+    let stored = storage.get(id).await?;
+    // NOTE: the attachments of invoices submitted before the
+    // versions were stored can't be rendered again
+    if stored.versions.is_empty() {
+        return Err(Error::FilesNotStored);
+    }
+    // NOTE: the personal data and attachments of pseudonymized invoices are gone
+    if stored.pseudonymized_at.is_some() {
+        return Err(Error::Pseudonymized);
+    }
+
+    let approval = Approval {
+        date: form.date,
+        meeting: form.meeting,
+        approver: form.approver,
+        account: form.account,
+        approved_at: OffsetDateTime::now_utc(),
+    };
+    let slot = render_pool.reserve()?;
+    let mut invoice = stored.invoice.clone();
+    invoice.attachments = storage.files(&stored).await?;
+    let (created_at, rendered) = (stored.created_at, approval.clone());
+    let pdf = slot
+        .render(move || {
+            generate_pdf(
+                invoice,
+                id,
+                created_at,
+                crate::CONFIG.pdf_a,
+                &signer,
+                Some(&rendered),
+            )
+        })
+        .await?;
+    drop(slot);
+
+    let details = format!(
+        "version {} approved by {} in meeting {}/{}",
+        stored.versions.len() + 1,
+        approval.approver,
+        approval.meeting,
+        approval.date.year()
+    );
+    let version = storage
+        .insert_version(id, &pdf, approval.approved_at, Some(approval))
+        .await?;
+    audit
+        .record(Action::Approved, Some(id), ADMIN, ip, Some(details))
+        .await?;
+    info!("Approved invoice {id} as version {}", version.number);
+
+    let location = format!("/invoices/{id}/versions/{}", version.number);
+    Ok((
+        StatusCode::CREATED,
+        [("Location", location)],
+        axum::Json(version),
+    ))
+}
+
+/// Get a generated PDF of an invoice
+pub async fn get_version(
+    _: Admin,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    Path((id, number)): Path<(Uuid, u32)>,
+) -> Result<Response, Error> {
+    let pdf = storage.get_version(id, number).await?;
+    audit
+        .record(
+            Action::Downloaded,
+            Some(id),
+            ADMIN,
+            ip,
+            Some(format!("version {number}")),
+        )
+        .await?;
+
+    Ok(Response::builder()
+        .header("Content-Type", "application/pdf")
+        .header(
+            "Content-Disposition",
+            format!("inline; filename=\"lasku-{id}-{number}.pdf\""),
+        )
+        .body(Bytes::from(pdf).into())
+        .unwrap())
+}
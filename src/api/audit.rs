@@ -0,0 +1,47 @@
+use crate::audit::{AuditLog, Entry};
+use crate::auth::Admin;
+use crate::error::Error;
+
+use axum::extract::Query;
+use serde_derive::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub invoice: Option<Uuid>,
+    pub actor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditResponse {
+    /// The matching entries, oldest first
+    pub entries: Vec<Entry>,
+    /// The first entry that doesn't match the hash chain, none if the whole log is intact
+    pub broken_at: Option<u64>,
+    /// The hash of the last entry, which can be recorded elsewhere to detect
+    /// the log being rewritten from that point on
+    pub head: Option<String>,
+}
+
+/// Query the audit log by invoice and actor
+pub async fn query(
+    _: Admin,
+    audit: AuditLog,
+    Query(query): Query<AuditQuery>,
+) -> Result<axum::Json<AuditResponse>, Error> {
+    let entries = audit.read().await?;
+    let broken_at = crate::audit::verify(&entries);
+    let head = entries.last().map(|e| e.hash.clone());
+
+    let entries = entries
+        .into_iter()
+        .filter(|e| query.invoice.is_none() || e.invoice == query.invoice)
+        .filter(|e| query.actor.as_ref().is_none_or(|a| &e.actor == a))
+        .collect();
+
+    Ok(axum::Json(AuditResponse {
+        entries,
+        broken_at,
+        head,
+    }))
+}
@@ -0,0 +1,70 @@
+use crate::audit::{Action, AuditLog, ClientIp, ADMIN};
+use crate::auth::Admin;
+use crate::error::Error;
+use crate::gdpr::{Erasure, Retention};
+use crate::storage::Storage;
+
+use axum::{body::Bytes, extract::Query, response::Response};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Subject {
+    /// The email address of the person
+    pub email: String,
+}
+
+/// Export the data stored about a person as a ZIP archive
+pub async fn export(
+    _: Admin,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    Query(subject): Query<Subject>,
+) -> Result<Response, Error> {
+    let zip = crate::gdpr::export(&storage, &audit, &subject.email).await?;
+    audit
+        .record(
+            Action::Exported,
+            None,
+            ADMIN,
+            ip,
+            Some(crate::audit::subject(&subject.email)),
+        )
+        .await?;
+
+    Ok(Response::builder()
+        .header("Content-Type", "application/zip")
+        .header(
+            "Content-Disposition",
+            "attachment; filename=\"laskugeneraattori.zip\"",
+        )
+        .body(Bytes::from(zip).into())
+        .unwrap())
+}
+
+/// Remove the data of a person from the invoices past the retention period
+pub async fn erase(
+    _: Admin,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    axum::Json(subject): axum::Json<Subject>,
+) -> Result<axum::Json<Erasure>, Error> {
+    let erasure = crate::gdpr::erase(
+        &storage,
+        &audit,
+        Retention::from_config(),
+        &subject.email,
+        ADMIN,
+        ip,
+    )
+    .await?;
+    info!(
+        "Erased {} invoices and {} drafts, {} invoices are retained",
+        erasure.invoices.len(),
+        erasure.drafts.len(),
+        erasure.retained.len()
+    );
+
+    Ok(axum::Json(erasure))
+}
@@ -1,25 +1,38 @@
-use std::sync::LazyLock;
+use std::path::Path as FilePath;
+use std::sync::Arc;
 
+use crate::attachments::Format;
+use crate::audit::{Action, AuditLog, ClientIp, ADMIN, ANONYMOUS};
+use crate::auth::Admin;
 use crate::error::Error;
+use crate::idempotency::{request_hash, Idempotency, IdempotencyKey};
+use crate::jobs::{Jobs, Progress, RenderPool, Reservation, Stage};
 #[cfg(feature = "email")]
 use crate::mailgun::MailgunClient;
+use crate::receipt::{check_totals, purchase_date, read_receipts};
+use crate::signing::Signer;
+use crate::storage::{Approval, Draft, Storage, StoredInvoice};
 
-use axum::{async_trait, body::Bytes, http::StatusCode};
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+};
 use axum_typed_multipart::{
     FieldData, FieldMetadata, TryFromChunks, TryFromMultipart, TypedMultipart, TypedMultipartError,
 };
 
 use axum_valid::Garde;
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt};
 use garde::Validate;
 use iban::Iban;
-use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
-
-use typst::model::Document;
-
-static ALLOWED_FILENAME: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"(?i)\.(jpg|jpeg|png|gif|svg|pdf)$").unwrap());
+use tempfile::NamedTempFile;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
 
 #[async_trait]
 impl TryFromChunks for Invoice {
@@ -33,6 +46,10 @@ impl TryFromChunks for Invoice {
     }
 }
 
+/// The maximum number of rows in an invoice, which bounds how long the
+/// template takes to render
+const MAX_ROWS: usize = 100;
+
 fn is_valid_iban(value: &str, _: &()) -> garde::Result {
     match value.parse::<Iban>() {
         Err(e) => Err(garde::Error::new(e)),
@@ -74,9 +91,24 @@ pub struct Invoice {
     pub phone_number: String,
     #[garde(inner(byte_length(max = 512)))]
     pub attachment_descriptions: Vec<String>,
+    /// Passwords of encrypted PDF attachments, empty for other attachments.
+    /// They are only used to decrypt the attachments and never stored.
+    #[garde(inner(byte_length(max = 128)))]
+    #[serde(default, skip_serializing)]
+    pub attachment_passwords: Vec<String>,
     /// The rows of the invoice
-    #[garde(length(min = 1), dive)]
+    #[garde(length(min = 1, max = MAX_ROWS), dive)]
     pub rows: Vec<InvoiceRow>,
+    /// Draft created from an inbound email, its attachments are
+    /// placed before the ones in the multipart form
+    #[garde(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<Uuid>,
+    /// The token in the link sent to the sender of the draft, which is
+    /// needed for submitting the draft without the admin token
+    #[garde(inner(byte_length(max = 128)))]
+    #[serde(default, skip_serializing)]
+    pub draft_token: Option<String>,
     // NOTE: We get the attachments from the multipart form
     #[garde(skip)]
     #[serde(skip_deserializing)]
@@ -87,10 +119,54 @@ pub struct Invoice {
 pub struct InvoiceForm {
     #[garde(dive)]
     pub data: Invoice,
-    // FIXME: Maybe use NamedTempFile
+    // NOTE: the size of each file is limited by TempFile
     #[garde(skip)]
     #[form_data(limit = "unlimited")]
-    pub attachments: Vec<FieldData<Bytes>>,
+    pub attachments: Vec<FieldData<TempFile>>,
+}
+
+/// A multipart file streamed to a temporary file on disk
+#[derive(Debug)]
+pub struct TempFile {
+    pub file: NamedTempFile,
+}
+
+#[async_trait]
+impl TryFromChunks for TempFile {
+    async fn try_from_chunks(
+        mut chunks: impl Stream<Item = Result<Bytes, TypedMultipartError>> + Send + Sync + Unpin,
+        metadata: FieldMetadata,
+    ) -> Result<Self, TypedMultipartError> {
+        let limit = crate::CONFIG.max_attachment_size;
+        let file =
+            NamedTempFile::new().map_err(|e| TypedMultipartError::Other { source: e.into() })?;
+        let mut writer = tokio::fs::File::from_std(
+            file.reopen()
+                .map_err(|e| TypedMultipartError::Other { source: e.into() })?,
+        );
+
+        let mut size = 0;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            if size > limit {
+                return Err(TypedMultipartError::FieldTooLarge {
+                    field_name: metadata.name.unwrap_or_default(),
+                    limit_bytes: limit as usize,
+                });
+            }
+            writer
+                .write_all(&chunk)
+                .await
+                .map_err(|e| TypedMultipartError::Other { source: e.into() })?;
+        }
+        writer
+            .flush()
+            .await
+            .map_err(|e| TypedMultipartError::Other { source: e.into() })?;
+
+        Ok(Self { file })
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Validate)]
@@ -110,13 +186,52 @@ pub struct InvoiceRow {
     pub unit_price: i32,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+impl InvoiceRow {
+    /// The price of the row in cents
+    pub fn total(&self) -> i64 {
+        self.quantity as i64 * self.unit_price as i64
+    }
+}
+
+impl Invoice {
+    /// The total of the rows in cents
+    pub fn total(&self) -> i64 {
+        self.rows.iter().map(InvoiceRow::total).sum()
+    }
+}
+
+/// An attachment stored in a temporary file, which is removed once
+/// the last clone of the attachment is dropped
+#[derive(Clone, Debug, Serialize)]
 pub struct InvoiceAttachment {
     pub filename: String,
-    pub bytes: Vec<u8>,
+    pub size: u64,
+    pub format: Format,
+    /// The number of pages the attachment takes in the invoice
+    #[serde(skip)]
+    pub pages: usize,
+    #[serde(skip)]
+    pub file: Arc<NamedTempFile>,
+    /// The uncropped image if the attachment was cropped to a receipt
+    #[serde(skip)]
+    pub original: Option<Arc<NamedTempFile>>,
 }
 
-fn try_handle_file(field: FieldData<Bytes>) -> Result<InvoiceAttachment, Error> {
+impl InvoiceAttachment {
+    pub fn path(&self) -> &FilePath {
+        self.file.path()
+    }
+
+    /// Path of the uncropped image, or of the attachment itself
+    pub fn original_path(&self) -> &FilePath {
+        self.original.as_deref().unwrap_or(&self.file).path()
+    }
+}
+
+pub async fn try_handle_file(
+    field: FieldData<TempFile>,
+    password: Option<&str>,
+) -> Result<InvoiceAttachment, Error> {
     let filename = field
         .metadata
         .file_name
@@ -124,82 +239,653 @@ fn try_handle_file(field: FieldData<Bytes>) -> Result<InvoiceAttachment, Error>
         .ok_or(Error::MissingFilename)?
         .to_string();
 
-    if !ALLOWED_FILENAME.is_match(&filename) {
-        return Err(Error::UnsupportedFileFormat(filename));
+    crate::attachments::prepare_blocking(filename, field.contents.file, password.map(String::from))
+        .await
+}
+
+/// Truncate a string to at most `max` bytes without splitting characters
+pub fn truncate(mut s: String, max: usize) -> String {
+    if s.len() > max {
+        let mut end = max;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
     }
+    s
+}
 
-    Ok(InvoiceAttachment {
-        filename,
-        bytes: field.contents.to_vec(),
-    })
+/// Check that the draft of the invoice is submitted with the token sent to
+/// its sender or with the admin token
+async fn authorize_draft(
+    storage: &Storage,
+    admin: Option<Admin>,
+    invoice: &Invoice,
+) -> Result<(), Error> {
+    let Some(id) = invoice.draft else {
+        return Ok(());
+    };
+    let draft = storage.get_draft(id).await?;
+    match (admin, invoice.draft_token.as_deref()) {
+        (Some(_), _) => Ok(()),
+        (None, Some(token)) if draft.accepts(token) => Ok(()),
+        _ => Err(Error::Unauthorized),
+    }
+}
+
+/// Collect the attachments of the draft and the multipart form
+async fn collect_attachments(
+    storage: &Storage,
+    draft: Option<Uuid>,
+    fields: Vec<FieldData<TempFile>>,
+    passwords: &[String],
+) -> Result<Vec<InvoiceAttachment>, Error> {
+    let mut attachments = match draft {
+        Some(id) => storage.draft_attachments(id).await?,
+        None => Vec::new(),
+    };
+    // NOTE: the passwords are indexed like the descriptions, after the draft's attachments
+    let offset = attachments.len();
+    for (i, field) in fields.into_iter().enumerate() {
+        let password = passwords
+            .get(offset + i)
+            .map(String::as_str)
+            .filter(|p| !p.is_empty());
+        attachments.push(try_handle_file(field, password).await?);
+    }
+
+    let total = attachments.iter().map(|a| a.size).sum::<u64>();
+    if total > crate::CONFIG.max_attachments_size {
+        return Err(Error::AttachmentsTooLarge(
+            crate::CONFIG.max_attachments_size,
+        ));
+    }
+
+    Ok(attachments)
+}
+
+/// Add the pages of a part of the invoice to the pages so far, failing with
+/// [`Error::TooManyPages`] if the invoice gets too long
+fn check_pages(pages: usize, more: usize) -> Result<usize, Error> {
+    let pages = pages + more;
+    if pages > crate::CONFIG.max_pages {
+        return Err(Error::TooManyPages(crate::CONFIG.max_pages));
+    }
+    Ok(pages)
+}
+
+/// Render the invoice, merge the PDF attachments after it and attach
+/// the originals of cropped images
+///
+/// Every attachment gets a bookmark named after its filename and description.
+/// The identifier and dates of the document come from the id and creation
+/// time of the stored invoice, so the same invoice always produces the same
+/// bytes. With `pdf_a` the document is made to conform to PDF/A-3b and the
+/// invoice data is embedded in it as JSON. An approved invoice has the approval
+/// filled in and an audit page at the end. The document is signed if signing
+/// is configured.
+pub fn generate_pdf(
+    invoice: Invoice,
+    id: Uuid,
+    created_at: OffsetDateTime,
+    pdf_a: bool,
+    signer: &Signer,
+    approval: Option<&Approval>,
+) -> Result<Vec<u8>, Error> {
+    let attachments = invoice.attachments.clone();
+    // NOTE: the attachments alone can't be too long, checked before the slow render
+    check_pages(0, attachments.iter().map(|a| a.pages).sum())?;
+    let pdf_pages = attachments
+        .iter()
+        .filter(|a| a.format == Format::Pdf)
+        .map(|a| a.pages)
+        .sum();
+    let data = serde_json::to_vec_pretty(&invoice)?;
+    let (title, author) = (invoice.subject.clone(), invoice.recipient_name.clone());
+    let subject = invoice.description.clone();
+    let keywords = format!("lasku, {id}");
+    let modified_at = approval.map_or(created_at, |a| a.approved_at);
+    let titles = attachments
+        .iter()
+        .zip(
+            invoice
+                .attachment_descriptions
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::repeat("")),
+        )
+        .map(|(a, description)| match description.trim() {
+            "" => a.filename.clone(),
+            description => format!("{} – {description}", a.filename),
+        })
+        .collect::<Vec<_>>();
+
+    let audit = approval
+        .map(|approval| crate::pdfgen::render_approval(&invoice, id, created_at, approval))
+        .transpose()?;
+    let document = crate::pdfgen::render(invoice, created_at, approval)?;
+    // NOTE: the rendered document includes the images
+    check_pages(pdf_pages, document.pages.len())?;
+    let created = created_at.to_offset(time::UtcOffset::UTC);
+    let timestamp = typst::foundations::Datetime::from_ymd_hms(
+        created.year(),
+        created.month().into(),
+        created.day(),
+        created.hour(),
+        created.minute(),
+        created.second(),
+    );
+    let pdf = typst_pdf::pdf(
+        &document,
+        typst::foundations::Smart::Custom(&id.to_string()),
+        timestamp,
+    );
+    let rendered = lopdf::Document::load_mem(&pdf)?;
+
+    // NOTE: the template places each image attachment on a page of its own
+    // after the invoice, in the order of the attachments
+    let images = attachments
+        .iter()
+        .filter(|a| a.format != Format::Pdf)
+        .count();
+    let first_image = rendered.get_pages().len().saturating_sub(images);
+    let mut bookmarks = vec![crate::merge::Bookmark {
+        title: "Lasku".into(),
+        page: 0,
+    }];
+    bookmarks.extend(
+        attachments
+            .iter()
+            .zip(&titles)
+            .filter(|(a, _)| a.format != Format::Pdf)
+            .enumerate()
+            .map(|(i, (_, title))| crate::merge::Bookmark {
+                title: title.clone(),
+                page: first_image + i,
+            }),
+    );
+
+    let mut inputs = vec![crate::merge::MergeInput {
+        name: "invoice.pdf".into(),
+        document: rendered,
+        bookmarks,
+    }];
+    for (attachment, title) in attachments
+        .iter()
+        .zip(&titles)
+        .filter(|(a, _)| a.format == Format::Pdf)
+    {
+        inputs.push(crate::merge::MergeInput {
+            name: attachment.filename.clone(),
+            document: lopdf::Document::load(attachment.path()).map_err(|e| {
+                Error::InvalidAttachment(attachment.filename.clone(), e.to_string())
+            })?,
+            bookmarks: vec![crate::merge::Bookmark {
+                title: title.clone(),
+                page: 0,
+            }],
+        });
+    }
+
+    if let Some(audit) = audit {
+        inputs.push(crate::merge::MergeInput {
+            name: "approval.pdf".into(),
+            document: lopdf::Document::load_mem(&typst_pdf::pdf(
+                &audit,
+                typst::foundations::Smart::Custom(&id.to_string()),
+                timestamp,
+            ))?,
+            bookmarks: vec![crate::merge::Bookmark {
+                title: "Hyväksyntä".into(),
+                page: 0,
+            }],
+        });
+    }
+
+    let mut document = crate::merge::merge_pdf(inputs)?;
+
+    let mut originals = Vec::new();
+    for attachment in attachments.iter().filter(|a| a.original.is_some()) {
+        // NOTE: the original is the uploaded file, which may be in another format
+        let data = std::fs::read(attachment.original_path())?;
+        let format = Format::detect(&data).unwrap_or(attachment.format);
+        originals.push(crate::merge::EmbeddedFile {
+            name: FilePath::new(&attachment.filename)
+                .with_extension(format.extension())
+                .to_string_lossy()
+                .into_owned(),
+            description: "Alkuperäinen rajaamaton kuva".into(),
+            mime: format.mime(),
+            relationship: "Source",
+            data,
+        });
+    }
+    let metadata = crate::merge::Metadata {
+        title: &title,
+        author: &author,
+        subject: &subject,
+        keywords: &keywords,
+        created: created_at,
+        modified: modified_at,
+        id: id.into_bytes(),
+    };
+    crate::merge::set_metadata(&mut document, &metadata);
+    if pdf_a {
+        originals.push(crate::merge::EmbeddedFile {
+            name: "invoice.json".into(),
+            description: "Laskun tiedot".into(),
+            mime: "application/json",
+            relationship: "Alternative",
+            data,
+        });
+    }
+    crate::merge::embed_files(&mut document, originals)?;
+
+    if pdf_a {
+        crate::pdfa::convert(&mut document, &metadata)?;
+        let problems = crate::pdfa::check(&document);
+        for problem in &problems {
+            warn!("The invoice doesn't conform to PDF/A-3b: {problem}");
+        }
+        // NOTE: e.g. the fonts of PDF attachments can't be embedded afterwards
+        if !problems.is_empty() {
+            crate::pdfa::unclaim(&mut document, &metadata)?;
+        }
+    }
+
+    signer.sign(document, modified_at)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SubmitOptions {
+    /// Submit the invoice in the background and respond with a job
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+}
+
+/// Respond with a job that can be followed at `/jobs/<id>`
+fn accepted(jobs: &Jobs, progress: &Progress) -> axum::response::Response {
+    let id = progress.id().expect("bug: progress without a job");
+    (
+        StatusCode::ACCEPTED,
+        [("Location", format!("/jobs/{id}"))],
+        axum::Json(jobs.get(id)),
+    )
+        .into_response()
 }
 
 #[cfg(feature = "email")]
+#[allow(clippy::too_many_arguments)]
 pub async fn create_email(
+    admin: Option<Admin>,
     client: MailgunClient,
-    Garde(TypedMultipart(mut multipart)): Garde<TypedMultipart<InvoiceForm>>,
-) -> Result<(StatusCode, axum::Json<Invoice>), Error> {
-    let orig = multipart.data.clone();
-    multipart.data.attachments =
-        Result::from_iter(multipart.attachments.into_iter().map(try_handle_file))?;
-
-    let document: Document = multipart.data.to_owned().try_into()?;
-    let pdf = typst_pdf::pdf(&document, typst::foundations::Smart::Auto, None);
-
-    let mut pdfs = vec![pdf];
-    pdfs.extend_from_slice(
-        multipart
-            .data
-            .attachments
-            .into_iter()
-            .map(|a| a.bytes)
-            .collect::<Vec<_>>()
-            .as_slice(),
-    );
+    storage: Storage,
+    signer: Signer,
+    audit: AuditLog,
+    idempotency: Idempotency,
+    render_pool: RenderPool,
+    jobs: Jobs,
+    ClientIp(ip): ClientIp,
+    IdempotencyKey(key): IdempotencyKey,
+    Query(options): Query<SubmitOptions>,
+    Garde(TypedMultipart(multipart)): Garde<TypedMultipart<InvoiceForm>>,
+) -> Result<axum::response::Response, Error> {
+    let request = request_hash(&multipart.data, &multipart.attachments).await?;
+    idempotency
+        .run(key, request, async move {
+            if let Err(e) = authorize_draft(&storage, admin, &multipart.data).await {
+                return e.into_response();
+            }
+            if !options.run_async {
+                let progress = Progress::default();
+                return send(
+                    client,
+                    storage,
+                    signer,
+                    audit,
+                    ip,
+                    multipart,
+                    Reservation::Later(render_pool),
+                    progress,
+                )
+                .await
+                .map(|stored| (StatusCode::CREATED, axum::Json(stored)))
+                .into_response();
+            }
+
+            // NOTE: the place is reserved before accepting the submission, so
+            // that a full queue is refused with a 503 like synchronous submissions
+            let reservation = match render_pool.reserve() {
+                Ok(slot) => Reservation::Reserved(slot),
+                Err(e) => return e.into_response(),
+            };
+            let progress = jobs.create();
+            let response = accepted(&jobs, &progress);
+            tokio::spawn(async move {
+                let result = send(
+                    client,
+                    storage,
+                    signer,
+                    audit,
+                    ip,
+                    multipart,
+                    reservation,
+                    progress.clone(),
+                )
+                .await;
+                progress.finish(result.map(|stored| stored.id));
+            });
+            response
+        })
+        .await
+}
+
+#[cfg(feature = "email")]
+#[allow(clippy::too_many_arguments)]
+async fn send(
+    client: MailgunClient,
+    storage: Storage,
+    signer: Signer,
+    audit: AuditLog,
+    ip: Option<std::net::IpAddr>,
+    mut multipart: InvoiceForm,
+    reservation: Reservation,
+    progress: Progress,
+) -> Result<StoredInvoice, Error> {
+    progress.set(Stage::Preparing);
+    let mut stored = StoredInvoice::new(multipart.data.clone());
+    multipart.data.attachments = collect_attachments(
+        &storage,
+        multipart.data.draft,
+        multipart.attachments,
+        &multipart.data.attachment_passwords,
+    )
+    .await?;
+    add_warnings(&storage, &mut stored, &multipart.data).await?;
+
+    let attachments = multipart.data.attachments.clone();
+    let slot = reservation.slot()?;
+    progress.set(Stage::Rendering);
+    let (id, created_at) = (stored.id, stored.created_at);
+    let pdf = slot
+        .render(move || {
+            generate_pdf(
+                multipart.data,
+                id,
+                created_at,
+                crate::CONFIG.pdf_a,
+                &signer,
+                None,
+            )
+        })
+        .await?;
+    // NOTE: the place in the queue is freed once the invoice is rendered
+    drop(slot);
 
-    let pdf = crate::merge::merge_pdf(pdfs)?;
+    progress.set(Stage::Sending);
+    stored.delivery.message_id = Some(client.send_mail(&stored, pdf.clone()).await?);
+    progress.set(Stage::Storing);
+    storage.insert_with_files(&mut stored, &attachments).await?;
+    let version = storage
+        .insert_version(stored.id, &pdf, stored.created_at, None)
+        .await?;
+    stored.versions.push(version);
+    let actor = &crate::audit::subject(&stored.invoice.recipient_email);
+    audit
+        .record(Action::Submitted, Some(stored.id), actor, ip, None)
+        .await?;
+    audit
+        .record(
+            Action::EmailSent,
+            Some(stored.id),
+            actor,
+            ip,
+            stored.delivery.message_id.clone(),
+        )
+        .await?;
+    if let Some(draft) = stored.invoice.draft {
+        storage.remove_draft(draft).await?;
+        audit
+            .record(
+                Action::Deleted,
+                Some(draft),
+                actor,
+                ip,
+                Some("draft".into()),
+            )
+            .await?;
+    }
 
-    client.send_mail(&orig, pdf).await?;
-    Ok((StatusCode::CREATED, axum::Json(orig)))
+    info!("Sent invoice {}", stored.id);
+    Ok(stored)
 }
 
 #[cfg(not(feature = "email"))]
+#[allow(clippy::too_many_arguments)]
 pub async fn create(
-    Garde(TypedMultipart(mut multipart)): Garde<TypedMultipart<InvoiceForm>>,
+    admin: Option<Admin>,
+    storage: Storage,
+    signer: Signer,
+    audit: AuditLog,
+    idempotency: Idempotency,
+    render_pool: RenderPool,
+    jobs: Jobs,
+    ClientIp(ip): ClientIp,
+    IdempotencyKey(key): IdempotencyKey,
+    Query(options): Query<SubmitOptions>,
+    Garde(TypedMultipart(multipart)): Garde<TypedMultipart<InvoiceForm>>,
 ) -> Result<axum::response::Response, Error> {
-    use tempfile::NamedTempFile;
-    use tokio::fs::File;
-    use tokio::io::AsyncWriteExt;
-
-    multipart.data.attachments =
-        Result::from_iter(multipart.attachments.into_iter().map(try_handle_file))?;
-
-    let document: Document = multipart.data.to_owned().try_into()?;
-    let pdf = typst_pdf::pdf(&document, typst::foundations::Smart::Auto, None);
-
-    let mut pdfs = vec![pdf];
-    pdfs.extend_from_slice(
-        multipart
-            .data
-            .attachments
-            .into_iter()
-            .map(|a| a.bytes)
-            .collect::<Vec<_>>()
-            .as_slice(),
-    );
+    let request = request_hash(&multipart.data, &multipart.attachments).await?;
+    idempotency
+        .run(key, request, async move {
+            if let Err(e) = authorize_draft(&storage, admin, &multipart.data).await {
+                return e.into_response();
+            }
+            if !options.run_async {
+                let progress = Progress::default();
+                let reservation = Reservation::Later(render_pool);
+                return store(storage, signer, audit, ip, multipart, reservation, progress)
+                    .await
+                    .map(|(stored, pdf)| pdf_response(&stored, pdf))
+                    .into_response();
+            }
+
+            // NOTE: the place is reserved before accepting the submission, so
+            // that a full queue is refused with a 503 like synchronous submissions
+            let reservation = match render_pool.reserve() {
+                Ok(slot) => Reservation::Reserved(slot),
+                Err(e) => return e.into_response(),
+            };
+            let progress = jobs.create();
+            let response = accepted(&jobs, &progress);
+            tokio::spawn(async move {
+                let result = store(
+                    storage,
+                    signer,
+                    audit,
+                    ip,
+                    multipart,
+                    reservation,
+                    progress.clone(),
+                )
+                .await;
+                progress.finish(result.map(|(stored, _)| stored.id));
+            });
+            response
+        })
+        .await
+}
+
+#[cfg(not(feature = "email"))]
+async fn store(
+    storage: Storage,
+    signer: Signer,
+    audit: AuditLog,
+    ip: Option<std::net::IpAddr>,
+    mut multipart: InvoiceForm,
+    reservation: Reservation,
+    progress: Progress,
+) -> Result<(StoredInvoice, Vec<u8>), Error> {
+    progress.set(Stage::Preparing);
+    let mut stored = StoredInvoice::new(multipart.data.clone());
 
-    let pdf = crate::merge::merge_pdf(pdfs)?;
+    multipart.data.attachments = collect_attachments(
+        &storage,
+        multipart.data.draft,
+        multipart.attachments,
+        &multipart.data.attachment_passwords,
+    )
+    .await?;
+    add_warnings(&storage, &mut stored, &multipart.data).await?;
 
-    let tmp = NamedTempFile::with_suffix(".pdf")?;
-    let (file, path) = tmp.keep().unwrap();
-    let mut file = File::from_std(file);
-    file.write_all(&pdf).await?;
+    let attachments = multipart.data.attachments.clone();
+    let slot = reservation.slot()?;
+    progress.set(Stage::Rendering);
+    let (id, created_at) = (stored.id, stored.created_at);
+    let pdf = slot
+        .render(move || {
+            generate_pdf(
+                multipart.data,
+                id,
+                created_at,
+                crate::CONFIG.pdf_a,
+                &signer,
+                None,
+            )
+        })
+        .await?;
+    // NOTE: the place in the queue is freed once the invoice is rendered
+    drop(slot);
+
+    progress.set(Stage::Storing);
+    storage.insert_with_files(&mut stored, &attachments).await?;
+    let version = storage
+        .insert_version(stored.id, &pdf, stored.created_at, None)
+        .await?;
+    stored.versions.push(version);
+    let actor = &crate::audit::subject(&stored.invoice.recipient_email);
+    audit
+        .record(Action::Submitted, Some(stored.id), actor, ip, None)
+        .await?;
+    if let Some(draft) = stored.invoice.draft {
+        storage.remove_draft(draft).await?;
+        audit
+            .record(
+                Action::Deleted,
+                Some(draft),
+                actor,
+                ip,
+                Some("draft".into()),
+            )
+            .await?;
+    }
+
+    info!("Stored invoice {}", stored.id);
+    Ok((stored, pdf))
+}
+
+/// The generated PDF, with the warnings in the `Invoice-Warnings` header and
+/// the ids of the invoices the invoice is a likely duplicate of in the
+/// `Possible-Duplicate-Of` header
+#[cfg(not(feature = "email"))]
+fn pdf_response(stored: &StoredInvoice, pdf: Vec<u8>) -> axum::response::Response {
+    use crate::receipt::Warning;
 
-    info!("Wrote invoice to {:?}", path);
+    let duplicates = stored
+        .warnings
+        .iter()
+        .filter_map(|w| match w {
+            Warning::PossibleDuplicate { invoice, .. } => Some(invoice.to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // NOTE: the messages aren't ASCII, so only the kinds and amounts are sent
+    let warnings = stored
+        .warnings
+        .iter()
+        .map(|w| match w {
+            Warning::TotalMismatch {
+                invoice_total,
+                attachments_total,
+                ..
+            } => format!(
+                "total_mismatch; invoice_total={invoice_total}; attachments_total={attachments_total}"
+            ),
+            Warning::PossibleDuplicate { invoice, .. } => {
+                format!("possible_duplicate; invoice={invoice}")
+            }
+        })
+        .collect::<Vec<_>>();
 
-    Ok(axum::response::Response::builder()
+    let mut response = axum::response::Response::builder()
         .status(StatusCode::CREATED)
         .header("Content-Type", "application/pdf")
-        .body(Bytes::from(pdf).into())
-        .unwrap())
+        .header("Location", format!("/invoices/{}", stored.id));
+    if !warnings.is_empty() {
+        response = response.header("Invoice-Warnings", warnings.join(", "));
+    }
+    if !duplicates.is_empty() {
+        response = response.header("Possible-Duplicate-Of", duplicates.join(", "));
+    }
+    response.body(Bytes::from(pdf).into()).unwrap()
+}
+
+/// Warn about the totals of the receipts and about likely duplicates
+async fn add_warnings(
+    storage: &Storage,
+    stored: &mut StoredInvoice,
+    invoice: &Invoice,
+) -> Result<(), Error> {
+    let receipts = read_receipts(&invoice.attachments).await?;
+    stored
+        .warnings
+        .extend(check_totals(invoice.total(), &receipts));
+
+    let date = purchase_date(&receipts).unwrap_or_else(|| stored.created_at.date().to_string());
+    stored.fingerprint = Some(crate::duplicates::fingerprint(invoice, &date));
+    let duplicates = crate::duplicates::check(storage, stored, &invoice.attachments).await?;
+    stored.warnings.extend(duplicates);
+    Ok(())
+}
+
+pub async fn get(
+    _: Admin,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<Uuid>,
+) -> Result<axum::Json<StoredInvoice>, Error> {
+    let invoice = storage.get(id).await?;
+    audit
+        .record(Action::Viewed, Some(id), ADMIN, ip, None)
+        .await?;
+    Ok(axum::Json(invoice))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DraftQuery {
+    /// The token in the link sent to the sender of the draft
+    pub token: Option<String>,
+}
+
+/// Get a draft with the token sent to its sender or with the admin token
+pub async fn get_draft(
+    admin: Option<Admin>,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DraftQuery>,
+) -> Result<axum::Json<Draft>, Error> {
+    let mut draft = storage.get_draft(id).await?;
+    let actor = match (admin, query.token) {
+        (Some(_), _) => ADMIN,
+        (None, Some(token)) if draft.accepts(&token) => ANONYMOUS,
+        _ => return Err(Error::Unauthorized),
+    };
+    audit
+        .record(Action::Viewed, Some(id), actor, ip, Some("draft".into()))
+        .await?;
+    draft.token_hash = None;
+    Ok(axum::Json(draft))
 }
@@ -0,0 +1,52 @@
+use crate::audit::{Action, AuditLog, ClientIp};
+use crate::error::Error;
+use crate::jobs::{Job, Jobs};
+use crate::storage::Storage;
+
+use axum::{body::Bytes, extract::Path, response::Response};
+use uuid::Uuid;
+
+/// The progress of an invoice submitted with `?async=true`, and the stored
+/// invoice once it's done
+///
+/// NOTE: the job ids are random, so they don't require the admin token
+pub async fn get(jobs: Jobs, Path(id): Path<Uuid>) -> Result<axum::Json<Job>, Error> {
+    jobs.get(id).map(axum::Json).ok_or(Error::NotFound)
+}
+
+/// The PDF of a finished job, for the submitter who only has the job id
+pub async fn pdf(
+    jobs: Jobs,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Error> {
+    let invoice = jobs
+        .get(id)
+        .and_then(|j| j.invoice)
+        .ok_or(Error::NotFound)?;
+    let stored = storage.get(invoice).await?;
+    if stored.pseudonymized_at.is_some() {
+        return Err(Error::Pseudonymized);
+    }
+    let pdf = storage.get_version(invoice, 1).await?;
+    audit
+        .record(
+            Action::Downloaded,
+            Some(invoice),
+            &crate::audit::subject(&stored.invoice.recipient_email),
+            ip,
+            Some(format!("job {id}")),
+        )
+        .await?;
+
+    Ok(Response::builder()
+        .header("Content-Type", "application/pdf")
+        .header(
+            "Content-Disposition",
+            format!("inline; filename=\"lasku-{invoice}.pdf\""),
+        )
+        .body(Bytes::from(pdf).into())
+        .unwrap())
+}
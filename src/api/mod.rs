@@ -9,7 +9,13 @@ use std::time::Duration;
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer, trace::TraceLayer};
 
+pub mod approvals;
+pub mod audit;
+pub mod gdpr;
 pub mod invoices;
+pub mod jobs;
+pub mod receipts;
+pub mod signatures;
 
 pub fn app() -> Router<crate::state::State> {
     let cors_layer = CorsLayer::new().allow_origin(
@@ -20,39 +26,71 @@ pub fn app() -> Router<crate::state::State> {
             .collect::<Vec<_>>(),
     );
 
-    let governor_config = Arc::new(
+    let submissions = Router::new().route(
+        "/invoices",
+        #[cfg(feature = "email")]
+        post(invoices::create_email),
+        #[cfg(not(feature = "email"))]
+        post(invoices::create),
+    );
+    let prefills = Router::new().route("/receipts/prefill", post(receipts::prefill));
+    let verifications = Router::new().route("/signatures/verify", post(signatures::verify));
+
+    let router = Router::new()
+        .route("/health", get(health))
+        // NOTE: only rate limit the routes used by the submitters
+        .merge(rate_limit(submissions, Duration::from_secs(720), 5))
+        .merge(rate_limit(prefills, Duration::from_secs(10), 20))
+        .merge(rate_limit(verifications, Duration::from_secs(10), 20))
+        .route("/invoices/:id", get(invoices::get))
+        .route("/invoices/:id/approve", post(approvals::approve))
+        .route(
+            "/invoices/:id/versions/:number",
+            get(approvals::get_version),
+        )
+        .route("/drafts/:id", get(invoices::get_draft))
+        .route("/jobs/:id", get(jobs::get))
+        .route("/jobs/:id/pdf", get(jobs::pdf))
+        .route("/audit", get(audit::query))
+        .route("/gdpr/export", get(gdpr::export))
+        .route("/gdpr/erase", post(gdpr::erase));
+
+    #[cfg(feature = "email")]
+    let router = router
+        .route("/mailgun/webhooks", post(crate::mailgun::webhooks::receive))
+        .route("/mailgun/inbound", post(crate::mailgun::inbound::receive));
+
+    router
+        .layer(TraceLayer::new_for_http())
+        .layer(cors_layer)
+        .layer(DefaultBodyLimit::disable())
+        // Limit the body to 24 MiB since the email is limited to 25 MiB
+        .layer(RequestBodyLimitLayer::new(24 * 1024 * 1024))
+}
+
+/// Rate limit the POST requests to the routes of the router by ip address
+fn rate_limit(
+    router: Router<crate::state::State>,
+    period: Duration,
+    burst_size: u32,
+) -> Router<crate::state::State> {
+    let config = Arc::new(
         GovernorConfigBuilder::default()
-            .const_period(Duration::from_secs(720))
-            .burst_size(5)
+            .period(period)
+            .burst_size(burst_size)
             .use_headers()
             .methods(vec![Method::POST])
             .finish()
             .unwrap(),
     );
-    let governor_limiter = governor_config.limiter().clone();
+    let limiter = config.limiter().clone();
 
     std::thread::spawn(move || loop {
         std::thread::sleep(Duration::from_secs(60));
-        governor_limiter.retain_recent();
+        limiter.retain_recent();
     });
 
-    Router::new()
-        .route("/health", get(health))
-        .route(
-            "/invoices",
-            #[cfg(feature = "email")]
-            post(invoices::create_email),
-            #[cfg(not(feature = "email"))]
-            post(invoices::create),
-        )
-        .layer(TraceLayer::new_for_http())
-        .layer(cors_layer)
-        .layer(DefaultBodyLimit::disable())
-        // Limit the body to 24 MiB since the email is limited to 25 MiB
-        .layer(RequestBodyLimitLayer::new(24 * 1024 * 1024))
-        .layer(GovernorLayer {
-            config: governor_config,
-        })
+    router.layer(GovernorLayer { config })
 }
 
 async fn health() -> String {
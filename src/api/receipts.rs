@@ -0,0 +1,87 @@
+use crate::api::invoices::{truncate, try_handle_file, InvoiceRow, TempFile};
+use crate::error::Error;
+use crate::receipt::Receipt;
+
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
+use serde_derive::{Deserialize, Serialize};
+use time::format_description::well_known::Iso8601;
+use time::Date;
+
+#[derive(TryFromMultipart)]
+pub struct ReceiptForm {
+    // NOTE: the size of the file is limited by TempFile
+    #[form_data(limit = "unlimited")]
+    pub attachment: FieldData<TempFile>,
+    /// Password of an encrypted PDF
+    pub password: Option<String>,
+}
+
+/// Invoice fields suggested from a receipt, to be confirmed by the user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvoiceSuggestion {
+    pub subject: String,
+    pub description: String,
+    pub rows: Vec<InvoiceRow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Prefill {
+    pub receipt: Receipt,
+    pub invoice: InvoiceSuggestion,
+    /// The text the receipt was read from
+    pub text: String,
+}
+
+impl From<&Receipt> for InvoiceSuggestion {
+    fn from(receipt: &Receipt) -> Self {
+        let merchant = receipt.merchant.clone().unwrap_or_default();
+        // NOTE: the date is shown in the Finnish format
+        let date = receipt
+            .date
+            .as_deref()
+            .and_then(|d| Date::parse(d, &Iso8601::DEFAULT).ok())
+            .map(|d| format!("{}.{}.{}", d.day(), d.month() as u8, d.year()));
+
+        Self {
+            subject: truncate(merchant.clone(), 128),
+            description: truncate(
+                [Some(merchant.as_str()), date.as_deref()]
+                    .into_iter()
+                    .flatten()
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                4096,
+            ),
+            rows: receipt
+                .total
+                .filter(|&total| total > 0)
+                .map(|total| InvoiceRow {
+                    product: match merchant.is_empty() {
+                        true => "Kuitti".into(),
+                        false => truncate(merchant.clone(), 128),
+                    },
+                    quantity: 1,
+                    unit: "kpl".into(),
+                    unit_price: total,
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+/// Read a receipt and suggest the fields of an invoice from it
+pub async fn prefill(
+    TypedMultipart(form): TypedMultipart<ReceiptForm>,
+) -> Result<axum::Json<Prefill>, Error> {
+    let attachment = try_handle_file(form.attachment, form.password.as_deref()).await?;
+    let text = crate::receipt::text(&attachment).await?;
+    let receipt = Receipt::parse(&text);
+
+    Ok(axum::Json(Prefill {
+        invoice: InvoiceSuggestion::from(&receipt),
+        receipt,
+        text,
+    }))
+}
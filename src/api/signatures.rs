@@ -0,0 +1,33 @@
+use crate::api::invoices::TempFile;
+use crate::error::Error;
+use crate::signing::{Signer, Verification};
+
+use axum_typed_multipart::{FieldData, TryFromMultipart, TypedMultipart};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(TryFromMultipart)]
+pub struct VerifyForm {
+    // NOTE: the size of the file is limited by TempFile
+    #[form_data(limit = "unlimited")]
+    pub pdf: FieldData<TempFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    /// The signatures of the PDF, empty if it isn't signed
+    pub signatures: Vec<Verification>,
+}
+
+/// Check the signatures of an uploaded PDF
+pub async fn verify(
+    signer: Signer,
+    TypedMultipart(form): TypedMultipart<VerifyForm>,
+) -> Result<axum::Json<VerifyResponse>, Error> {
+    let filename = form.pdf.metadata.file_name.ok_or(Error::MissingFilename)?;
+    let pdf = tokio::fs::read(form.pdf.contents.file.path()).await?;
+    let signatures = signer
+        .verify(&pdf)
+        .map_err(|e| Error::InvalidAttachment(filename, e.to_string()))?;
+
+    Ok(axum::Json(VerifyResponse { signatures }))
+}
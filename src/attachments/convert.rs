@@ -0,0 +1,163 @@
+use super::Format;
+use crate::error::Error;
+
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use std::io::{BufWriter, Cursor};
+use tempfile::NamedTempFile;
+
+/// Decode a raster image
+///
+/// The EXIF orientation is applied, so the image is upright.
+pub fn decode(filename: &str, data: &[u8], format: Format) -> Result<DynamicImage, Error> {
+    let invalid = |reason: String| Error::InvalidAttachment(filename.to_string(), reason);
+
+    let image_format = match format {
+        Format::Jpg => ImageFormat::Jpeg,
+        Format::Png => ImageFormat::Png,
+        Format::Webp => ImageFormat::WebP,
+        Format::Tiff => ImageFormat::Tiff,
+        Format::Bmp => ImageFormat::Bmp,
+        // NOTE: libheif applies the orientation itself
+        Format::Heif => return decode_heif(filename, data),
+        _ => unreachable!("{} isn't decoded", format.name()),
+    };
+    // NOTE: the size is read from the header before allocating the pixels
+    let (width, height) = image::io::Reader::with_format(Cursor::new(data), image_format)
+        .into_dimensions()
+        .map_err(|e| invalid(e.to_string()))?;
+    check_pixels(filename, width.into(), height.into())?;
+    let mut image = image::load_from_memory_with_format(data, image_format)
+        .map_err(|e| invalid(e.to_string()))?;
+
+    if let Some(orientation) = super::normalize::orientation(data) {
+        super::normalize::apply_orientation(&mut image, orientation);
+    }
+
+    Ok(image)
+}
+
+/// Check the size of an image typst decodes itself from its header, before
+/// the pixels are allocated
+pub fn check_header(filename: &str, data: &[u8], format: Format) -> Result<(), Error> {
+    let invalid = |reason: String| Error::InvalidAttachment(filename.to_string(), reason);
+
+    let (width, height) = match format {
+        // NOTE: the image crate is built without GIF support. The frames are
+        // decoded into the logical screen, whose size follows the signature.
+        Format::Gif => {
+            let size = data
+                .get(6..10)
+                .ok_or_else(|| invalid("the GIF header is truncated".into()))?;
+            (
+                u16::from_le_bytes([size[0], size[1]]).into(),
+                u16::from_le_bytes([size[2], size[3]]).into(),
+            )
+        }
+        Format::Jpg | Format::Png => {
+            let image_format = match format {
+                Format::Jpg => ImageFormat::Jpeg,
+                _ => ImageFormat::Png,
+            };
+            image::io::Reader::with_format(Cursor::new(data), image_format)
+                .into_dimensions()
+                .map_err(|e| invalid(e.to_string()))?
+        }
+        _ => unreachable!("{} isn't decoded by typst", format.name()),
+    };
+    check_pixels(filename, width.into(), height.into())
+}
+
+/// Refuse images with more pixels than configured, which would take too
+/// much memory to decode and render
+pub fn check_pixels(filename: &str, width: u64, height: u64) -> Result<(), Error> {
+    if width.saturating_mul(height) > crate::CONFIG.max_image_pixels {
+        return Err(Error::ImageTooLarge(
+            filename.to_string(),
+            crate::CONFIG.max_image_pixels,
+        ));
+    }
+    Ok(())
+}
+
+/// The format an image in the given format is encoded in
+///
+/// Photos (HEIF and WebP) become JPEGs unless they have transparency,
+/// other formats that typst can't embed become lossless PNGs.
+pub fn target_format(image: &DynamicImage, from: Format) -> Format {
+    match from {
+        Format::Jpg => Format::Jpg,
+        Format::Heif | Format::Webp if !image.color().has_alpha() => Format::Jpg,
+        _ => Format::Png,
+    }
+}
+
+/// Encode an image into a temporary file
+///
+/// Only the pixels are written, so any metadata of the original is dropped.
+pub fn encode(image: &DynamicImage, format: Format) -> Result<NamedTempFile, Error> {
+    let file = NamedTempFile::new()?;
+    let mut writer = BufWriter::new(file.reopen()?);
+    match format {
+        Format::Jpg => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(
+            &mut writer,
+            ImageOutputFormat::Jpeg(crate::CONFIG.jpeg_quality),
+        )?,
+        Format::Png => image.write_to(&mut writer, ImageOutputFormat::Png)?,
+        _ => unreachable!("{} isn't encoded", format.name()),
+    }
+    writer.into_inner().map_err(|e| e.into_error())?;
+
+    Ok(file)
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(filename: &str, data: &[u8]) -> Result<DynamicImage, Error> {
+    use image::{ImageBuffer, Rgb, Rgba};
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let invalid = |reason: String| Error::InvalidAttachment(filename.to_string(), reason);
+
+    let context = HeifContext::read_from_bytes(data).map_err(|e| invalid(e.to_string()))?;
+    let handle = context
+        .primary_image_handle()
+        .map_err(|e| invalid(e.to_string()))?;
+    check_pixels(filename, handle.width().into(), handle.height().into())?;
+    let alpha = handle.has_alpha_channel();
+    let chroma = if alpha {
+        RgbChroma::Rgba
+    } else {
+        RgbChroma::Rgb
+    };
+
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .map_err(|e| invalid(e.to_string()))?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or_else(|| invalid("the image has no interleaved plane".into()))?;
+
+    // NOTE: rows may be padded, so they are copied one at a time
+    let row_len = plane.width as usize * if alpha { 4 } else { 3 };
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    let image = if alpha {
+        ImageBuffer::<Rgba<u8>, _>::from_raw(plane.width, plane.height, pixels)
+            .map(DynamicImage::ImageRgba8)
+    } else {
+        ImageBuffer::<Rgb<u8>, _>::from_raw(plane.width, plane.height, pixels)
+            .map(DynamicImage::ImageRgb8)
+    };
+    image.ok_or_else(|| invalid("the decoded image is truncated".into()))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(filename: &str, _data: &[u8]) -> Result<DynamicImage, Error> {
+    Err(Error::UnsupportedFileFormat(format!(
+        "{filename} ({})",
+        Format::Heif.name()
+    )))
+}
@@ -0,0 +1,133 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb, RgbImage};
+use imageproc::contours::{find_contours, BorderType};
+use imageproc::contrast::{otsu_level, threshold};
+use imageproc::filter::gaussian_blur_f32;
+use imageproc::geometric_transformations::{warp_into, Interpolation, Projection};
+use imageproc::geometry::convex_hull;
+use imageproc::point::Point;
+
+/// Size of the long side of the downscaled copy the receipt is detected from
+const DETECTION_SIZE: u32 = 512;
+
+/// Smallest and largest part of the photo the receipt may cover
+const MIN_COVERAGE: f32 = 0.1;
+const MAX_COVERAGE: f32 = 0.9;
+
+/// How much of the receipt's outline the corners have to cover,
+/// anything less isn't a quadrilateral
+const MIN_FILL: f32 = 0.9;
+
+/// Crop a photo to the receipt in it
+///
+/// The receipt has to be brighter than the background. Its corners are
+/// detected from the outline of the largest bright area, after which the
+/// perspective is corrected and the contrast is stretched. Returns `None`
+/// if no receipt was found.
+pub fn crop(image: &DynamicImage) -> Option<DynamicImage> {
+    let corners = detect(image)?;
+    let mut receipt = straighten(&image.to_rgb8(), corners)?;
+    stretch_contrast(&mut receipt);
+    Some(DynamicImage::ImageRgb8(receipt))
+}
+
+/// Find the corners of the receipt in clockwise order from the top left
+fn detect(image: &DynamicImage) -> Option<[(f32, f32); 4]> {
+    let small = image
+        .resize(DETECTION_SIZE, DETECTION_SIZE, FilterType::Triangle)
+        .to_luma8();
+    let scale = image.width() as f32 / small.width() as f32;
+
+    let blurred = gaussian_blur_f32(&small, 2.0);
+    let binary = threshold(&blurred, otsu_level(&blurred));
+
+    let outline = find_contours::<i32>(&binary)
+        .into_iter()
+        .filter(|c| c.border_type == BorderType::Outer)
+        .map(|c| convex_hull(&c.points))
+        .max_by(|a, b| area(a).total_cmp(&area(b)))?;
+
+    // NOTE: works for receipts that are rotated less than 45°
+    let corners = [
+        *outline.iter().min_by_key(|p| p.x + p.y)?,
+        *outline.iter().max_by_key(|p| p.x - p.y)?,
+        *outline.iter().max_by_key(|p| p.x + p.y)?,
+        *outline.iter().min_by_key(|p| p.x - p.y)?,
+    ];
+
+    let coverage = area(&corners) / (small.width() * small.height()) as f32;
+    if !(MIN_COVERAGE..=MAX_COVERAGE).contains(&coverage)
+        || area(&corners) < MIN_FILL * area(&outline)
+    {
+        return None;
+    }
+
+    Some(corners.map(|p| (p.x as f32 * scale, p.y as f32 * scale)))
+}
+
+/// Area of a polygon with the shoelace formula
+fn area(polygon: &[Point<i32>]) -> f32 {
+    let twice = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .map(|(a, b)| (a.x * b.y - b.x * a.y) as i64)
+        .sum::<i64>();
+    twice.abs() as f32 / 2.0
+}
+
+/// Map the quadrilateral to an upright rectangle
+fn straighten(image: &RgbImage, corners: [(f32, f32); 4]) -> Option<RgbImage> {
+    let distance = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right));
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right));
+
+    let (width, height) = (width.round() as u32, height.round() as u32);
+    let (right, bottom) = (width as f32 - 1.0, height as f32 - 1.0);
+    let projection = Projection::from_control_points(
+        corners,
+        [(0.0, 0.0), (right, 0.0), (right, bottom), (0.0, bottom)],
+    )?;
+
+    let mut receipt = RgbImage::new(width, height);
+    warp_into(
+        image,
+        &projection,
+        Interpolation::Bilinear,
+        Rgb([255, 255, 255]),
+        &mut receipt,
+    );
+    Some(receipt)
+}
+
+/// Stretch the brightness so that the darkest and brightest percent
+/// of the pixels become black and white
+fn stretch_contrast(image: &mut RgbImage) {
+    let luma = |p: &Rgb<u8>| (p[0] as u32 * 299 + p[1] as u32 * 587 + p[2] as u32 * 114) / 1000;
+
+    let mut histogram = [0u32; 256];
+    for pixel in image.pixels() {
+        histogram[luma(pixel) as usize] += 1;
+    }
+    let percentile = |target: u32| {
+        let mut count = 0;
+        histogram
+            .iter()
+            .position(|n| {
+                count += n;
+                count > target
+            })
+            .unwrap_or(255) as f32
+    };
+    let total = image.width() * image.height();
+    let (low, high) = (percentile(total / 100), percentile(total - total / 100));
+    if high - low < 1.0 {
+        return;
+    }
+
+    for pixel in image.pixels_mut() {
+        for channel in pixel.0.iter_mut() {
+            *channel = ((*channel as f32 - low) * 255.0 / (high - low)).clamp(0.0, 255.0) as u8;
+        }
+    }
+}
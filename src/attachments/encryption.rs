@@ -0,0 +1,95 @@
+use crate::error::Error;
+
+use lopdf::{Document, Object, ObjectId};
+
+/// Type object streams are given while loading an encrypted PDF,
+/// so that lopdf doesn't parse them before they are decrypted
+const ENCRYPTED_OBJECT_STREAM: &str = "EncryptedObjStm";
+
+/// Decrypt an encrypted PDF, returns `None` if the PDF isn't encrypted
+///
+/// The password may be either the user or the owner password. The empty
+/// password is always tried, which opens PDFs that only restrict printing
+/// or copying with an owner password.
+pub fn decrypt(
+    filename: &str,
+    data: &[u8],
+    password: Option<&str>,
+) -> Result<Option<Document>, Error> {
+    let invalid = |reason: String| Error::InvalidAttachment(filename.to_string(), reason);
+
+    let document = Document::load_mem(data).map_err(|e| invalid(e.to_string()))?;
+    if !document.is_encrypted() {
+        return Ok(None);
+    }
+    let encrypt = document
+        .trailer
+        .get(b"Encrypt")
+        .map_err(|e| invalid(e.to_string()))?
+        .clone();
+
+    // NOTE: lopdf only decrypts while loading with the empty password and drops
+    // the objects it can't decrypt. With the key of the trailer hidden the
+    // objects are loaded as they are, and decrypted with the right password.
+    let hidden = hide_encryption(data);
+    let reader = lopdf::Reader {
+        buffer: &hidden,
+        document: Document::new(),
+        encryption_state: None,
+        raw_objects: Default::default(),
+    };
+    let mut document = reader
+        .read(Some(hide_object_streams))
+        .map_err(|e| invalid(e.to_string()))?;
+    for object in document.objects.values_mut() {
+        if let Object::Stream(stream) = object {
+            if stream.dict.has_type(ENCRYPTED_OBJECT_STREAM.as_bytes()) {
+                stream.dict.set("Type", "ObjStm");
+            }
+        }
+    }
+    document.trailer.set("Encrypt", encrypt);
+
+    for password in password.into_iter().chain([""]) {
+        match document.decrypt(password) {
+            Ok(()) => {
+                debug!("Decrypted {filename:?}");
+                return Ok(Some(document));
+            }
+            Err(lopdf::Error::Decryption(
+                lopdf::encryption::DecryptionError::IncorrectPassword,
+            )) => {}
+            Err(e) => return Err(invalid(e.to_string())),
+        }
+    }
+    Err(match password {
+        Some(_) => Error::IncorrectPdfPassword(filename.to_string()),
+        None => Error::PdfPasswordRequired(filename.to_string()),
+    })
+}
+
+fn hide_object_streams(id: ObjectId, object: &mut Object) -> Option<(ObjectId, Object)> {
+    if let Object::Stream(stream) = object {
+        if stream.dict.has_type(b"ObjStm") {
+            stream.dict.set("Type", ENCRYPTED_OBJECT_STREAM);
+        }
+    }
+    Some((id, object.clone()))
+}
+
+/// Rename the `/Encrypt` keys of the trailers, keeping the offsets of the objects
+fn hide_encryption(data: &[u8]) -> Vec<u8> {
+    const KEY: &[u8] = b"/Encrypt";
+
+    let mut data = data.to_vec();
+    let mut start = 0;
+    while let Some(i) = data[start..].windows(KEY.len()).position(|w| w == KEY) {
+        let end = start + i + KEY.len();
+        // NOTE: e.g. `/EncryptMetadata` of the encryption dictionary is kept
+        if data.get(end).is_none_or(|b| !b.is_ascii_alphanumeric()) {
+            data[end - 1] = b'X';
+        }
+        start = end;
+    }
+    data
+}
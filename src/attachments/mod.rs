@@ -0,0 +1,257 @@
+use crate::api::invoices::InvoiceAttachment;
+use crate::error::Error;
+
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::NamedTempFile;
+use typst::visualize::{Image, RasterFormat, VectorFormat};
+
+mod convert;
+pub mod crop;
+mod encryption;
+mod normalize;
+
+/// File format of an attachment, detected from the contents of the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Jpg,
+    Png,
+    Gif,
+    Svg,
+    Pdf,
+    Webp,
+    Tiff,
+    Bmp,
+    Heif,
+}
+
+impl Format {
+    /// Detect the format from the magic bytes at the start of the file
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        let format = match data {
+            [0xFF, 0xD8, 0xFF, ..] => Self::Jpg,
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Self::Png,
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Self::Gif,
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Self::Webp,
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Self::Tiff,
+            [b'B', b'M', ..] => Self::Bmp,
+            [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..]
+                if [
+                    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+                ]
+                .iter()
+                .any(|b| brand.starts_with(*b)) =>
+            {
+                Self::Heif
+            }
+            // The header is allowed to be anywhere in the first 1024 bytes
+            _ if data[..data.len().min(1024)]
+                .windows(5)
+                .any(|w| w == b"%PDF-") =>
+            {
+                Self::Pdf
+            }
+            _ if is_svg(data) => Self::Svg,
+            _ => return None,
+        };
+
+        Some(format)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Jpg => "JPEG",
+            Self::Png => "PNG",
+            Self::Gif => "GIF",
+            Self::Svg => "SVG",
+            Self::Pdf => "PDF",
+            Self::Webp => "WebP",
+            Self::Tiff => "TIFF",
+            Self::Bmp => "BMP",
+            Self::Heif => "HEIF",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpg => "jpg",
+            Self::Png => "png",
+            Self::Gif => "gif",
+            Self::Svg => "svg",
+            Self::Pdf => "pdf",
+            Self::Webp => "webp",
+            Self::Tiff => "tiff",
+            Self::Bmp => "bmp",
+            Self::Heif => "heic",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Jpg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Gif => "image/gif",
+            Self::Svg => "image/svg+xml",
+            Self::Pdf => "application/pdf",
+            Self::Webp => "image/webp",
+            Self::Tiff => "image/tiff",
+            Self::Bmp => "image/bmp",
+            Self::Heif => "image/heif",
+        }
+    }
+}
+
+fn is_svg(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(4096)];
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    head.trim_ascii_start().starts_with(b"<") && head.windows(4).any(|w| w == b"<svg")
+}
+
+/// Turn an uploaded file into an attachment
+///
+/// Raster images are rotated upright, downscaled to fit an A4 page
+/// at the configured DPI and re-encoded without metadata. If auto-cropping
+/// is enabled, photos of receipts are cropped to the receipt and the
+/// uploaded file is kept as the original. Formats typst doesn't support
+/// are converted into PNG or JPEG and the extension of the filename is
+/// changed to match. Encrypted PDFs are decrypted with the password.
+pub fn prepare(
+    mut filename: String,
+    mut file: NamedTempFile,
+    password: Option<&str>,
+) -> Result<InvoiceAttachment, Error> {
+    let data = std::fs::read(file.path())?;
+    let format =
+        Format::detect(&data).ok_or_else(|| Error::UnsupportedFileFormat(filename.clone()))?;
+
+    if format == Format::Pdf {
+        if let Some(mut document) = encryption::decrypt(&filename, &data, password)? {
+            file = NamedTempFile::new()?;
+            document.save(file.path())?;
+        }
+    }
+
+    let mut original = None;
+    // NOTE: GIFs may be animated, SVGs and PDFs are kept as is
+    if !matches!(format, Format::Gif | Format::Svg | Format::Pdf) {
+        let image = convert::decode(&filename, &data, format)?;
+        let image = normalize::downscale(image, crate::CONFIG.image_dpi);
+        let target = convert::target_format(&image, format);
+
+        let cropped = match crate::CONFIG.auto_crop {
+            true => crop::crop(&image),
+            false => None,
+        };
+        file = match cropped {
+            Some(cropped) => {
+                debug!("Cropped {filename:?} to a receipt");
+                original = Some(Arc::new(file));
+                convert::encode(&cropped, target)?
+            }
+            None => convert::encode(&image, target)?,
+        };
+
+        if target != format {
+            debug!(
+                "Converted {filename:?} from {} to {}",
+                format.name(),
+                target.name()
+            );
+            filename = Path::new(&filename)
+                .with_extension(target.extension())
+                .to_string_lossy()
+                .into_owned();
+        }
+    }
+
+    let (format, pages) = validate(&filename, file.path())?;
+    Ok(InvoiceAttachment {
+        size: file.as_file().metadata()?.len(),
+        pages,
+        filename,
+        format,
+        file: Arc::new(file),
+        original,
+    })
+}
+
+/// [`prepare`] on a blocking thread, so that decoding and converting images
+/// doesn't stall the async runtime
+pub async fn prepare_blocking(
+    filename: String,
+    file: NamedTempFile,
+    password: Option<String>,
+) -> Result<InvoiceAttachment, Error> {
+    tokio::task::spawn_blocking(move || prepare(filename, file, password.as_deref()))
+        .await
+        .map_err(std::io::Error::from)?
+}
+
+/// The number of pages an attachment takes in the invoice, images take a page each
+pub fn pages(format: Format, path: &Path) -> Result<usize, Error> {
+    match format {
+        Format::Pdf => Ok(lopdf::Document::load(path)?.get_pages().len()),
+        _ => Ok(1),
+    }
+}
+
+/// Check the size of an SVG as decoded by typst, in points
+fn check_size(filename: &str, image: &Image) -> Result<(), Error> {
+    convert::check_pixels(
+        filename,
+        image.width().ceil() as u64,
+        image.height().ceil() as u64,
+    )
+}
+
+/// Detect the format of an attachment and the number of pages it takes in
+/// the invoice, and check that it can be fully decoded
+///
+/// Images are decoded the same way the template decodes them and can't have
+/// more pixels than configured, PDFs have to be parseable and contain at least one page.
+pub fn validate(filename: &str, path: &Path) -> Result<(Format, usize), Error> {
+    let data = std::fs::read(path)?;
+    let invalid = |reason: String| Error::InvalidAttachment(filename.to_string(), reason);
+
+    let format =
+        Format::detect(&data).ok_or_else(|| Error::UnsupportedFileFormat(filename.to_string()))?;
+
+    match format {
+        Format::Jpg | Format::Png | Format::Gif => {
+            let raster = match format {
+                Format::Jpg => RasterFormat::Jpg,
+                Format::Png => RasterFormat::Png,
+                _ => RasterFormat::Gif,
+            };
+            convert::check_header(filename, &data, format)?;
+            Image::new(data.into(), raster.into(), None).map_err(|e| invalid(e.to_string()))?;
+        }
+        Format::Svg => {
+            let image = Image::new(data.into(), VectorFormat::Svg.into(), None)
+                .map_err(|e| invalid(e.to_string()))?;
+            check_size(filename, &image)?;
+        }
+        Format::Pdf => {
+            let document = lopdf::Document::load_mem(&data).map_err(|e| invalid(e.to_string()))?;
+            if document.is_encrypted() {
+                return Err(Error::PdfPasswordRequired(filename.to_string()));
+            }
+            let pages = document.get_pages().len();
+            if pages == 0 {
+                return Err(invalid("the PDF has no pages".into()));
+            }
+            return Ok((format, pages));
+        }
+        // NOTE: these are converted by `prepare`
+        Format::Webp | Format::Tiff | Format::Bmp | Format::Heif => {
+            return Err(Error::UnsupportedFileFormat(format!(
+                "{filename} ({})",
+                format.name()
+            )));
+        }
+    }
+
+    Ok((format, 1))
+}
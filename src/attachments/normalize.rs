@@ -0,0 +1,60 @@
+use image::imageops::{self, FilterType};
+use image::DynamicImage;
+
+/// Size of an A4 page in inches
+const A4: (f64, f64) = (8.27, 11.69);
+
+/// Read the EXIF orientation of an image
+pub fn orientation(data: &[u8]) -> Option<u32> {
+    exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(data))
+        .ok()?
+        .get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)
+}
+
+/// Rotate and flip an image according to its EXIF orientation
+///
+/// See the Orientation tag in <https://exiftool.org/TagNames/EXIF.html>
+pub fn apply_orientation(image: &mut DynamicImage, orientation: u32) {
+    match orientation {
+        2 => imageops::flip_horizontal_in_place(image),
+        3 => imageops::rotate180_in_place(image),
+        4 => imageops::flip_vertical_in_place(image),
+        5 => {
+            imageops::flip_horizontal_in_place(image);
+            *image = image.rotate270();
+        }
+        6 => *image = image.rotate90(),
+        7 => {
+            imageops::flip_horizontal_in_place(image);
+            *image = image.rotate90();
+        }
+        8 => *image = image.rotate270(),
+        _ => {}
+    }
+}
+
+/// Downscale an image so that it fits on an A4 page at the given DPI
+///
+/// The page is turned to match the image, smaller images are left as is.
+pub fn downscale(image: DynamicImage, dpi: u32) -> DynamicImage {
+    let (short, long) = (A4.0 * dpi as f64, A4.1 * dpi as f64);
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let scale = if width <= height {
+        (short / width).min(long / height)
+    } else {
+        (long / width).min(short / height)
+    };
+
+    if scale >= 1.0 {
+        return image;
+    }
+
+    image.resize_exact(
+        ((width * scale).round() as u32).max(1),
+        ((height * scale).round() as u32).max(1),
+        FilterType::CatmullRom,
+    )
+}
@@ -0,0 +1,214 @@
+use crate::error::Error;
+use crate::state::State;
+
+use axum::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// The actor of requests that aren't authenticated
+pub const ANONYMOUS: &str = "anonymous";
+/// The actor of requests authorized with the admin token
+pub const ADMIN: &str = "admin";
+/// The actor of the scheduled removal of old invoices
+pub const RETENTION: &str = "retention";
+/// The actor of mailgun webhooks
+#[cfg(feature = "email")]
+pub const MAILGUN: &str = "mailgun";
+
+/// The pseudonymous id a person is recorded in the log with
+///
+/// The log can't forget anything, so it doesn't keep email addresses. The
+/// entries of a person can still be found with their address.
+pub fn subject(email: &str) -> String {
+    let digest = Sha256::digest(email.trim().to_lowercase().as_bytes());
+    format!("subject-{}", &hex::encode(digest)[..16])
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Submitted,
+    Viewed,
+    Downloaded,
+    Approved,
+    EmailSent,
+    DeliveryStatusChanged,
+    DraftCreated,
+    Deleted,
+    Exported,
+}
+
+/// An entry of the audit log
+///
+/// Every entry contains the hash of the previous one, so modifying or
+/// removing an entry breaks the chain from that entry on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Entries are numbered from 1
+    pub seq: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub action: Action,
+    /// The invoice or draft the action concerns
+    pub invoice: Option<Uuid>,
+    /// Who did it, e.g. the [`subject`] id of the submitter or `admin`
+    pub actor: String,
+    pub ip: Option<IpAddr>,
+    pub details: Option<String>,
+    /// Hex encoded hash of the previous entry, empty for the first entry
+    pub prev_hash: String,
+    /// Hex encoded SHA-256 of this entry with an empty `hash`
+    pub hash: String,
+}
+
+impl Entry {
+    pub fn digest(&self) -> String {
+        let entry = Entry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&entry).expect("bug: unserializable audit entry");
+        hex::encode(Sha256::digest(json))
+    }
+}
+
+/// Append-only audit log of the actions on invoices, stored in
+/// `<root>/audit.jsonl` with one entry per line
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    /// The number and hash of the last entry, read from the file when needed
+    last: Arc<Mutex<Option<(u64, String)>>>,
+}
+
+impl AuditLog {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            path: root.into().join("audit.jsonl"),
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Append an entry to the log
+    pub async fn record(
+        &self,
+        action: Action,
+        invoice: Option<Uuid>,
+        actor: &str,
+        ip: Option<IpAddr>,
+        details: Option<String>,
+    ) -> Result<(), Error> {
+        let mut last = self.last.lock().await;
+        let (seq, prev_hash) = match last.take() {
+            Some(last) => last,
+            None => self
+                .read()
+                .await?
+                .pop()
+                .map(|e| (e.seq, e.hash))
+                .unwrap_or_default(),
+        };
+
+        let mut entry = Entry {
+            seq: seq + 1,
+            timestamp: OffsetDateTime::now_utc(),
+            action,
+            invoice,
+            actor: actor.to_string(),
+            ip,
+            details,
+            prev_hash,
+            hash: String::new(),
+        };
+        entry.hash = entry.digest();
+
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        file.sync_data().await?;
+
+        *last = Some((entry.seq, entry.hash));
+        Ok(())
+    }
+
+    /// Every entry of the log, oldest first
+    pub async fn read(&self) -> Result<Vec<Entry>, Error> {
+        let contents = match tokio::fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(contents
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+/// The number of the first entry that doesn't match the chain, if any
+pub fn verify(entries: &[Entry]) -> Option<u64> {
+    let mut prev_hash = "";
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.seq != i as u64 + 1 || entry.prev_hash != prev_hash || entry.hash != entry.digest()
+        {
+            return Some(entry.seq);
+        }
+        prev_hash = &entry.hash;
+    }
+    None
+}
+
+/// The address of the client, if the server provides it
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+        ))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuditLog
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.audit)
+    }
+}
@@ -0,0 +1,56 @@
+use crate::error::Error;
+use crate::state::State;
+
+use axum::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::{header::AUTHORIZATION, request::Parts},
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// The bearer token of the administrative endpoints, which are
+/// disabled if it isn't configured
+#[derive(Clone, Default)]
+pub struct AdminToken(Option<Arc<str>>);
+
+impl AdminToken {
+    pub fn new(token: Option<&str>) -> Self {
+        Self(token.filter(|t| !t.is_empty()).map(Arc::from))
+    }
+
+    fn accepts(&self, token: &str) -> bool {
+        // NOTE: the digests are compared so that the comparison
+        // doesn't reveal how much of the token is correct
+        self.0
+            .as_deref()
+            .is_some_and(|t| Sha256::digest(t) == Sha256::digest(token))
+    }
+}
+
+/// Extractor for requests authorized with the admin token
+pub struct Admin;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .ok_or(Error::Unauthorized)?;
+
+        match state.admin_token.accepts(token.trim()) {
+            true => Ok(Admin),
+            false => Err(Error::Unauthorized),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::api::invoices::{Invoice, InvoiceAttachment};
+use crate::error::Error;
+use crate::receipt::Warning;
+use crate::storage::{Storage, StoredFile, StoredInvoice};
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+
+/// Hex encoded SHA-256 digest of the submitter, total, date and subject of an invoice
+///
+/// The date is the purchase date read from the receipts, or the date the
+/// invoice was submitted on if there is none. Case and whitespace are ignored.
+pub fn fingerprint(invoice: &Invoice, date: &str) -> String {
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    let total = invoice.total();
+
+    let data = format!(
+        "{}\n{total}\n{date}\n{}",
+        normalize(&invoice.recipient_email),
+        normalize(&invoice.subject)
+    );
+    hex::encode(Sha256::digest(data))
+}
+
+/// Hex encoded SHA-256 digests of the attachments, like their keys in the blob store
+async fn attachment_hashes(
+    attachments: &[InvoiceAttachment],
+) -> Result<Vec<(String, BTreeSet<String>)>, Error> {
+    let mut hashes = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let mut digests = BTreeSet::new();
+        for path in [attachment.path(), attachment.original_path()] {
+            digests.insert(hex::encode(Sha256::digest(tokio::fs::read(path).await?)));
+        }
+        hashes.push((attachment.filename.clone(), digests));
+    }
+    Ok(hashes)
+}
+
+/// Warn about the previous invoices that the invoice is likely a duplicate of
+///
+/// An invoice is a likely duplicate if it has the same fingerprint as a
+/// previous invoice, or if any of its attachments has been attached to one.
+pub async fn check(
+    storage: &Storage,
+    invoice: &StoredInvoice,
+    attachments: &[InvoiceAttachment],
+) -> Result<Vec<Warning>, Error> {
+    let hashes = attachment_hashes(attachments).await?;
+
+    let mut previous = storage.list().await?;
+    previous.retain(|p| p.id != invoice.id && p.pseudonymized_at.is_none());
+    previous.sort_by_key(|p| p.created_at);
+
+    let mut warnings = Vec::new();
+    for previous in previous {
+        let same_details =
+            invoice.fingerprint.is_some() && previous.fingerprint == invoice.fingerprint;
+        let stored = previous
+            .files
+            .iter()
+            .flat_map(StoredFile::blobs)
+            .collect::<BTreeSet<_>>();
+        let same_attachments = hashes
+            .iter()
+            .filter(|(_, h)| h.iter().any(|h| stored.contains(h)))
+            .map(|(filename, _)| filename.clone())
+            .collect::<Vec<_>>();
+
+        if same_details || !same_attachments.is_empty() {
+            warnings.push(Warning::PossibleDuplicate {
+                invoice: previous.id,
+                submitted_at: previous.created_at,
+                same_details,
+                attachments: same_attachments,
+            });
+        }
+    }
+    Ok(warnings)
+}
@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
 };
 
@@ -8,7 +8,7 @@ use serde_derive::Serialize;
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[cfg(feature = "email")]
+    #[cfg(any(feature = "email", feature = "s3"))]
     #[error("Reqwest error {0}")]
     ReqwestError(#[from] reqwest::Error),
     #[error("Error while parsing multipart form")]
@@ -17,16 +17,115 @@ pub enum Error {
     MultipartRejection(#[from] axum::extract::multipart::MultipartRejection),
     #[error("Missing filename multipart")]
     MissingFilename,
-    #[error("Unsupported file format: {0}. Supported file formats are (jpg|jpeg|png|gif|svg|pdf)")]
+    #[error("Unsupported file format: {0}. Supported file formats are (jpg|jpeg|png|gif|svg|pdf|webp|tiff|bmp|heic)")]
     UnsupportedFileFormat(String),
+    #[error("Invalid attachment {0}: {1}")]
+    InvalidAttachment(String, String),
+    #[error("Attachment {0} is protected with a password")]
+    PdfPasswordRequired(String),
+    #[error("Incorrect password for attachment {0}")]
+    IncorrectPdfPassword(String),
     #[error("Error in handling json value")]
     JsonRejection(#[from] axum::extract::rejection::JsonRejection),
     #[error("Error while parsing json")]
     JsonError(#[from] serde_json::Error),
     #[error("Internal server error")]
     InternalServerError(#[from] std::io::Error),
-    #[error("Typst error")]
-    TypstError,
+    #[error("Error while rendering the invoice template")]
+    TypstError(Vec<crate::pdfgen::Diagnostic>),
+    #[error("The invoice couldn't be rendered: {0}")]
+    InvalidRenderInput(String),
+    #[error("PDF error")]
+    PdfError(#[from] lopdf::Error),
+    #[error("Image error")]
+    ImageError(#[from] image::ImageError),
+    #[error("Not found")]
+    NotFound,
+    #[error("Invalid webhook signature")]
+    InvalidSignature,
+    #[error("Missing or invalid admin token")]
+    Unauthorized,
+    #[error("The Idempotency-Key header must be 1-255 visible ASCII characters")]
+    InvalidIdempotencyKey,
+    #[error("The Idempotency-Key has already been used for a different request")]
+    IdempotencyKeyConflict,
+    #[error("The attachments of the invoice aren't stored")]
+    FilesNotStored,
+    #[error("The invoice has been pseudonymized")]
+    Pseudonymized,
+    #[error("The total size of the attachments exceeds {0} bytes")]
+    AttachmentsTooLarge(u64),
+    #[error("Reading receipt images is not configured")]
+    OcrUnavailable,
+    #[error("Error while reading the receipt: {0}")]
+    OcrError(String),
+    #[error("Reading the receipt took longer than {0} seconds")]
+    OcrTimeout(u64),
+    #[error("Error while signing the PDF: {0}")]
+    SigningError(String),
+    #[error("Encryption error: {0}")]
+    EncryptionError(String),
+    #[error("Blob store error: {0}")]
+    BlobStoreError(String),
+    #[error("Too many invoices are being rendered, try again later")]
+    RenderQueueFull,
+    #[error("Rendering the invoice failed")]
+    RenderFailed,
+    #[error("Rendering the invoice took longer than {0} seconds")]
+    RenderTimeout(u64),
+    #[error("The invoice has more than {0} pages")]
+    TooManyPages(usize),
+    #[error("Attachment {0} has more than {1} pixels")]
+    ImageTooLarge(String, u64),
+    #[error("Error while writing the archive")]
+    ZipError(#[from] zip::result::ZipError),
+    #[error("Error while parsing multipart form: {0}")]
+    TypedMultipartError(#[from] axum_typed_multipart::TypedMultipartError),
+}
+
+/// Seconds the clients are asked to wait when the render queue is full
+const RETRY_AFTER_SECONDS: u64 = 10;
+
+impl Error {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::InternalServerError(_)
+            | Error::TypstError(_)
+            | Error::PdfError(_)
+            | Error::ImageError(_)
+            | Error::OcrError(_)
+            | Error::SigningError(_)
+            | Error::EncryptionError(_)
+            | Error::BlobStoreError(_)
+            | Error::RenderFailed
+            | Error::ZipError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            #[cfg(any(feature = "email", feature = "s3"))]
+            Error::ReqwestError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::JsonError(_)
+            | Error::MissingFilename
+            | Error::MultipartError(_)
+            | Error::MultipartRejection(_)
+            | Error::JsonRejection(_)
+            | Error::UnsupportedFileFormat(_)
+            | Error::InvalidAttachment(..)
+            | Error::PdfPasswordRequired(_)
+            | Error::IncorrectPdfPassword(_)
+            | Error::InvalidRenderInput(_)
+            | Error::InvalidIdempotencyKey => StatusCode::BAD_REQUEST,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::OcrUnavailable => StatusCode::NOT_IMPLEMENTED,
+            Error::InvalidSignature | Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::FilesNotStored => StatusCode::CONFLICT,
+            Error::Pseudonymized => StatusCode::GONE,
+            Error::IdempotencyKeyConflict => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::AttachmentsTooLarge(_) | Error::TooManyPages(_) | Error::ImageTooLarge(..) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            Error::RenderTimeout(_) | Error::OcrTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::RenderQueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            Error::TypedMultipartError(e) => e.get_status(),
+        }
+    }
 }
 
 impl IntoResponse for Error {
@@ -38,24 +137,18 @@ impl IntoResponse for Error {
 
         error!(%self);
 
-        let status = match self {
-            Error::InternalServerError(_) | Error::TypstError => StatusCode::INTERNAL_SERVER_ERROR,
-            #[cfg(feature = "email")]
-            Error::ReqwestError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Error::JsonError(_)
-            | Error::MissingFilename
-            | Error::MultipartError(_)
-            | Error::MultipartRejection(_)
-            | Error::JsonRejection(_)
-            | Error::UnsupportedFileFormat(_) => StatusCode::BAD_REQUEST,
-        };
-
-        (
-            status,
+        let mut response = (
+            self.status(),
             axum::Json(ErrorResponse {
                 error: self.to_string(),
             }),
         )
-            .into_response()
+            .into_response();
+        if let Error::RenderQueueFull = self {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, RETRY_AFTER_SECONDS.into());
+        }
+        response
     }
 }
@@ -0,0 +1,300 @@
+use crate::audit::{Action, AuditLog, Entry, RETENTION};
+use crate::error::Error;
+use crate::storage::{Draft, Storage, StoredInvoice};
+
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+use std::net::IpAddr;
+use std::time::Duration;
+use time::{Date, Month, OffsetDateTime};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// What is done to invoices once their retention period has ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    /// Replace the personal data with a pseudonym and remove the PDFs and attachments
+    Pseudonymize,
+    /// Remove the invoice altogether
+    Erase,
+}
+
+/// How long invoices are kept for bookkeeping
+#[derive(Clone, Copy, Debug)]
+pub struct Retention {
+    /// Years after the end of the year the invoice was submitted in
+    pub years: u16,
+    pub action: RetentionAction,
+}
+
+impl Retention {
+    pub fn from_config() -> Self {
+        Self {
+            years: crate::CONFIG.retention_years,
+            action: crate::CONFIG.retention_action,
+        }
+    }
+
+    /// The first day the personal data of the invoice may be removed on
+    pub fn retained_until(&self, invoice: &StoredInvoice) -> Date {
+        let year = invoice.created_at.year() + i32::from(self.years) + 1;
+        Date::from_calendar_date(year, Month::January, 1).expect("bug: invalid retention year")
+    }
+
+    async fn apply(
+        &self,
+        storage: &Storage,
+        audit: &AuditLog,
+        invoice: &StoredInvoice,
+        pseudonym: &str,
+        actor: &str,
+        ip: Option<IpAddr>,
+    ) -> Result<(), Error> {
+        let details = match self.action {
+            RetentionAction::Pseudonymize => {
+                storage.pseudonymize(invoice.id, pseudonym).await?;
+                "pseudonymized"
+            }
+            RetentionAction::Erase => {
+                storage.remove(invoice.id).await?;
+                "erased"
+            }
+        };
+        audit
+            .record(
+                Action::Deleted,
+                Some(invoice.id),
+                actor,
+                ip,
+                Some(details.into()),
+            )
+            .await
+    }
+}
+
+fn pseudonym() -> String {
+    format!("poistettu-{}", &Uuid::new_v4().simple().to_string()[..12])
+}
+
+fn is_subject(email: &str, subject: &str) -> bool {
+    email.trim().eq_ignore_ascii_case(subject.trim())
+}
+
+/// Everything stored about a person
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Export {
+    pub email: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub exported_at: OffsetDateTime,
+    pub invoices: Vec<StoredInvoice>,
+    pub drafts: Vec<Draft>,
+    /// Entries of the audit log about the invoices and drafts, or by the person
+    pub audit: Vec<Entry>,
+}
+
+/// Collect the data stored about a person into a ZIP archive
+///
+/// The archive contains the data as `data.json`, the generated PDFs as
+/// `invoices/<id>/<version>.pdf` and the attachments of the invoices and drafts
+/// in `invoices/<id>/attachments/` and `drafts/<id>/`.
+pub async fn export(storage: &Storage, audit: &AuditLog, email: &str) -> Result<Vec<u8>, Error> {
+    let mut invoices = storage.list().await?;
+    invoices.retain(|i| is_subject(&i.invoice.recipient_email, email));
+    invoices.sort_by_key(|i| i.created_at);
+    let mut drafts = storage.list_drafts().await?;
+    drafts.retain(|d| is_subject(&d.recipient_email, email));
+    drafts.sort_by_key(|d| d.created_at);
+
+    let mut files = Vec::new();
+    for invoice in &invoices {
+        for version in &invoice.versions {
+            // NOTE: the PDFs of pseudonymized invoices have been removed
+            match storage.get_version(invoice.id, version.number).await {
+                Ok(pdf) => files.push((
+                    format!("invoices/{}/{}.pdf", invoice.id, version.number),
+                    pdf,
+                )),
+                Err(Error::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        for (i, attachment) in storage.files(invoice).await?.iter().enumerate() {
+            files.push((
+                format!(
+                    "invoices/{}/attachments/{i}-{}",
+                    invoice.id,
+                    safe_filename(&attachment.filename)
+                ),
+                tokio::fs::read(attachment.original_path()).await?,
+            ));
+        }
+    }
+    for draft in &drafts {
+        for (i, attachment) in storage
+            .draft_attachments(draft.id)
+            .await?
+            .iter()
+            .enumerate()
+        {
+            files.push((
+                format!(
+                    "drafts/{}/{i}-{}",
+                    draft.id,
+                    safe_filename(&attachment.filename)
+                ),
+                tokio::fs::read(attachment.original_path()).await?,
+            ));
+        }
+    }
+
+    let ids = invoices
+        .iter()
+        .map(|i| i.id)
+        .chain(drafts.iter().map(|d| d.id))
+        .collect::<BTreeSet<_>>();
+    let mut entries = audit.read().await?;
+    let subject = crate::audit::subject(email);
+    entries.retain(|e| e.actor == subject || e.invoice.is_some_and(|i| ids.contains(&i)));
+
+    let data = Export {
+        email: email.to_string(),
+        exported_at: OffsetDateTime::now_utc(),
+        invoices,
+        drafts,
+        audit: entries,
+    };
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    zip.start_file("data.json", options)?;
+    zip.write_all(&serde_json::to_vec_pretty(&data)?)?;
+    for (name, contents) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(&contents)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+fn safe_filename(filename: &str) -> String {
+    filename.replace(['/', '\\'], "_")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Retained {
+    pub id: Uuid,
+    #[serde(with = "iso_date")]
+    pub retained_until: Date,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Erasure {
+    /// Invoices that were pseudonymized or erased
+    pub invoices: Vec<Uuid>,
+    /// Invoices that still have to be kept for bookkeeping
+    pub retained: Vec<Retained>,
+    /// Drafts are removed right away
+    pub drafts: Vec<Uuid>,
+}
+
+/// Remove the data of a person from the invoices whose retention period has
+/// ended and remove their drafts
+pub async fn erase(
+    storage: &Storage,
+    audit: &AuditLog,
+    retention: Retention,
+    email: &str,
+    actor: &str,
+    ip: Option<IpAddr>,
+) -> Result<Erasure, Error> {
+    let today = OffsetDateTime::now_utc().date();
+    let pseudonym = pseudonym();
+    let mut erasure = Erasure::default();
+
+    for invoice in storage.list().await? {
+        if !is_subject(&invoice.invoice.recipient_email, email) {
+            continue;
+        }
+        let retained_until = retention.retained_until(&invoice);
+        if retained_until > today {
+            erasure.retained.push(Retained {
+                id: invoice.id,
+                retained_until,
+            });
+            continue;
+        }
+        retention
+            .apply(storage, audit, &invoice, &pseudonym, actor, ip)
+            .await?;
+        erasure.invoices.push(invoice.id);
+    }
+
+    for draft in storage.list_drafts().await? {
+        if is_subject(&draft.recipient_email, email) {
+            storage.remove_draft(draft.id).await?;
+            audit
+                .record(
+                    Action::Deleted,
+                    Some(draft.id),
+                    actor,
+                    ip,
+                    Some("draft".into()),
+                )
+                .await?;
+            erasure.drafts.push(draft.id);
+        }
+    }
+
+    Ok(erasure)
+}
+
+/// Apply the retention policy to every invoice whose retention period has
+/// ended by `today`
+///
+/// The invoices of the same person get the same pseudonym.
+pub async fn purge(
+    storage: &Storage,
+    audit: &AuditLog,
+    retention: Retention,
+    today: Date,
+) -> Result<Vec<Uuid>, Error> {
+    let mut pseudonyms = HashMap::new();
+    let mut purged = Vec::new();
+
+    for invoice in storage.list().await? {
+        if invoice.pseudonymized_at.is_some() || retention.retained_until(&invoice) > today {
+            continue;
+        }
+        let pseudonym = pseudonyms
+            .entry(invoice.invoice.recipient_email.to_lowercase())
+            .or_insert_with(pseudonym);
+        retention
+            .apply(storage, audit, &invoice, pseudonym, RETENTION, None)
+            .await?;
+        purged.push(invoice.id);
+    }
+
+    Ok(purged)
+}
+
+/// Purge the invoices once a day in the background
+pub fn schedule(storage: Storage, audit: AuditLog) {
+    let retention = Retention::from_config();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(24 * 60 * 60));
+        loop {
+            interval.tick().await;
+            let today = OffsetDateTime::now_utc().date();
+            match purge(&storage, &audit, retention, today).await {
+                Ok(purged) if !purged.is_empty() => {
+                    info!("Purged {} invoices past the retention period", purged.len())
+                }
+                Ok(_) => {}
+                Err(e) => error!("Purging invoices failed: {e}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,295 @@
+use crate::api::invoices::{Invoice, TempFile};
+use crate::error::Error;
+use crate::state::State;
+use crate::storage::Storage;
+
+use axum::async_trait;
+use axum::{
+    body::Body,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_typed_multipart::FieldData;
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+/// The longest accepted idempotency key
+const MAX_KEY_LENGTH: usize = 255;
+
+/// The `Idempotency-Key` header, with which a client can safely retry
+/// submitting an invoice
+pub struct IdempotencyKey(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(key) = parts.headers.get("Idempotency-Key") else {
+            return Ok(Self(None));
+        };
+        match key.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => {
+                Ok(Self(Some(key.to_string())))
+            }
+            _ => Err(Error::InvalidIdempotencyKey),
+        }
+    }
+}
+
+/// Where the outcomes of requests with idempotency keys are kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum IdempotencyBackend {
+    /// Lost when the server is restarted
+    Memory,
+    /// In the data directory, encrypted like the invoices
+    Persistent,
+}
+
+/// The response to the first request with an idempotency key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Outcome {
+    /// Hex encoded SHA-256 digest of the request, which retries must match
+    pub request: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    #[serde(with = "hex")]
+    pub body: Vec<u8>,
+}
+
+impl IntoResponse for Outcome {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let headers = response.headers_mut();
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::try_from(value))
+            {
+                headers.append(name, value);
+            }
+        }
+        headers.insert("Idempotent-Replayed", HeaderValue::from_static("true"));
+        response
+    }
+}
+
+#[async_trait]
+pub trait IdempotencyStore: std::fmt::Debug + Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<Outcome>, Error>;
+    async fn insert(&self, key: &str, outcome: &Outcome) -> Result<(), Error>;
+    /// Remove the outcomes created before the time
+    async fn purge(&self, before: OffsetDateTime) -> Result<(), Error>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    outcomes: std::sync::Mutex<HashMap<String, Outcome>>,
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Outcome>, Error> {
+        Ok(self.outcomes.lock().unwrap().get(key).cloned())
+    }
+
+    async fn insert(&self, key: &str, outcome: &Outcome) -> Result<(), Error> {
+        self.outcomes
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), outcome.clone());
+        Ok(())
+    }
+
+    async fn purge(&self, before: OffsetDateTime) -> Result<(), Error> {
+        self.outcomes
+            .lock()
+            .unwrap()
+            .retain(|_, o| o.created_at >= before);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for Storage {
+    async fn get(&self, key: &str) -> Result<Option<Outcome>, Error> {
+        self.get_outcome(key).await
+    }
+
+    async fn insert(&self, key: &str, outcome: &Outcome) -> Result<(), Error> {
+        self.insert_outcome(key, outcome).await
+    }
+
+    async fn purge(&self, before: OffsetDateTime) -> Result<(), Error> {
+        self.purge_outcomes(before).await
+    }
+}
+
+/// Replays the outcome of the first request with an idempotency key to
+/// retries within the window
+///
+/// Requests with the same key are handled one at a time, so a retry sent
+/// while the first request is still being handled waits for its outcome.
+/// Server errors aren't kept, so that the request can be retried.
+#[derive(Clone, Debug)]
+pub struct Idempotency {
+    store: Arc<dyn IdempotencyStore>,
+    window: Duration,
+    locks: Arc<std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+impl Idempotency {
+    pub fn new(store: impl IdempotencyStore + 'static, window: Duration) -> Self {
+        Self {
+            store: Arc::new(store),
+            window,
+            locks: Arc::default(),
+        }
+    }
+
+    pub fn from_config(storage: &Storage) -> Self {
+        let window = Duration::hours(crate::CONFIG.idempotency_window_hours.into());
+        match crate::CONFIG.idempotency_store {
+            IdempotencyBackend::Memory => Self::new(MemoryStore::default(), window),
+            IdempotencyBackend::Persistent => Self::new(storage.clone(), window),
+        }
+    }
+
+    /// Remove the outcomes that are no longer replayed
+    pub async fn purge(&self) -> Result<(), Error> {
+        self.store
+            .purge(OffsetDateTime::now_utc() - self.window)
+            .await
+    }
+
+    /// Purge the outcomes once an hour in the background
+    pub fn schedule(&self) {
+        let idempotency = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = idempotency.purge().await {
+                    error!("Purging idempotency outcomes failed: {e}");
+                }
+            }
+        });
+    }
+
+    /// Handle a request, or replay the outcome of the first request with the key
+    pub async fn run(
+        &self,
+        key: Option<String>,
+        request: String,
+        handler: impl Future<Output = Response>,
+    ) -> Result<Response, Error> {
+        let Some(key) = key else {
+            return Ok(handler.await);
+        };
+
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let guard = lock.lock().await;
+        let result = self.run_locked(&key, request, handler).await;
+        drop(guard);
+
+        let mut locks = self.locks.lock().unwrap();
+        // NOTE: the lock is only shared by the map and this request
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(&key);
+        }
+        result
+    }
+
+    async fn run_locked(
+        &self,
+        key: &str,
+        request: String,
+        handler: impl Future<Output = Response>,
+    ) -> Result<Response, Error> {
+        let now = OffsetDateTime::now_utc();
+        let previous = self
+            .store
+            .get(key)
+            .await?
+            .filter(|o| o.created_at + self.window > now);
+        if let Some(outcome) = previous {
+            return match outcome.request == request {
+                true => Ok(outcome.into_response()),
+                false => Err(Error::IdempotencyKeyConflict),
+            };
+        }
+
+        let response = handler.await;
+        if response.status().is_server_error() {
+            return Ok(response);
+        }
+
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .map_err(std::io::Error::other)?;
+        let outcome = Outcome {
+            request,
+            created_at: now,
+            status: parts.status.as_u16(),
+            headers: parts
+                .headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+                .collect(),
+            body: body.to_vec(),
+        };
+        self.store.insert(key, &outcome).await?;
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+/// Hex encoded SHA-256 digest of the invoice and the uploaded files, which
+/// is the same for retries of the same request
+pub async fn request_hash(
+    invoice: &Invoice,
+    files: &[FieldData<TempFile>],
+) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(invoice)?);
+    // NOTE: the token isn't serialized, but a retry with another token is another request
+    hasher.update(invoice.draft_token.as_deref().unwrap_or_default());
+    for file in files {
+        hasher.update(file.metadata.file_name.as_deref().unwrap_or_default());
+        hasher.update(Sha256::digest(
+            tokio::fs::read(file.contents.file.path()).await?,
+        ));
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Idempotency
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.idempotency)
+    }
+}
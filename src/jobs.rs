@@ -0,0 +1,285 @@
+use crate::error::Error;
+use crate::state::State;
+
+use axum::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+/// How long finished jobs can be queried
+const JOB_RETENTION: Duration = Duration::hours(24);
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Dedicated threads for rendering PDFs, so that compiling the Typst
+/// templates, embedding the attachments and merging the PDFs don't block
+/// the async runtime
+///
+/// Each thread keeps its own Typst world. At most as many renders as there
+/// are threads and places in the queue are accepted at a time, which also
+/// limits how many renders run concurrently across the server.
+#[derive(Clone)]
+pub struct RenderPool {
+    sender: mpsc::Sender<Task>,
+    slots: Arc<Semaphore>,
+    timeout: Option<std::time::Duration>,
+}
+
+impl RenderPool {
+    pub fn new(workers: usize, queue: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("render-{i}"))
+                .spawn(move || loop {
+                    // NOTE: the threads stop once the pool has been dropped
+                    let Ok(task) = receiver.lock().unwrap().recv() else {
+                        return;
+                    };
+                    // The panic is reported to the waiting request
+                    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(task));
+                })
+                .expect("Failed to start a render thread");
+        }
+
+        Self {
+            sender,
+            slots: Arc::new(Semaphore::new(workers.max(1) + queue)),
+            timeout: None,
+        }
+    }
+
+    /// Fail renders that take longer than the timeout with [`Error::RenderTimeout`]
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn from_config() -> Self {
+        let workers = crate::CONFIG.render_workers.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        });
+        Self::new(workers, crate::CONFIG.render_queue).with_timeout(std::time::Duration::from_secs(
+            crate::CONFIG.render_timeout_secs,
+        ))
+    }
+
+    /// Reserve a place in the queue, fails with [`Error::RenderQueueFull`]
+    /// if the queue is full
+    pub fn reserve(&self) -> Result<Slot, Error> {
+        let permit = self
+            .slots
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Error::RenderQueueFull)?;
+        Ok(Slot {
+            sender: self.sender.clone(),
+            permit: Arc::new(permit),
+            timeout: self.timeout,
+        })
+    }
+}
+
+/// A reserved place in the render queue, which is released once dropped
+/// and the renders on it have finished
+pub struct Slot {
+    sender: mpsc::Sender<Task>,
+    permit: Arc<OwnedSemaphorePermit>,
+    timeout: Option<std::time::Duration>,
+}
+
+impl Slot {
+    /// Run a render on one of the render threads, failing once it takes
+    /// longer than the timeout of the pool
+    pub async fn render<T, F>(&self, render: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, Error> + Send + 'static,
+    {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        // NOTE: a render that has timed out can't be stopped, so it keeps its
+        // place until the thread is done with it
+        let permit = self.permit.clone();
+        self.sender
+            .send(Box::new(move || {
+                let result = render();
+                drop(permit);
+                let _ = sender.send(result);
+            }))
+            .map_err(|_| Error::RenderFailed)?;
+
+        let Some(timeout) = self.timeout else {
+            return receiver.await.map_err(|_| Error::RenderFailed)?;
+        };
+        match tokio::time::timeout(timeout, receiver).await {
+            Ok(result) => result.map_err(|_| Error::RenderFailed)?,
+            Err(_) => Err(Error::RenderTimeout(timeout.as_secs())),
+        }
+    }
+}
+
+/// The place of a submission in the render queue
+pub enum Reservation {
+    /// Reserved before an asynchronous submission was accepted
+    Reserved(Slot),
+    /// Reserved once the invoice is about to be rendered
+    Later(RenderPool),
+}
+
+impl Reservation {
+    pub fn slot(self) -> Result<Slot, Error> {
+        match self {
+            Self::Reserved(slot) => Ok(slot),
+            Self::Later(render_pool) => render_pool.reserve(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Queued,
+    /// Reading the attachments and checking the invoice
+    Preparing,
+    Rendering,
+    /// Sending the invoice by email
+    Sending,
+    Storing,
+    Done,
+    Failed,
+}
+
+/// An invoice submitted asynchronously
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+    pub stage: Stage,
+    /// The stored invoice, once done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<Uuid>,
+    /// The generated PDF, once done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pdf: Option<String>,
+    /// Why the submission failed, with the status it would have been responded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
+/// The asynchronous submissions of the last day, kept in memory
+#[derive(Clone, Default)]
+pub struct Jobs {
+    jobs: Arc<Mutex<HashMap<Uuid, Job>>>,
+}
+
+impl Jobs {
+    pub fn create(&self) -> Progress {
+        let now = OffsetDateTime::now_utc();
+        let job = Job {
+            id: Uuid::new_v4(),
+            created_at: now,
+            updated_at: now,
+            stage: Stage::Queued,
+            invoice: None,
+            pdf: None,
+            error: None,
+            status: None,
+        };
+
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, j| {
+            !matches!(j.stage, Stage::Done | Stage::Failed) || j.updated_at + JOB_RETENTION > now
+        });
+        jobs.insert(job.id, job.clone());
+        Progress(Some((self.clone(), job.id)))
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            f(job);
+            job.updated_at = OffsetDateTime::now_utc();
+        }
+    }
+}
+
+/// Reports the stages of a submission to its job, does nothing for
+/// synchronous submissions
+#[derive(Clone, Default)]
+pub struct Progress(Option<(Jobs, Uuid)>);
+
+impl Progress {
+    pub fn id(&self) -> Option<Uuid> {
+        self.0.as_ref().map(|(_, id)| *id)
+    }
+
+    pub fn set(&self, stage: Stage) {
+        if let Some((jobs, id)) = &self.0 {
+            jobs.update(*id, |job| job.stage = stage);
+        }
+    }
+
+    pub fn finish(&self, result: Result<Uuid, Error>) {
+        let Some((jobs, id)) = &self.0 else {
+            return;
+        };
+        jobs.update(*id, |job| match result {
+            Ok(invoice) => {
+                job.stage = Stage::Done;
+                job.invoice = Some(invoice);
+                job.pdf = Some(format!("/jobs/{id}/pdf"));
+            }
+            Err(e) => {
+                error!("Submitting the invoice of job {id} failed: {e}");
+                job.stage = Stage::Failed;
+                job.status = Some(e.status().as_u16());
+                job.error = Some(e.to_string());
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RenderPool
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.render_pool)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Jobs
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.jobs)
+    }
+}
@@ -0,0 +1,225 @@
+use super::MailgunClient;
+use crate::api::invoices::{truncate, try_handle_file, TempFile};
+use crate::audit::{Action, AuditLog, ClientIp};
+use crate::error::Error;
+use crate::storage::{Draft, Storage};
+
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use axum::extract::Multipart;
+use axum::http::StatusCode;
+use axum_typed_multipart::{FieldData, FieldMetadata, TryFromChunks, TypedMultipartError};
+use futures::stream::StreamExt;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::sync::LazyLock;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+static NAME_ADDR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(.*?)\s*<([^>]+)>\s*$").unwrap());
+
+/// Fields of a message forwarded by a mailgun route
+///
+/// See <https://documentation.mailgun.com/docs/mailgun/user-manual/receive-forward-store/#receiving-messages-via-http-through-a-forward-action>
+#[derive(Default)]
+struct InboundMessage {
+    sender: String,
+    from: String,
+    subject: String,
+    body: String,
+    timestamp: String,
+    token: String,
+    signature: String,
+    attachments: Vec<FieldData<TempFile>>,
+    /// Filenames of the attachments that were too large
+    too_large: Vec<String>,
+}
+
+impl InboundMessage {
+    /// Read the message, verifying its signature before any attachment is
+    /// written to disk
+    ///
+    /// Mailgun sends the timestamp, token and signature before the attachments,
+    /// so an unsigned request is rejected at its first attachment.
+    async fn from_multipart(
+        client: &MailgunClient,
+        mut multipart: Multipart,
+    ) -> Result<Self, Error> {
+        let mut message = Self::default();
+        let mut body_plain = String::new();
+        let mut verified = false;
+
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().unwrap_or_default().to_string();
+            if name.starts_with("attachment-") {
+                if !verified {
+                    message.verify(client)?;
+                    verified = true;
+                }
+                let metadata = FieldMetadata::from(&field);
+                let chunks = field.map(|chunk| chunk.map_err(TypedMultipartError::from));
+                match TempFile::try_from_chunks(chunks, metadata.clone()).await {
+                    Ok(contents) => message.attachments.push(FieldData { metadata, contents }),
+                    Err(TypedMultipartError::FieldTooLarge { .. }) => message
+                        .too_large
+                        .push(metadata.file_name.unwrap_or_default()),
+                    Err(e) => return Err(e.into()),
+                }
+                continue;
+            }
+
+            let value = field.text().await?;
+            match name.as_str() {
+                "sender" => message.sender = value,
+                "from" => message.from = value,
+                "subject" => message.subject = value,
+                "stripped-text" => message.body = value,
+                "body-plain" => body_plain = value,
+                "timestamp" => message.timestamp = value,
+                "token" => message.token = value,
+                "signature" => message.signature = value,
+                _ => {}
+            }
+        }
+
+        if !verified {
+            message.verify(client)?;
+        }
+        if message.body.trim().is_empty() {
+            message.body = body_plain;
+        }
+
+        Ok(message)
+    }
+
+    fn verify(&self, client: &MailgunClient) -> Result<(), Error> {
+        client.verify_signature(&self.timestamp, &self.token, &self.signature)
+    }
+
+    /// The name and the address of the sender
+    fn sender(&self) -> (String, String) {
+        match NAME_ADDR.captures(&self.from) {
+            Some(captures) => (
+                captures[1].trim_matches('"').to_string(),
+                captures[2].to_string(),
+            ),
+            None => (String::new(), self.sender.clone()),
+        }
+    }
+}
+
+/// Create a draft invoice from an email forwarded by a mailgun route
+///
+/// The sender gets a reply with a link for completing the invoice.
+pub async fn receive(
+    client: MailgunClient,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    multipart: Multipart,
+) -> Result<StatusCode, Error> {
+    let mut message = InboundMessage::from_multipart(&client, multipart).await?;
+
+    let (recipient_name, recipient_email) = message.sender();
+
+    let mut attachments = Vec::new();
+    let mut rejected = std::mem::take(&mut message.too_large);
+    let mut total = 0;
+    for field in std::mem::take(&mut message.attachments) {
+        let filename = field.metadata.file_name.clone().unwrap_or_default();
+        match try_handle_file(field, None).await {
+            Ok(attachment) if total + attachment.size > crate::CONFIG.max_attachments_size => {
+                rejected.push(filename);
+            }
+            Ok(attachment) => {
+                total += attachment.size;
+                attachments.push(attachment);
+            }
+            Err(e) => {
+                debug!("Rejected inbound attachment {filename:?}: {e}");
+                rejected.push(filename);
+            }
+        }
+    }
+
+    let token = {
+        let mut token = [0; 32];
+        OsRng.fill_bytes(&mut token);
+        hex::encode(token)
+    };
+    let draft = Draft {
+        id: Uuid::new_v4(),
+        created_at: OffsetDateTime::now_utc(),
+        recipient_name: truncate(recipient_name, 128),
+        recipient_email: truncate(recipient_email, 128),
+        subject: truncate(message.subject, 128),
+        description: truncate(message.body.trim().to_string(), 4096),
+        attachments: attachments.iter().map(|a| a.filename.clone()).collect(),
+        token_hash: Some(hex::encode(Sha256::digest(&token))),
+    };
+    storage.insert_draft(&draft, &attachments).await?;
+    audit
+        .record(
+            Action::DraftCreated,
+            Some(draft.id),
+            &crate::audit::subject(&draft.recipient_email),
+            ip,
+            None,
+        )
+        .await?;
+
+    info!(
+        "Created draft {} from an email by {}",
+        draft.id, draft.recipient_email
+    );
+    client.send_draft_link(&draft, &token, &rejected).await?;
+
+    Ok(StatusCode::OK)
+}
+
+impl MailgunClient {
+    /// Reply to the sender of an inbound email with a link for completing the draft
+    pub async fn send_draft_link(
+        self,
+        draft: &Draft,
+        token: &str,
+        rejected: &[String],
+    ) -> Result<(), Error> {
+        let link = format!(
+            "{}/?draft={}&token={token}",
+            crate::CONFIG.frontend_url.trim_end_matches('/'),
+            draft.id
+        );
+
+        let mut html = format!(
+            "Laskuluonnos luotu viestistäsi. Täydennä ja lähetä lasku osoitteessa <a href=\"{link}\">{link}</a>"
+        );
+        if !rejected.is_empty() {
+            html += "<br><br>Seuraavia liitteitä ei voitu lisätä, koska niiden tiedostomuotoa ei tueta tai ne ovat liian suuria: ";
+            html += &rejected
+                .iter()
+                .map(|f| f.replace('&', "&amp;").replace('<', "&lt;"))
+                .collect::<Vec<_>>()
+                .join(", ");
+        }
+
+        let form = reqwest::multipart::Form::new()
+            .text("from", self.from)
+            .text("to", draft.recipient_email.clone())
+            .text("subject", format!("Re: {}", draft.subject))
+            .text("html", html);
+
+        let response = self
+            .client
+            .post(self.url)
+            .basic_auth(self.api_user, Some(self.api_key))
+            .multipart(form)
+            .send()
+            .await?;
+
+        match response.error_for_status() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::ReqwestError(e)),
+        }
+    }
+}
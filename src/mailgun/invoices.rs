@@ -1,22 +1,44 @@
 use super::MailgunClient;
-use crate::api::invoices::Invoice;
 use crate::error::Error;
+use crate::receipt::Warning;
+use crate::storage::StoredInvoice;
+use serde_derive::Deserialize;
+
+#[derive(Deserialize)]
+struct SendResponse {
+    id: String,
+}
 
 impl MailgunClient {
-    pub async fn send_mail(self, invoice: &Invoice, pdf: Vec<u8>) -> Result<(), Error> {
+    /// Send the invoice to the treasurer, returns the message id assigned by mailgun
+    pub async fn send_mail(self, stored: &StoredInvoice, pdf: Vec<u8>) -> Result<String, Error> {
+        let invoice = &stored.invoice;
         let invoice_recipient = format!("{} <{}>", invoice.recipient_name, invoice.recipient_email);
+
+        let mut subject = format!("Uusi lasku, lähettäjä {}", invoice.recipient_name);
+        if stored
+            .warnings
+            .iter()
+            .any(|w| matches!(w, Warning::PossibleDuplicate { .. }))
+        {
+            subject = format!("Mahdollinen kaksoiskappale: {subject}");
+        }
+
+        let mut html = format!("Uusi lasku, lähettäjä {}", invoice.recipient_name);
+        for warning in &stored.warnings {
+            html += "<br><br>Huomio: ";
+            html += &warning
+                .to_string()
+                .replace('&', "&amp;")
+                .replace('<', "&lt;");
+        }
+
         let form = reqwest::multipart::Form::new()
             .text("from", self.from)
             .text("to", self.default_to)
             .text("cc", invoice_recipient)
-            .text(
-                "subject",
-                format!("Uusi lasku, lähettäjä {}", invoice.recipient_name),
-            )
-            .text(
-                "html",
-                format!("Uusi lasku, lähettäjä {}", invoice.recipient_name),
-            )
+            .text("subject", subject)
+            .text("html", html)
             .part(
                 "attachment",
                 reqwest::multipart::Part::bytes(pdf).file_name("invoice.pdf"),
@@ -31,7 +53,10 @@ impl MailgunClient {
             .await?;
 
         match response.error_for_status() {
-            Ok(_) => Ok(()),
+            Ok(response) => {
+                let response: SendResponse = response.json().await?;
+                Ok(response.id.trim_matches(['<', '>']).to_string())
+            }
             Err(e) => Err(Error::ReqwestError(e)),
         }
     }
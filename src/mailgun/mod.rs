@@ -1,11 +1,22 @@
+use crate::error::Error;
 use crate::state::State;
 use axum::async_trait;
 use axum::{
     extract::{FromRef, FromRequestParts},
     http::request::Parts,
 };
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
 
+pub mod inbound;
 mod invoices;
+pub mod webhooks;
+
+/// How far the timestamp of a signed request may be from the current time in seconds
+const SIGNATURE_MAX_AGE: i64 = 15 * 60;
 
 #[derive(Clone, Debug)]
 pub struct MailgunClient {
@@ -15,6 +26,10 @@ pub struct MailgunClient {
     api_key: String,
     default_to: String,
     from: String,
+    webhook_signing_key: Option<String>,
+    /// The tokens of the accepted requests with their timestamps, so that a
+    /// captured request can't be replayed within the window
+    seen_tokens: Arc<Mutex<HashMap<String, i64>>>,
 }
 
 impl From<crate::MailgunConfig> for MailgunClient {
@@ -26,6 +41,52 @@ impl From<crate::MailgunConfig> for MailgunClient {
             api_key: config.password,
             default_to: config.to,
             from: config.from,
+            webhook_signing_key: config.webhook_signing_key,
+            seen_tokens: Arc::default(),
+        }
+    }
+}
+
+impl MailgunClient {
+    /// Verify that a webhook was sent by mailgun
+    ///
+    /// The timestamp has to be within [`SIGNATURE_MAX_AGE`] of the current time
+    /// and each token is only accepted once.
+    ///
+    /// See <https://documentation.mailgun.com/docs/mailgun/user-manual/tracking-messages/#securing-webhooks>
+    pub fn verify_signature(
+        &self,
+        timestamp: &str,
+        token: &str,
+        signature: &str,
+    ) -> Result<(), Error> {
+        let key = self
+            .webhook_signing_key
+            .as_deref()
+            .filter(|key| !key.is_empty())
+            .ok_or(Error::InvalidSignature)?;
+        let signature = hex::decode(signature).map_err(|_| Error::InvalidSignature)?;
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+            .expect("bug: HMAC accepts keys of any length");
+        mac.update(timestamp.as_bytes());
+        mac.update(token.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| Error::InvalidSignature)?;
+
+        let timestamp = timestamp
+            .parse::<i64>()
+            .map_err(|_| Error::InvalidSignature)?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if (now - timestamp).abs() > SIGNATURE_MAX_AGE {
+            return Err(Error::InvalidSignature);
+        }
+
+        let mut seen = self.seen_tokens.lock().unwrap();
+        seen.retain(|_, t| (now - *t).abs() <= SIGNATURE_MAX_AGE);
+        match seen.insert(token.to_string(), timestamp) {
+            Some(_) => Err(Error::InvalidSignature),
+            None => Ok(()),
         }
     }
 }
@@ -36,7 +97,7 @@ where
     S: Send + Sync,
     State: FromRef<S>,
 {
-    type Rejection = crate::error::Error;
+    type Rejection = Error;
 
     async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let state = State::from_ref(state);
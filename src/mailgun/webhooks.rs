@@ -0,0 +1,134 @@
+use super::MailgunClient;
+use crate::audit::{Action, AuditLog, ClientIp, MAILGUN};
+use crate::error::Error;
+use crate::storage::{DeliveryEvent, DeliveryStatus, Storage};
+
+use axum::http::StatusCode;
+use serde_derive::Deserialize;
+use time::OffsetDateTime;
+
+/// Body of a webhook request sent by mailgun
+///
+/// See <https://documentation.mailgun.com/docs/mailgun/user-manual/tracking-messages/#webhooks>
+#[derive(Debug, Deserialize)]
+pub struct WebhookPayload {
+    signature: Signature,
+    #[serde(rename = "event-data")]
+    event_data: EventData,
+}
+
+#[derive(Debug, Deserialize)]
+struct Signature {
+    timestamp: String,
+    token: String,
+    signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EventData {
+    event: String,
+    timestamp: f64,
+    recipient: String,
+    severity: Option<String>,
+    reason: Option<String>,
+    #[serde(rename = "delivery-status")]
+    delivery_status: Option<DeliveryStatusData>,
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeliveryStatusData {
+    description: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    headers: Headers,
+}
+
+#[derive(Debug, Deserialize)]
+struct Headers {
+    #[serde(rename = "message-id")]
+    message_id: String,
+}
+
+impl EventData {
+    fn status(&self) -> Option<DeliveryStatus> {
+        match (self.event.as_str(), self.severity.as_deref()) {
+            ("delivered", _) => Some(DeliveryStatus::Delivered),
+            ("failed", Some("permanent")) | ("bounced", _) => Some(DeliveryStatus::Bounced),
+            ("failed", _) => Some(DeliveryStatus::Failed),
+            _ => None,
+        }
+    }
+
+    fn reason(&self) -> Option<String> {
+        self.delivery_status
+            .as_ref()
+            .and_then(|d| {
+                d.description
+                    .clone()
+                    .filter(|d| !d.is_empty())
+                    .or(d.message.clone())
+            })
+            .filter(|r| !r.is_empty())
+            .or(self.reason.clone())
+    }
+}
+
+/// Receive delivery and bounce events from mailgun
+pub async fn receive(
+    client: MailgunClient,
+    storage: Storage,
+    audit: AuditLog,
+    ClientIp(ip): ClientIp,
+    axum::Json(payload): axum::Json<WebhookPayload>,
+) -> Result<StatusCode, Error> {
+    let signature = &payload.signature;
+    client.verify_signature(&signature.timestamp, &signature.token, &signature.signature)?;
+
+    let event = payload.event_data;
+    let Some(status) = event.status() else {
+        debug!("Ignoring mailgun event {}", event.event);
+        return Ok(StatusCode::OK);
+    };
+
+    let message_id = event.message.headers.message_id.trim_matches(['<', '>']);
+    let Some(id) = storage.find_by_message_id(message_id).await? else {
+        warn!("Received mailgun event for unknown message {message_id}");
+        return Ok(StatusCode::OK);
+    };
+
+    let event = DeliveryEvent {
+        status,
+        reason: event.reason(),
+        recipient: event.recipient,
+        timestamp: OffsetDateTime::from_unix_timestamp_nanos((event.timestamp * 1e9) as i128)
+            .unwrap_or_else(|_| OffsetDateTime::now_utc()),
+    };
+
+    info!(
+        "Invoice {id} delivery to {} {:?}",
+        event.recipient, event.status
+    );
+    let details = format!(
+        "{} {:?}",
+        crate::audit::subject(&event.recipient),
+        event.status
+    );
+    storage
+        .update(id, |invoice| invoice.delivery.record(event))
+        .await?;
+    audit
+        .record(
+            Action::DeliveryStatusChanged,
+            Some(id),
+            MAILGUN,
+            ip,
+            Some(details),
+        )
+        .await?;
+
+    Ok(StatusCode::OK)
+}
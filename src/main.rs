@@ -1,17 +1,28 @@
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::net::SocketAddr;
 use std::sync::LazyLock;
 
 mod api;
+mod attachments;
+mod audit;
+mod auth;
+mod duplicates;
 mod error;
+mod gdpr;
+mod idempotency;
+mod jobs;
 #[cfg(feature = "email")]
 mod mailgun;
 mod merge;
+mod pdfa;
+mod signing;
 mod state;
+mod storage;
 
 mod pdfgen;
+mod receipt;
 
 #[cfg(test)]
 mod tests;
@@ -37,11 +48,47 @@ struct MailgunConfig {
     /// From-value used by mailgun
     #[clap(long = "mailgun-from", env = "MAILGUN_FROM")]
     from: String,
+    /// HTTP webhook signing key used to verify webhooks sent by mailgun
+    #[clap(
+        long = "mailgun-webhook-signing-key",
+        env = "MAILGUN_WEBHOOK_SIGNING_KEY"
+    )]
+    webhook_signing_key: Option<String>,
+}
+
+#[cfg(feature = "s3")]
+#[derive(Parser, Clone, Debug)]
+struct S3Config {
+    /// The url of the S3-compatible service, e.g. http://localhost:9000 for MinIO
+    #[clap(long = "s3-endpoint", env = "S3_ENDPOINT")]
+    endpoint: Option<String>,
+    /// The bucket the attachments and PDFs are stored in
+    #[clap(long = "s3-bucket", env = "S3_BUCKET")]
+    bucket: Option<String>,
+    #[clap(
+        long = "s3-region",
+        env = "S3_REGION",
+        required = false,
+        default_value = "us-east-1"
+    )]
+    region: String,
+    #[clap(long = "s3-access-key-id", env = "S3_ACCESS_KEY_ID")]
+    access_key_id: Option<String>,
+    #[clap(long = "s3-secret-access-key", env = "S3_SECRET_ACCESS_KEY")]
+    secret_access_key: Option<String>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Encrypt the stored data with the first encryption key, e.g. after rotating the keys
+    Reencrypt,
 }
 
 #[derive(Parser, Clone, Debug)]
 #[command(version, about, long_about = None)]
 struct LaskugenConfig {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[cfg(feature = "email")]
     #[clap(flatten)]
     mailgun: MailgunConfig,
@@ -54,6 +101,97 @@ struct LaskugenConfig {
     /// A comma-separated list of allowed origins
     #[clap(long, env, required = false, value_delimiter = ',')]
     allowed_origins: Vec<String>,
+    /// The directory where submitted invoices are stored
+    #[clap(long, env, required = false, default_value = "data")]
+    data_dir: std::path::PathBuf,
+    /// The maximum size of a single attachment in bytes
+    #[clap(long, env, required = false, default_value = "16777216")]
+    max_attachment_size: u64,
+    /// The maximum total size of the attachments of an invoice in bytes
+    #[clap(long, env, required = false, default_value = "23068672")]
+    max_attachments_size: u64,
+    /// The url of the frontend, used in links sent by email
+    #[clap(long, env, required = false, default_value = "http://localhost:5173")]
+    frontend_url: String,
+    /// The resolution in DPI that attachment images are downscaled to for an A4 page
+    #[clap(long, env, required = false, default_value = "200")]
+    image_dpi: u32,
+    /// The quality of the JPEGs that attachment images are re-encoded as (1-100)
+    #[clap(long, env, required = false, default_value = "85", value_parser = clap::value_parser!(u8).range(1..=100))]
+    jpeg_quality: u8,
+    /// Crop photos of receipts to the receipt and correct their perspective
+    #[clap(long, env)]
+    auto_crop: bool,
+    /// The tesseract executable used for reading receipt images
+    #[clap(long, env)]
+    tesseract: Option<std::path::PathBuf>,
+    /// The languages tesseract reads receipts in
+    #[clap(long, env, required = false, default_value = "fin+eng")]
+    ocr_languages: String,
+    /// How long reading a receipt image may take in seconds
+    #[clap(long, env, required = false, default_value = "30")]
+    ocr_timeout_secs: u64,
+    /// Generate PDF/A-3b documents for archiving, with the invoice data embedded
+    #[clap(long, env)]
+    pdf_a: bool,
+    /// A PKCS#12 file, or a PEM file with the certificate chain, used to sign the generated PDFs
+    #[clap(long, env)]
+    signing_certificate: Option<std::path::PathBuf>,
+    /// The PEM private key of the signing certificate, if it isn't in a PKCS#12 file
+    #[clap(long, env)]
+    signing_key: Option<std::path::PathBuf>,
+    /// The password of the PKCS#12 file or the encrypted private key
+    #[clap(long, env)]
+    signing_password: Option<String>,
+    /// The bearer token required for approving invoices and the other administrative endpoints
+    #[clap(long, env)]
+    admin_token: Option<String>,
+    /// The number of years invoices are kept after the end of the year they were submitted in
+    #[clap(long, env, required = false, default_value = "6")]
+    retention_years: u16,
+    /// What is done to invoices after the retention period
+    #[clap(
+        long,
+        env,
+        required = false,
+        default_value = "pseudonymize",
+        value_enum
+    )]
+    retention_action: gdpr::RetentionAction,
+    /// Comma-separated hex encoded 256-bit keys for encrypting the stored data,
+    /// the first one is used for encrypting
+    #[clap(long, env)]
+    encryption_keys: Option<String>,
+    /// A file with the encryption keys, one per line
+    #[clap(long, env)]
+    encryption_key_file: Option<std::path::PathBuf>,
+    /// Where the attachments and generated PDFs are stored
+    #[clap(long, env, required = false, default_value = "fs", value_enum)]
+    blob_store: storage::BlobBackend,
+    #[cfg(feature = "s3")]
+    #[clap(flatten)]
+    s3: S3Config,
+    /// How long the responses to requests with an Idempotency-Key are replayed to retries
+    #[clap(long, env, required = false, default_value = "24")]
+    idempotency_window_hours: u32,
+    /// Where the responses to requests with an Idempotency-Key are kept
+    #[clap(long, env, required = false, default_value = "persistent", value_enum)]
+    idempotency_store: idempotency::IdempotencyBackend,
+    /// The number of threads rendering PDFs, by default the number of CPUs
+    #[clap(long, env)]
+    render_workers: Option<usize>,
+    /// How many invoices can wait for a render thread before new submissions are refused
+    #[clap(long, env, required = false, default_value = "8")]
+    render_queue: usize,
+    /// How long rendering an invoice may take in seconds
+    #[clap(long, env, required = false, default_value = "60")]
+    render_timeout_secs: u64,
+    /// The maximum number of pages in an invoice, including the PDF attachments
+    #[clap(long, env, required = false, default_value = "200")]
+    max_pages: usize,
+    /// The maximum number of pixels in an attachment image
+    #[clap(long, env, required = false, default_value = "100000000")]
+    max_image_pixels: u64,
 }
 
 static CONFIG: LazyLock<LaskugenConfig> = LazyLock::new(LaskugenConfig::parse);
@@ -69,6 +207,18 @@ async fn main() {
         .init();
 
     let state = state::new().await;
+    if let Some(Command::Reencrypt) = CONFIG.command {
+        let count = state
+            .storage
+            .reencrypt()
+            .await
+            .expect("Failed to re-encrypt the stored data");
+        info!("Re-encrypted {count} files");
+        return;
+    }
+
+    gdpr::schedule(state.storage.clone(), state.audit.clone());
+    state.idempotency.schedule();
     let addr = SocketAddr::from((CONFIG.bind_addr, CONFIG.port));
     debug!("Listening on {addr}");
     let listener = tokio::net::TcpListener::bind(addr)
@@ -1,146 +1,516 @@
 use crate::error::Error;
-use std::collections::BTreeMap;
 
-use lopdf::{Document, Object, ObjectId};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use time::{OffsetDateTime, UtcOffset};
 
-// Mostly copied from https://github.com/J-F-Liu/lopdf/blob/master/README.md merge example
-pub fn merge_pdf(documents: Vec<Vec<u8>>) -> Result<Vec<u8>, Error> {
-    let documents = documents
-        .iter()
-        .flat_map(|f| Document::load_mem(f))
-        .collect::<Vec<Document>>();
-    let mut max_id = 1;
-    // Collect all Documents Objects grouped by a map
-    let mut documents_pages = BTreeMap::new();
-    let mut documents_objects = BTreeMap::new();
-    let mut document = Document::with_version("1.5");
-
-    for mut doc in documents {
-        doc.renumber_objects_with(max_id);
-
-        max_id = doc.max_id + 1;
-
-        documents_pages.extend(
-            doc.get_pages()
-                .into_values()
-                .map(|object_id| (object_id, doc.get_object(object_id).unwrap().to_owned())),
-        );
-        documents_objects.extend(doc.objects);
-    }
+/// A document to merge
+pub struct MergeInput {
+    /// Name of the document in errors
+    pub name: String,
+    pub document: Document,
+    /// Bookmarks to the pages of the document, the outline of the
+    /// document itself is placed under the first one
+    pub bookmarks: Vec<Bookmark>,
+}
 
-    // "Catalog" and "Pages" are mandatory.
-    let mut catalog_object: Option<(ObjectId, Object)> = None;
-    let mut pages_object: Option<(ObjectId, Object)> = None;
-
-    // Process all objects except "Page" type
-    for (object_id, object) in documents_objects.iter() {
-        // We have to ignore "Page" (as are processed later), "Outlines" and "Outline" objects.
-        // All other objects should be collected and inserted into the main Document.
-        match object.type_name().unwrap_or("") {
-            "Catalog" => {
-                // Collect a first "Catalog" object and use it for the future "Pages".
-                catalog_object = Some((
-                    if let Some((id, _)) = catalog_object {
-                        id
-                    } else {
-                        *object_id
-                    },
-                    object.clone(),
-                ));
-            }
-            "Pages" => {
-                // Collect and update a first "Pages" object and use it for the future "Catalog"
-                // We have also to merge all dictionaries of the old and the new "Pages" object
-                if let Ok(dictionary) = object.as_dict() {
-                    let mut dictionary = dictionary.clone();
-                    if let Some((_, ref object)) = pages_object {
-                        if let Ok(old_dictionary) = object.as_dict() {
-                            dictionary.extend(old_dictionary);
-                        }
+pub struct Bookmark {
+    pub title: String,
+    /// Index of the page in the document
+    pub page: usize,
+}
+
+/// An item of the merged outline
+struct OutlineItem {
+    title: String,
+    page: ObjectId,
+    /// The first and last items of the outline of the document
+    children: Option<(ObjectId, ObjectId)>,
+}
+
+/// Page attributes that may be inherited from the page tree
+const INHERITED: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+/// Merge the documents into one
+///
+/// Annotations, outlines and named destinations are kept, and form fields
+/// are merged by placing the fields of each document under a field of their own.
+/// The named destinations of each document are prefixed in the same way.
+pub fn merge_pdf(inputs: Vec<MergeInput>) -> Result<Document, Error> {
+    let mut document = Document::with_version("1.7");
+    let pages_id = document.new_object_id();
+    let mut max_id = pages_id.0 + 1;
+
+    let mut kids = Vec::new();
+    let mut outline = Vec::new();
+    let mut dests = Dictionary::new();
+    let mut fields = Vec::new();
+    let mut form_resources = Dictionary::new();
+    let mut need_appearances = false;
+
+    for (index, mut input) in inputs.into_iter().enumerate() {
+        input.document.renumber_objects_with(max_id);
+        max_id = input.document.max_id + 1;
+        // NOTE: names have to be unique, e.g. typst names its destinations after labels
+        let prefix = format!("liite{index}.");
+        for object in input.document.objects.values_mut() {
+            prefix_dests(object, prefix.as_bytes());
+        }
+
+        let doc = &input.document;
+        let invalid = |reason: &str| Error::InvalidAttachment(input.name.clone(), reason.into());
+
+        let catalog = doc
+            .catalog()
+            .map_err(|_| invalid("the PDF has no catalog"))?
+            .clone();
+        let page_ids = doc.get_pages().into_values().collect::<Vec<_>>();
+        if page_ids.is_empty() {
+            return Err(invalid("the PDF has no pages"));
+        }
+
+        // NOTE: the page tree is flattened, so inherited attributes are set on the pages
+        let mut pages = Vec::with_capacity(page_ids.len());
+        for &id in &page_ids {
+            let mut page = doc.get_dictionary(id)?.clone();
+            for key in INHERITED {
+                if !page.has(key) {
+                    if let Some(value) = inherited(doc, &page, key) {
+                        page.set(key, value);
                     }
+                }
+            }
+            page.set("Parent", pages_id);
+            pages.push((id, page));
+        }
+
+        let outlines = catalog.get(b"Outlines").and_then(Object::as_reference).ok();
+        let children = outlines.and_then(|id| {
+            let outlines = doc.get_dictionary(id).ok()?;
+            Some((
+                outlines.get(b"First").ok()?.as_reference().ok()?,
+                outlines.get(b"Last").ok()?.as_reference().ok()?,
+            ))
+        });
+        for (i, bookmark) in input.bookmarks.into_iter().enumerate() {
+            outline.push(OutlineItem {
+                title: bookmark.title,
+                page: page_ids[bookmark.page.min(page_ids.len() - 1)],
+                children: if i == 0 { children } else { None },
+            });
+        }
+
+        collect_dests(doc, &catalog, prefix.as_bytes(), &mut dests);
+
+        let mut form_fields = Vec::new();
+        if let Some(form) = catalog
+            .get(b"AcroForm")
+            .ok()
+            .and_then(|f| doc.dereference(f).ok())
+            .and_then(|(_, f)| f.as_dict().ok())
+        {
+            if let Ok(fields) = form.get(b"Fields").and_then(Object::as_array) {
+                form_fields.extend(fields.iter().filter_map(|f| f.as_reference().ok()));
+            }
+            if let Ok((_, resources)) = form.get(b"DR").and_then(|r| doc.dereference(r)) {
+                merge_resources(doc, &mut form_resources, resources);
+            }
+            need_appearances |= matches!(form.get(b"NeedAppearances"), Ok(Object::Boolean(true)));
+        }
 
-                    pages_object = Some((
-                        if let Some((id, _)) = pages_object {
-                            id
-                        } else {
-                            *object_id
-                        },
-                        Object::Dictionary(dictionary),
-                    ));
+        if !form_fields.is_empty() {
+            // NOTE: field names have to be unique, so the fields of each
+            // document are placed under a parent field
+            let parent_id = (max_id, 0);
+            max_id += 1;
+            for &id in &form_fields {
+                if let Some(Object::Dictionary(field)) = input.document.objects.get_mut(&id) {
+                    field.set("Parent", parent_id);
                 }
             }
-            "Page" => {}     // Ignored, processed later and separately
-            "Outlines" => {} // Ignored, not supported yet
-            "Outline" => {}  // Ignored, not supported yet
-            _ => {
-                document.objects.insert(*object_id, object.clone());
+            input.document.objects.insert(
+                parent_id,
+                Object::Dictionary(dictionary! {
+                    "T" => Object::string_literal(format!("liite{index}")),
+                    "Kids" => form_fields.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+                }),
+            );
+            fields.push(Object::Reference(parent_id));
+        }
+
+        for (id, object) in input.document.objects {
+            let skip =
+                matches!(object.type_name(), Ok(b"Catalog" | b"Pages")) || Some(id) == outlines;
+            if !skip {
+                document.objects.insert(id, object);
             }
         }
+        for (id, page) in pages {
+            document.objects.insert(id, Object::Dictionary(page));
+        }
+        kids.extend(page_ids);
     }
 
-    let catalog_object = catalog_object.expect("Catalog not found when merging PDF");
-    let pages_object = pages_object.expect("Pages not found when merging PDF");
+    document.max_id = max_id - 1;
 
-    // Iterate over all "Page" objects and collect into the parent "Pages" created before
-    for (object_id, object) in documents_pages.iter() {
-        if let Ok(dictionary) = object.as_dict() {
-            let mut dictionary = dictionary.clone();
-            dictionary.set("Parent", pages_object.0);
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Count" => kids.len() as i64,
+            "Kids" => kids.into_iter().map(Object::Reference).collect::<Vec<_>>(),
+        }),
+    );
 
-            document
-                .objects
-                .insert(*object_id, Object::Dictionary(dictionary));
+    let mut catalog = dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    };
+    if !outline.is_empty() {
+        catalog.set("Outlines", build_outline(&mut document, outline)?);
+        catalog.set("PageMode", "UseOutlines");
+    }
+    if !dests.is_empty() {
+        catalog.set("Dests", dests);
+    }
+    if !fields.is_empty() {
+        let mut form = dictionary! { "Fields" => fields };
+        if !form_resources.is_empty() {
+            form.set("DR", form_resources);
+        }
+        if need_appearances {
+            form.set("NeedAppearances", true);
         }
+        catalog.set("AcroForm", form);
     }
+    let catalog_id = document.add_object(catalog);
+    document.trailer.set("Root", catalog_id);
 
-    // Build a new "Pages" with updated fields
-    if let Ok(dictionary) = pages_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
+    Ok(document)
+}
 
-        // Set new pages count
-        dictionary.set("Count", documents_pages.len() as u32);
+/// Find an attribute of a page from the page or its ancestors
+fn inherited(doc: &Document, page: &Dictionary, key: &[u8]) -> Option<Object> {
+    let mut node = page;
+    // NOTE: limit the depth in case the page tree has a cycle
+    for _ in 0..32 {
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        node = doc
+            .get_dictionary(node.get(b"Parent").ok()?.as_reference().ok()?)
+            .ok()?;
+    }
+    None
+}
 
-        // Set new "Kids" list (collected from documents pages) for "Pages"
-        dictionary.set(
-            "Kids",
-            documents_pages
-                .into_keys()
-                .map(Object::Reference)
-                .collect::<Vec<_>>(),
-        );
+/// Prefix the named destinations that links, actions and outline items go to
+///
+/// The destinations are merged into the old style dictionary, so the
+/// strings of the name tree become names.
+fn prefix_dests(object: &mut Object, prefix: &[u8]) {
+    match object {
+        Object::Array(items) => {
+            for item in items {
+                prefix_dests(item, prefix);
+            }
+        }
+        Object::Dictionary(dictionary) => prefix_dictionary(dictionary, prefix),
+        Object::Stream(stream) => prefix_dictionary(&mut stream.dict, prefix),
+        _ => {}
+    }
+}
 
-        document
-            .objects
-            .insert(pages_object.0, Object::Dictionary(dictionary));
+fn prefix_dictionary(dictionary: &mut Dictionary, prefix: &[u8]) {
+    let go_to = matches!(dictionary.get(b"S").and_then(Object::as_name), Ok(b"GoTo"));
+    for (key, value) in dictionary.iter_mut() {
+        let named = match value {
+            Object::Name(name) | Object::String(name, _)
+                if key == b"Dest" || (go_to && key == b"D") =>
+            {
+                Some([prefix, name.as_slice()].concat())
+            }
+            _ => None,
+        };
+        match named {
+            Some(name) => *value = Object::Name(name),
+            None => prefix_dests(value, prefix),
+        }
+    }
+}
+
+/// Collect the named destinations of a document with the prefix, as the
+/// old style dictionary and from the name tree
+fn collect_dests(doc: &Document, catalog: &Dictionary, prefix: &[u8], dests: &mut Dictionary) {
+    if let Ok((_, Object::Dictionary(old))) = catalog.get(b"Dests").and_then(|d| doc.dereference(d))
+    {
+        for (name, dest) in old.iter() {
+            dests.set([prefix, name].concat(), dest.clone());
+        }
     }
 
-    // Build a new "Catalog" with updated fields
-    if let Ok(dictionary) = catalog_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
-        dictionary.set("Pages", pages_object.0);
-        dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
+    let Some(tree) = catalog
+        .get(b"Names")
+        .and_then(|n| doc.dereference(n))
+        .and_then(|(_, n)| n.as_dict())
+        .and_then(|n| n.get(b"Dests"))
+        .ok()
+    else {
+        return;
+    };
 
-        document
-            .objects
-            .insert(catalog_object.0, Object::Dictionary(dictionary));
+    let mut nodes = vec![(tree, 0)];
+    while let Some((node, depth)) = nodes.pop() {
+        let Ok((_, Object::Dictionary(node))) = doc.dereference(node) else {
+            continue;
+        };
+        if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+            for pair in names.chunks_exact(2) {
+                if let Ok(name) = pair[0].as_str() {
+                    let name = [prefix, name].concat();
+                    if !dests.has(&name) {
+                        dests.set(name, pair[1].clone());
+                    }
+                }
+            }
+        }
+        // NOTE: limit the depth in case the name tree has a cycle
+        if depth < 32 {
+            if let Ok(kids) = node.get(b"Kids").and_then(Object::as_array) {
+                nodes.extend(kids.iter().map(|k| (k, depth + 1)));
+            }
+        }
+    }
+}
+
+/// Merge the default resources of a form into the merged ones,
+/// keeping the first resource with each name
+fn merge_resources(doc: &Document, merged: &mut Dictionary, resources: &Object) {
+    let Ok(resources) = resources.as_dict() else {
+        return;
+    };
+    for (category, entries) in resources.iter() {
+        let Ok((_, Object::Dictionary(entries))) = doc.dereference(entries) else {
+            continue;
+        };
+        if !merged.has(category) {
+            merged.set(category.clone(), Dictionary::new());
+        }
+        if let Ok(Object::Dictionary(target)) = merged.get_mut(category) {
+            for (name, resource) in entries.iter() {
+                if !target.has(name) {
+                    target.set(name.clone(), resource.clone());
+                }
+            }
+        }
     }
+}
 
-    document.trailer.set("Root", catalog_object.0);
+/// Add the outline items to the document, returns the outline dictionary
+fn build_outline(document: &mut Document, items: Vec<OutlineItem>) -> Result<ObjectId, Error> {
+    let outlines_id = document.new_object_id();
+    let ids = items
+        .iter()
+        .map(|_| document.new_object_id())
+        .collect::<Vec<_>>();
 
-    // Update the max internal ID as wasn't updated before due to direct objects insertion
-    document.max_id = document.objects.len() as u32;
+    for (i, item) in items.into_iter().enumerate() {
+        let mut dictionary = dictionary! {
+            "Title" => text_string(&item.title),
+            "Parent" => outlines_id,
+            "Dest" => vec![item.page.into(), "Fit".into()],
+        };
+        if i > 0 {
+            dictionary.set("Prev", ids[i - 1]);
+        }
+        if let Some(&next) = ids.get(i + 1) {
+            dictionary.set("Next", next);
+        }
 
-    // Reorder all new Document objects
-    document.renumber_objects();
+        if let Some((first, last)) = item.children {
+            let mut count = 0;
+            let mut child = Some(first);
+            while let Some(id) = child {
+                let Ok(Object::Dictionary(c)) = document.get_object_mut(id) else {
+                    break;
+                };
+                c.set("Parent", ids[i]);
+                child = c.get(b"Next").and_then(Object::as_reference).ok();
+                count += 1;
+                // NOTE: guard against cycles in the outline
+                if id == last || count > 10_000 {
+                    break;
+                }
+            }
+            dictionary.set("First", first);
+            dictionary.set("Last", last);
+            // NOTE: a negative count means that the item is closed
+            dictionary.set("Count", -count);
+        }
+
+        document
+            .objects
+            .insert(ids[i], Object::Dictionary(dictionary));
+    }
+
+    document.objects.insert(
+        outlines_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Outlines",
+            "First" => ids[0],
+            "Last" => *ids.last().unwrap(),
+            "Count" => ids.len() as i64,
+        }),
+    );
+    Ok(outlines_id)
+}
 
-    // Set any Bookmarks to the First child if they are not set to a page
-    document.adjust_zero_pages();
+/// The comment after the header, whose bytes above 127 mark the file as binary
+const BINARY_MARK: [u8; 4] = [0xE2, 0xE3, 0xCF, 0xD3];
 
+pub fn save(mut document: Document) -> Result<Vec<u8>, Error> {
     document.compress();
+    // NOTE: PDF/A requires the mark, loaded documents keep the one they had
+    document.binary_mark = BINARY_MARK.to_vec();
 
     let mut buffer = Vec::new();
-    document
-        .save_to(&mut buffer)
-        .expect("Error saving merged PDF");
+    document.save_to(&mut buffer)?;
     Ok(buffer)
 }
+
+/// Information about the document, shown in the document properties of PDF readers
+pub struct Metadata<'a> {
+    pub title: &'a str,
+    pub author: &'a str,
+    pub subject: &'a str,
+    /// Comma-separated keywords
+    pub keywords: &'a str,
+    pub created: OffsetDateTime,
+    /// When the document was last changed, e.g. approved
+    pub modified: OffsetDateTime,
+    /// Identifies the document, the same for every copy of the same invoice
+    pub id: [u8; 16],
+}
+
+pub const PRODUCER: &str = "laskugeneraattori";
+
+/// Write the document information dictionary and the file identifier
+///
+/// Nothing in them depends on the time the document is generated at, so the
+/// same invoice always produces the same bytes.
+pub fn set_metadata(document: &mut Document, metadata: &Metadata) {
+    let created = pdf_date(metadata.created);
+    let modified = pdf_date(metadata.modified);
+    let info_id = document.add_object(dictionary! {
+        "Title" => text_string(metadata.title),
+        "Author" => text_string(metadata.author),
+        "Subject" => text_string(metadata.subject),
+        "Keywords" => text_string(metadata.keywords),
+        "Creator" => Object::string_literal(PRODUCER),
+        "Producer" => Object::string_literal(PRODUCER),
+        "CreationDate" => created,
+        "ModDate" => modified,
+    });
+    document.trailer.set("Info", info_id);
+
+    let id = Object::String(metadata.id.to_vec(), StringFormat::Hexadecimal);
+    document.trailer.set("ID", vec![id.clone(), id]);
+}
+
+/// Encode a date as a PDF date string in UTC
+pub(crate) fn pdf_date(date: OffsetDateTime) -> Object {
+    let date = date.to_offset(UtcOffset::UTC);
+    Object::string_literal(format!(
+        "D:{:04}{:02}{:02}{:02}{:02}{:02}Z",
+        date.year(),
+        u8::from(date.month()),
+        date.day(),
+        date.hour(),
+        date.minute(),
+        date.second()
+    ))
+}
+
+/// A file attached to the PDF, shown in the attachments panel of PDF readers
+pub struct EmbeddedFile {
+    pub name: String,
+    pub description: String,
+    pub mime: &'static str,
+    /// How the file relates to the document, e.g. `Source` or `Alternative`
+    pub relationship: &'static str,
+    pub data: Vec<u8>,
+}
+
+pub fn embed_files(document: &mut Document, files: Vec<EmbeddedFile>) -> Result<(), Error> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let mut names = Vec::with_capacity(files.len());
+    let mut associated = Vec::with_capacity(files.len());
+    for file in files {
+        let size = file.data.len() as i64;
+        let stream = Stream::new(
+            dictionary! {
+                "Type" => "EmbeddedFile",
+                "Subtype" => Object::Name(file.mime.into()),
+                "Params" => dictionary! { "Size" => size },
+            },
+            file.data,
+        );
+        let stream_id = document.add_object(stream);
+        let spec_id = document.add_object(dictionary! {
+            "Type" => "Filespec",
+            "F" => text_string(&file.name),
+            "UF" => text_string(&file.name),
+            "Desc" => text_string(&file.description),
+            "EF" => dictionary! { "F" => stream_id, "UF" => stream_id },
+            "AFRelationship" => Object::Name(file.relationship.into()),
+        });
+        names.push((text_string(&file.name), spec_id));
+        associated.push(Object::Reference(spec_id));
+    }
+
+    // NOTE: the keys of a name tree have to be sorted
+    names.sort_by(|(a, _), (b, _)| a.as_str().ok().cmp(&b.as_str().ok()));
+    let embedded_files = dictionary! {
+        "Names" => names
+            .into_iter()
+            .flat_map(|(name, id)| [name, Object::Reference(id)])
+            .collect::<Vec<_>>(),
+    };
+
+    let catalog_id = document.trailer.get(b"Root")?.as_reference()?;
+    let catalog = document.get_object_mut(catalog_id)?.as_dict_mut()?;
+    match catalog.get_mut(b"AF") {
+        Ok(Object::Array(af)) => af.extend(associated),
+        _ => catalog.set("AF", associated),
+    }
+
+    let names = match document.get_object(catalog_id)?.as_dict()?.get(b"Names") {
+        Ok(Object::Reference(id)) => document.get_object_mut(*id)?.as_dict_mut()?,
+        Ok(Object::Dictionary(_)) => document
+            .get_object_mut(catalog_id)?
+            .as_dict_mut()?
+            .get_mut(b"Names")?
+            .as_dict_mut()?,
+        _ => {
+            let catalog = document.get_object_mut(catalog_id)?.as_dict_mut()?;
+            catalog.set("Names", Dictionary::new());
+            catalog.get_mut(b"Names")?.as_dict_mut()?
+        }
+    };
+    names.set("EmbeddedFiles", embedded_files);
+
+    Ok(())
+}
+
+/// Encode a PDF text string, as UTF-16BE unless it's plain ASCII
+pub(crate) fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+
+    let mut bytes = vec![0xFE, 0xFF];
+    bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
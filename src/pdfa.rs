@@ -0,0 +1,413 @@
+use crate::error::Error;
+use crate::merge::{pdf_date, Metadata, PRODUCER};
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use std::collections::BTreeSet;
+use time::{OffsetDateTime, UtcOffset};
+use xmp_writer::{DateTime, Timezone, XmpWriter};
+
+/// The annotation flags that have to be cleared, and the print flag that has to be set
+const INVISIBLE: i64 = 1 << 0;
+const HIDDEN: i64 = 1 << 1;
+const PRINT: i64 = 1 << 2;
+const NO_VIEW: i64 = 1 << 5;
+const TOGGLE_NO_VIEW: i64 = 1 << 8;
+
+/// Make a merged document conform to PDF/A-3b
+///
+/// XMP metadata matching the document information dictionary written by
+/// [`crate::merge::set_metadata`] and an sRGB output intent are added, annotations are made
+/// printable and embedded files are dated. Fonts can't be embedded afterwards,
+/// typst embeds them but PDF attachments may not have.
+pub fn convert(document: &mut Document, metadata: &Metadata) -> Result<(), Error> {
+    let xmp_id = document.add_object(xmp(metadata, true));
+
+    let profile_id = document.add_object(Stream::new(
+        dictionary! { "N" => 3 },
+        typst_assets::icc::S_RGB_V4.to_vec(),
+    ));
+    let output_intent = dictionary! {
+        "Type" => "OutputIntent",
+        "S" => "GTS_PDFA1",
+        "OutputConditionIdentifier" => Object::string_literal("sRGB IEC61966-2.1"),
+        "Info" => Object::string_literal("sRGB IEC61966-2.1"),
+        "DestOutputProfile" => profile_id,
+    };
+
+    let catalog_id = document.trailer.get(b"Root")?.as_reference()?;
+    let catalog = document.get_object_mut(catalog_id)?.as_dict_mut()?;
+    catalog.set("Metadata", xmp_id);
+    catalog.set("OutputIntents", vec![Object::Dictionary(output_intent)]);
+    let form = match catalog.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => Some(*id),
+        Ok(Object::Dictionary(_)) => {
+            if let Ok(Object::Dictionary(form)) = catalog.get_mut(b"AcroForm") {
+                form.remove(b"NeedAppearances");
+            }
+            None
+        }
+        _ => None,
+    };
+    if let Some(Object::Dictionary(form)) = form.and_then(|id| document.objects.get_mut(&id)) {
+        form.remove(b"NeedAppearances");
+    }
+
+    for id in annotations(document) {
+        if let Some(Object::Dictionary(annotation)) = document.objects.get_mut(&id) {
+            let flags = annotation.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            let flags = (flags | PRINT) & !(INVISIBLE | HIDDEN | NO_VIEW | TOGGLE_NO_VIEW);
+            annotation.set("F", flags);
+        }
+    }
+
+    for id in embedded_files(document) {
+        if let Some(Object::Stream(stream)) = document.objects.get_mut(&id) {
+            let mut params = match stream.dict.get(b"Params") {
+                Ok(Object::Dictionary(params)) => params.clone(),
+                _ => Dictionary::new(),
+            };
+            if !params.has(b"ModDate") {
+                params.set("ModDate", pdf_date(metadata.created));
+            }
+            stream.dict.set("Params", params);
+        }
+    }
+
+    // NOTE: e.g. the catalogs and metadata of the merged documents are left unreferenced
+    document.prune_objects();
+    Ok(())
+}
+
+/// Remove the PDF/A identification from a converted document that doesn't
+/// conform after all, keeping the rest of the metadata
+pub fn unclaim(document: &mut Document, metadata: &Metadata) -> Result<(), Error> {
+    let xmp_id = document.catalog()?.get(b"Metadata")?.as_reference()?;
+    document
+        .objects
+        .insert(xmp_id, Object::Stream(xmp(metadata, false)));
+    Ok(())
+}
+
+/// The XMP metadata matching the document information dictionary, which
+/// identifies the document as PDF/A-3b with `pdf_a`
+fn xmp(metadata: &Metadata, pdf_a: bool) -> Stream {
+    let date = |date: OffsetDateTime| {
+        let date = date.to_offset(UtcOffset::UTC);
+        DateTime::new(
+            date.year() as u16,
+            date.month().into(),
+            date.day(),
+            date.hour(),
+            date.minute(),
+            date.second(),
+            Timezone::Utc,
+        )
+    };
+    let (created, modified) = (date(metadata.created), date(metadata.modified));
+    let id = hex::encode(metadata.id);
+
+    let mut xmp = XmpWriter::new();
+    xmp.title([(None, metadata.title)])
+        .creator([metadata.author])
+        .description([(None, metadata.subject)])
+        .pdf_keywords(metadata.keywords)
+        .format("application/pdf")
+        .create_date(created)
+        .modify_date(modified)
+        .metadata_date(modified)
+        .creator_tool(PRODUCER)
+        .producer(PRODUCER)
+        .document_id(&id)
+        .instance_id(&id);
+    if pdf_a {
+        xmp.pdfa_part("3").pdfa_conformance("B");
+    }
+    let mut xmp = Stream::new(
+        dictionary! {
+            "Type" => "Metadata",
+            "Subtype" => "XML",
+        },
+        xmp.finish(None).into_bytes(),
+    );
+    // NOTE: the metadata has to be readable without decoding the stream
+    xmp.allows_compression = false;
+    xmp
+}
+
+/// Find the problems that keep a document from conforming to PDF/A-3b
+///
+/// Only the structure of the document is checked, e.g. the contents of the
+/// fonts and the XMP metadata aren't validated.
+pub fn check<'a>(document: &'a Document) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if document.trailer.has(b"Encrypt") {
+        problems.push("the document is encrypted".into());
+    }
+    match document.trailer.get(b"ID").and_then(Object::as_array) {
+        Ok(id) if id.len() == 2 && id.iter().all(|i| i.as_str().is_ok()) => {}
+        _ => problems.push("the document has no file identifier".into()),
+    }
+
+    let Ok(catalog) = document.catalog() else {
+        problems.push("the document has no catalog".into());
+        return problems;
+    };
+    let dereference = |object: &'a Object| document.dereference(object).ok().map(|(_, o)| o);
+
+    match catalog.get(b"Metadata").ok().and_then(dereference) {
+        Some(Object::Stream(xmp)) => {
+            if xmp.dict.has(b"Filter") {
+                problems.push("the XMP metadata is compressed".into());
+            }
+            let xmp = String::from_utf8_lossy(&xmp.content);
+            if !xmp.contains("<pdfaid:part>3</pdfaid:part>")
+                || !xmp.contains("<pdfaid:conformance>B</pdfaid:conformance>")
+            {
+                problems.push("the XMP metadata doesn't identify the document as PDF/A-3b".into());
+            }
+        }
+        _ => problems.push("the document has no XMP metadata".into()),
+    }
+
+    let has_output_intent = catalog
+        .get(b"OutputIntents")
+        .ok()
+        .and_then(dereference)
+        .and_then(|intents| intents.as_array().ok())
+        .is_some_and(|intents| {
+            intents.iter().filter_map(dereference).any(|intent| {
+                intent.as_dict().is_ok_and(|intent| {
+                    intent.get(b"S").and_then(Object::as_name).ok() == Some(b"GTS_PDFA1")
+                        && intent
+                            .get(b"DestOutputProfile")
+                            .ok()
+                            .and_then(dereference)
+                            .is_some_and(|p| p.as_stream().is_ok())
+                })
+            })
+        });
+    if !has_output_intent {
+        problems.push("the document has no PDF/A output intent".into());
+    }
+
+    let mut fonts = BTreeSet::new();
+    for (number, page_id) in document.get_pages() {
+        let Ok(page) = document.get_dictionary(page_id) else {
+            continue;
+        };
+        if let Some(resources) = page.get(b"Resources").ok().and_then(dereference) {
+            collect_fonts(document, resources, &mut fonts, 0);
+        }
+        for id in page_annotations(document, page) {
+            let Ok(annotation) = document.get_dictionary(id) else {
+                continue;
+            };
+            let subtype = annotation.get(b"Subtype").and_then(Object::as_name).ok();
+            let flags = annotation.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            if subtype != Some(b"Popup")
+                && (flags & PRINT == 0
+                    || flags & (INVISIBLE | HIDDEN | NO_VIEW | TOGGLE_NO_VIEW) != 0)
+            {
+                problems.push(format!("an annotation on page {number} isn't printable"));
+            }
+            if !matches!(subtype, Some(b"Link" | b"Popup")) && !annotation.has(b"AP") {
+                problems.push(format!("an annotation on page {number} has no appearance"));
+            }
+        }
+    }
+    for id in fonts {
+        let Ok(font) = document.get_dictionary(id) else {
+            continue;
+        };
+        if !font_is_embedded(document, font) {
+            let name = font
+                .get(b"BaseFont")
+                .and_then(Object::as_name)
+                .map_or("unnamed".into(), String::from_utf8_lossy);
+            problems.push(format!("the font {name} isn't embedded"));
+        }
+    }
+
+    let need_appearances = catalog
+        .get(b"AcroForm")
+        .ok()
+        .and_then(dereference)
+        .and_then(|form| {
+            form.as_dict()
+                .ok()?
+                .get(b"NeedAppearances")
+                .ok()?
+                .as_bool()
+                .ok()
+        });
+    if need_appearances == Some(true) {
+        problems.push("the form fields have no appearances".into());
+    }
+
+    let associated = catalog
+        .get(b"AF")
+        .and_then(Object::as_array)
+        .map(|af| af.iter().filter_map(|f| f.as_reference().ok()).collect())
+        .unwrap_or_else(|_| Vec::new());
+    for spec_id in filespecs(document) {
+        let Ok(spec) = document.get_dictionary(spec_id) else {
+            continue;
+        };
+        let name = spec
+            .get(b"UF")
+            .or_else(|_| spec.get(b"F"))
+            .and_then(Object::as_str)
+            .map(|n| String::from_utf8_lossy(n).into_owned())
+            .unwrap_or_default();
+        if !spec.has(b"AFRelationship") || !associated.contains(&spec_id) {
+            problems.push(format!(
+                "the embedded file {name} isn't associated with the document"
+            ));
+        }
+        let file = spec
+            .get(b"EF")
+            .and_then(Object::as_dict)
+            .and_then(|ef| ef.get(b"F"))
+            .ok()
+            .and_then(dereference)
+            .and_then(|f| f.as_stream().ok());
+        let dated = file.is_some_and(|f| {
+            f.dict.has(b"Subtype")
+                && f.dict
+                    .get(b"Params")
+                    .and_then(Object::as_dict)
+                    .is_ok_and(|p| p.has(b"ModDate"))
+        });
+        if !dated {
+            problems.push(format!("the embedded file {name} has no type or date"));
+        }
+    }
+
+    problems
+}
+
+/// Collect the fonts used by the resources and the forms in them
+fn collect_fonts(
+    document: &Document,
+    resources: &Object,
+    fonts: &mut BTreeSet<ObjectId>,
+    depth: usize,
+) {
+    let Ok(resources) = resources.as_dict() else {
+        return;
+    };
+    let entries = |key: &[u8]| {
+        resources
+            .get(key)
+            .and_then(|d| document.dereference(d))
+            .and_then(|(_, d)| d.as_dict())
+            .map(|d| d.iter().map(|(_, v)| v).collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+
+    fonts.extend(
+        entries(b"Font")
+            .into_iter()
+            .filter_map(|f| f.as_reference().ok()),
+    );
+
+    // NOTE: limit the depth in case the forms refer to each other
+    if depth < 8 {
+        for xobject in entries(b"XObject") {
+            let Ok((_, Object::Stream(xobject))) = document.dereference(xobject) else {
+                continue;
+            };
+            if let Ok((_, resources)) = xobject
+                .dict
+                .get(b"Resources")
+                .and_then(|r| document.dereference(r))
+            {
+                collect_fonts(document, resources, fonts, depth + 1);
+            }
+        }
+    }
+}
+
+fn dictionary<'a>(document: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    document
+        .dereference(object)
+        .ok()
+        .and_then(|(_, o)| o.as_dict().ok())
+}
+
+fn font_is_embedded(document: &Document, font: &Dictionary) -> bool {
+    match font.get(b"Subtype").and_then(Object::as_name) {
+        // NOTE: the glyphs of type 3 fonts are content streams in the font itself
+        Ok(b"Type3") => true,
+        Ok(b"Type0") => font
+            .get(b"DescendantFonts")
+            .ok()
+            .and_then(|d| document.dereference(d).ok())
+            .and_then(|(_, d)| d.as_array().ok()?.first())
+            .and_then(|d| dictionary(document, d))
+            .is_some_and(|descendant| font_is_embedded(document, descendant)),
+        _ => font
+            .get(b"FontDescriptor")
+            .ok()
+            .and_then(|d| dictionary(document, d))
+            .is_some_and(|descriptor| {
+                [&b"FontFile"[..], b"FontFile2", b"FontFile3"]
+                    .iter()
+                    .any(|key| descriptor.has(key))
+            }),
+    }
+}
+
+fn page_annotations(document: &Document, page: &Dictionary) -> Vec<ObjectId> {
+    page.get(b"Annots")
+        .and_then(|a| document.dereference(a))
+        .and_then(|(_, a)| a.as_array())
+        .map(|a| a.iter().filter_map(|a| a.as_reference().ok()).collect())
+        .unwrap_or_default()
+}
+
+fn annotations(document: &Document) -> Vec<ObjectId> {
+    document
+        .get_pages()
+        .into_values()
+        .filter_map(|id| document.get_dictionary(id).ok())
+        .flat_map(|page| page_annotations(document, page))
+        .collect()
+}
+
+/// The file specifications in the embedded files name tree
+fn filespecs(document: &Document) -> Vec<ObjectId> {
+    document
+        .catalog()
+        .and_then(|c| c.get(b"Names"))
+        .and_then(|n| document.dereference(n))
+        .and_then(|(_, n)| n.as_dict())
+        .and_then(|n| n.get(b"EmbeddedFiles"))
+        .and_then(|e| document.dereference(e))
+        .and_then(|(_, e)| e.as_dict())
+        .and_then(|e| e.get(b"Names"))
+        .and_then(Object::as_array)
+        .map(|names| {
+            names
+                .chunks_exact(2)
+                .filter_map(|pair| pair[1].as_reference().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The embedded file streams
+fn embedded_files(document: &Document) -> Vec<ObjectId> {
+    filespecs(document)
+        .into_iter()
+        .filter_map(|id| document.get_dictionary(id).ok())
+        .filter_map(|spec| {
+            spec.get(b"EF")
+                .and_then(Object::as_dict)
+                .and_then(|ef| ef.get(b"F"))
+                .and_then(Object::as_reference)
+                .ok()
+        })
+        .collect()
+}
@@ -0,0 +1,93 @@
+use typst::diag::{Severity, SourceDiagnostic};
+use typst::World;
+
+/// The beginnings of the errors typst raises when an image can't be loaded,
+/// which are caused by the attachments rather than the template
+const IMAGE_ERRORS: &[&str] = &[
+    "failed to decode image",
+    "failed to parse SVG",
+    "unknown image format",
+    "file is too large",
+    "file is not valid utf-8",
+    "file is not compressed correctly",
+];
+
+/// Where in a template a diagnostic points to, the line and column start from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A Typst error or warning with the place in the template it was raised at
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub hints: Vec<String>,
+    pub location: Option<Location>,
+}
+
+impl Diagnostic {
+    pub fn new(world: &dyn World, diagnostic: &SourceDiagnostic) -> Self {
+        let location = diagnostic.span.id().and_then(|id| {
+            let source = world.source(id).ok()?;
+            let start = source.range(diagnostic.span)?.start;
+            Some(Location {
+                file: id.vpath().as_rootless_path().display().to_string(),
+                line: source.byte_to_line(start)? + 1,
+                column: source.byte_to_column(start)? + 1,
+            })
+        });
+
+        Self {
+            message: diagnostic.message.to_string(),
+            hints: diagnostic.hints.iter().map(ToString::to_string).collect(),
+            location,
+        }
+    }
+
+    /// Whether the data filled into the template caused the diagnostic,
+    /// instead of a bug in the template
+    pub fn caused_by_input(&self) -> bool {
+        IMAGE_ERRORS.iter().any(|e| self.message.starts_with(e))
+    }
+
+    /// The message and hints without the details of the template, which
+    /// can be shown to the submitter
+    pub fn sanitized(&self) -> String {
+        std::iter::once(self.message.as_str())
+            .chain(self.hints.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Log the diagnostic with its place in the template
+    pub fn log(&self, severity: Severity) {
+        let (file, line, column) = match &self.location {
+            Some(l) => (Some(l.file.as_str()), Some(l.line), Some(l.column)),
+            None => (None, None, None),
+        };
+        match severity {
+            Severity::Error => {
+                error!(file, line, column, hints = ?self.hints, "Typst error: {}", self.message)
+            }
+            Severity::Warning => {
+                warn!(file, line, column, hints = ?self.hints, "Typst warning: {}", self.message)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(Location { file, line, column }) = &self.location {
+            write!(f, "{file}:{line}:{column}: ")?;
+        }
+        f.write_str(&self.message)?;
+        for hint in &self.hints {
+            write!(f, " (hint: {hint})")?;
+        }
+        Ok(())
+    }
+}
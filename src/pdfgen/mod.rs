@@ -1,13 +1,17 @@
-use crate::{api::invoices::Invoice, error::Error};
+use crate::{
+    api::invoices::{Invoice, InvoiceAttachment},
+    error::Error,
+    storage::Approval,
+};
 use comemo::Prehashed;
 use std::{
-    cell::{RefCell, RefMut},
+    cell::{OnceCell, RefCell, RefMut},
     collections::HashMap,
     path::PathBuf,
     sync::OnceLock,
 };
 use typst::{
-    diag::{FileError, FileResult},
+    diag::{FileError, FileResult, Severity},
     eval::Tracer,
     foundations::{Bytes, Datetime, IntoValue, Value},
     model::Document,
@@ -16,6 +20,28 @@ use typst::{
     Library, World,
 };
 
+pub mod diagnostic;
+mod value;
+
+pub use diagnostic::Diagnostic;
+
+/// A Typst template and the path its diagnostics refer to
+#[derive(Clone, Copy, Debug)]
+pub struct Template<'a> {
+    pub path: &'a str,
+    pub source: &'a str,
+}
+
+pub const INVOICE: Template<'static> = Template {
+    path: "/invoice.typ",
+    source: include_str!("../../templates/invoice.typ"),
+};
+
+pub const APPROVAL: Template<'static> = Template {
+    path: "/approval.typ",
+    source: include_str!("../../templates/approval.typ"),
+};
+
 thread_local! {
     static WORLD: RefCell<Sandbox> = RefCell::new(Sandbox::new());
 }
@@ -85,23 +111,48 @@ fn fonts() -> (FontBook, Vec<FontSlot>) {
 
 #[derive(Clone, Debug)]
 struct FileEntry {
-    bytes: Bytes,
+    bytes: OnceCell<Bytes>,
+    /// The file on disk the bytes are read from when first accessed
+    path: Option<PathBuf>,
     source: Option<Source>,
 }
 
 impl FileEntry {
     fn new(bytes: Vec<u8>, source: Option<Source>) -> Self {
         Self {
-            bytes: bytes.into(),
+            bytes: OnceCell::from(Bytes::from(bytes)),
+            path: None,
             source,
         }
     }
 
+    fn lazy(path: PathBuf) -> Self {
+        Self {
+            bytes: OnceCell::new(),
+            path: Some(path),
+            source: None,
+        }
+    }
+
+    fn bytes(&self) -> FileResult<Bytes> {
+        if let Some(bytes) = self.bytes.get() {
+            return Ok(bytes.clone());
+        }
+
+        let path = self
+            .path
+            .as_ref()
+            .expect("bug: file entry without bytes or path");
+        let bytes = std::fs::read(path).map_err(|e| FileError::from_io(e, path))?;
+        Ok(self.bytes.get_or_init(|| bytes.into()).clone())
+    }
+
     fn source(&mut self, id: FileId) -> FileResult<Source> {
         let source = if let Some(source) = &self.source {
             source
         } else {
-            let contents = std::str::from_utf8(&self.bytes).map_err(|_| FileError::InvalidUtf8)?;
+            let bytes = self.bytes()?;
+            let contents = std::str::from_utf8(&bytes).map_err(|_| FileError::InvalidUtf8)?;
             let contents = contents.trim_start_matches('\u{feff}');
             let source = Source::new(id, contents.into());
             self.source.insert(source)
@@ -129,7 +180,7 @@ impl Sandbox {
             library: Prehashed::new(Library::builder().build()),
             book: Prehashed::new(book),
             fonts,
-            source: Source::detached(include_str!("../../templates/invoice.typ")),
+            source: Source::detached(INVOICE.source),
             time: time::OffsetDateTime::now_utc(),
             files: RefCell::new(HashMap::new()),
         };
@@ -152,15 +203,30 @@ impl Sandbox {
         }
     }
 
-    fn with_data(&self, data: impl IntoValue) -> Self {
+    /// A sandbox for compiling the template with the given data, where today is `time`
+    fn with_data(
+        &self,
+        template: Template<'_>,
+        data: impl IntoValue,
+        approval: Option<&Approval>,
+        time: time::OffsetDateTime,
+    ) -> Self {
         let mut new = self.clone();
         new.library.update(|l| {
             let scope = l.global.scope_mut();
             scope.define("data", data);
+            scope.define(
+                "approval",
+                approval.map_or(Value::None, value::approval_value),
+            );
             scope.define("COMMIT_HASH", Value::Str(env!("COMMIT_HASH").into()));
             scope.define("VERSION", Value::Str(env!("CARGO_PKG_VERSION").into()));
         });
-        new.time = time::OffsetDateTime::now_utc();
+        new.source = Source::new(
+            FileId::new(None, VirtualPath::new(template.path)),
+            template.source.into(),
+        );
+        new.time = time;
         new
     }
 }
@@ -187,7 +253,7 @@ impl World for Sandbox {
     }
 
     fn file(&self, id: FileId) -> FileResult<Bytes> {
-        self.sandbox_file(id).map(|file| file.bytes.clone())
+        self.sandbox_file(id)?.bytes()
     }
 
     fn font(&self, index: usize) -> Option<Font> {
@@ -202,33 +268,84 @@ impl World for Sandbox {
     }
 }
 
-impl IntoValue for Invoice {
-    fn into_value(self) -> typst::foundations::Value {
-        serde_json::from_str(&serde_json::to_string(&self).unwrap()).unwrap()
+/// Format a time like `19.10.2024 klo 12.00 UTC`
+fn timestamp(time: time::OffsetDateTime) -> String {
+    time.to_offset(time::UtcOffset::UTC)
+        .format(time::macros::format_description!(
+            "[day padding:none].[month padding:none].[year] klo [hour].[minute] UTC"
+        ))
+        .expect("bug: invalid format description")
+}
+
+/// Compile the template, logging the diagnostics
+///
+/// An error caused by the data filled into the template, like an attachment
+/// image that can't be decoded, is reported without the details of the template.
+fn compile(world: &Sandbox) -> Result<Document, Error> {
+    let mut tracer = Tracer::default();
+    let result = typst::compile(world, &mut tracer);
+    for warning in tracer.warnings() {
+        Diagnostic::new(world, &warning).log(Severity::Warning);
     }
+
+    result.map_err(|errors| {
+        let diagnostics = errors
+            .iter()
+            .map(|e| Diagnostic::new(world, e))
+            .collect::<Vec<_>>();
+        diagnostics.iter().for_each(|d| d.log(Severity::Error));
+        match diagnostics.iter().find(|d| d.caused_by_input()) {
+            Some(diagnostic) => Error::InvalidRenderInput(diagnostic.sanitized()),
+            None => Error::TypstError(diagnostics),
+        }
+    })
 }
 
-impl TryInto<Document> for Invoice {
-    type Error = Error;
-
-    fn try_into(self) -> Result<Document, Error> {
-        let w = WORLD.with_borrow(|w| w.with_data(self.clone()));
-        self.attachments.into_iter().for_each(|a| {
-            w.files.borrow_mut().insert(
-                FileId::new(
-                    None,
-                    VirtualPath::new("/attachments/".to_owned() + &a.filename),
-                ),
-                FileEntry::new(a.bytes, None),
-            );
-        });
+/// Compile a template with the data and the attachments, where today is `time`
+///
+/// The attachments are mounted at `/attachments/<index>`, since their
+/// filenames aren't unique.
+pub fn compile_template(
+    template: Template<'_>,
+    data: impl IntoValue,
+    approval: Option<&Approval>,
+    time: time::OffsetDateTime,
+    attachments: &[InvoiceAttachment],
+) -> Result<Document, Error> {
+    let w = WORLD.with_borrow(|w| w.with_data(template, data, approval, time));
+    attachments.iter().enumerate().for_each(|(i, a)| {
+        w.files.borrow_mut().insert(
+            FileId::new(None, VirtualPath::new(format!("/attachments/{i}"))),
+            FileEntry::lazy(a.path().to_path_buf()),
+        );
+    });
 
-        let mut tracer = Tracer::default();
-        let template = typst::compile(&w, &mut tracer).map_err(|e| {
-            dbg!(e);
-            Error::TypstError
-        })?;
+    compile(&w)
+}
 
-        Ok(template)
-    }
+/// Render the invoice as it was on `date`, with the approval
+/// filled into the treasurer's box
+pub fn render(
+    invoice: Invoice,
+    date: time::OffsetDateTime,
+    approval: Option<&Approval>,
+) -> Result<Document, Error> {
+    let attachments = invoice.attachments.clone();
+    compile_template(INVOICE, invoice, approval, date, &attachments)
+}
+
+/// Render the audit page appended to approved invoices
+pub fn render_approval(
+    invoice: &Invoice,
+    id: uuid::Uuid,
+    created_at: time::OffsetDateTime,
+    approval: &Approval,
+) -> Result<Document, Error> {
+    compile_template(
+        APPROVAL,
+        value::audit_value(invoice, id, created_at),
+        Some(approval),
+        approval.approved_at,
+        &[],
+    )
 }
@@ -0,0 +1,89 @@
+use crate::api::invoices::{Address, Invoice, InvoiceAttachment, InvoiceRow};
+use crate::storage::Approval;
+use typst::foundations::{dict, IntoValue, Value};
+
+impl IntoValue for Address {
+    fn into_value(self) -> Value {
+        Value::Dict(dict! {
+            "street" => self.street,
+            "city" => self.city,
+            "zip" => self.zip,
+        })
+    }
+}
+
+impl IntoValue for InvoiceRow {
+    fn into_value(self) -> Value {
+        Value::Dict(dict! {
+            "total" => self.total(),
+            "product" => self.product,
+            "quantity" => i64::from(self.quantity),
+            "unit" => self.unit,
+            "unit_price" => i64::from(self.unit_price),
+        })
+    }
+}
+
+impl IntoValue for InvoiceAttachment {
+    fn into_value(self) -> Value {
+        Value::Dict(dict! {
+            "filename" => self.filename,
+            "size" => self.size,
+            // NOTE: the extensions of the formats that reach the template are
+            // also the names typst knows them by
+            "format" => self.format.extension(),
+            "mime" => self.format.mime(),
+            "pages" => self.pages,
+        })
+    }
+}
+
+/// The invoice as the template gets it, without the passwords of the attachments
+///
+/// The contents of the attachments aren't included, the template reads them
+/// from the files of the world.
+impl IntoValue for Invoice {
+    fn into_value(self) -> Value {
+        Value::Dict(dict! {
+            "total" => self.total(),
+            "recipient_name" => self.recipient_name,
+            "recipient_email" => self.recipient_email,
+            "address" => self.address,
+            "bank_account_number" => self.bank_account_number,
+            "subject" => self.subject,
+            "description" => self.description,
+            "phone_number" => self.phone_number,
+            "attachment_descriptions" => self.attachment_descriptions,
+            "rows" => self.rows,
+            "attachments" => self.attachments,
+        })
+    }
+}
+
+/// The approval with its date split into numbers for the template
+pub(super) fn approval_value(approval: &Approval) -> Value {
+    Value::Dict(dict! {
+        "day" => approval.date.day(),
+        "month" => u8::from(approval.date.month()),
+        "year" => i64::from(approval.date.year()),
+        "meeting" => approval.meeting.clone(),
+        "approver" => approval.approver.clone(),
+        "account" => approval.account.clone(),
+        "approved_at" => super::timestamp(approval.approved_at),
+    })
+}
+
+/// The summary of the invoice on the audit page of an approved invoice
+pub(super) fn audit_value(
+    invoice: &Invoice,
+    id: uuid::Uuid,
+    created_at: time::OffsetDateTime,
+) -> Value {
+    Value::Dict(dict! {
+        "id" => id.to_string(),
+        "created_at" => super::timestamp(created_at),
+        "subject" => invoice.subject.clone(),
+        "recipient_name" => invoice.recipient_name.clone(),
+        "total" => invoice.total(),
+    })
+}
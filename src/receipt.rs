@@ -0,0 +1,361 @@
+use crate::api::invoices::InvoiceAttachment;
+use crate::attachments::Format;
+use crate::error::Error;
+
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use time::macros::format_description;
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
+
+static DATE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b(?:(\d{1,2})\.(\d{1,2})\.(\d{4}|\d{2})|(\d{4})-(\d{2})-(\d{2}))\b").unwrap()
+});
+static AMOUNT: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-?\b\d{1,6}[,.]\d{2}\b").unwrap());
+static TOTAL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^\s*(yhteensä|yhteensa|yht\b|loppusumma|summa|maksettava|total)").unwrap()
+});
+static PAYMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^\s*(korttimaksu|pankkikortti|käteinen|visa|mastercard|maksukortti)").unwrap()
+});
+static VAT_HEADER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\b(alv|vero)").unwrap());
+/// A row of a VAT table, e.g. `14,00 % 11,64 1,63 13,27` or `ALV 25,5% 2,03`
+static VAT_ROW: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^\s*(?:alv\s*)?(?:[a-z]\s+)?(\d{1,2}(?:[,.]\d{1,2})?)\s*(%?)((?:\s+-?\d{1,6}[,.]\d{2}){1,3})\s*$")
+        .unwrap()
+});
+/// Lines that aren't the name of the merchant
+static NOT_MERCHANT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)y-?tunnus|\bpuh|\btel\b|www\.|https?:|kuitti|tervetuloa|\b\d{5}\b").unwrap()
+});
+
+/// The VAT rates used in Finland, in percent
+const VAT_RATES: [f64; 6] = [0.0, 10.0, 13.5, 14.0, 24.0, 25.5];
+
+/// Information read from a receipt, amounts are in cents
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub merchant: Option<String>,
+    /// The date of the purchase in ISO 8601 format
+    pub date: Option<String>,
+    pub total: Option<i32>,
+    pub vat: Vec<VatLine>,
+}
+
+/// A row of the VAT breakdown, amounts are in cents
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VatLine {
+    /// The VAT rate in percent
+    pub rate: f64,
+    pub net: Option<i32>,
+    pub tax: i32,
+    pub gross: Option<i32>,
+}
+
+impl Receipt {
+    /// Read the merchant, date, total and VAT breakdown from the text
+    /// of a Finnish receipt
+    pub fn parse(text: &str) -> Self {
+        let lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+
+        let merchant = lines
+            .iter()
+            .find(|l| {
+                l.chars().filter(|c| c.is_alphabetic()).count() >= 3 && !NOT_MERCHANT.is_match(l)
+            })
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "));
+
+        let date = lines
+            .iter()
+            .flat_map(|l| DATE.captures_iter(l))
+            .find_map(|c| {
+                let number = |i: usize| c.get(i).map(|m| m.as_str().parse::<i32>().unwrap());
+                let (day, month, year) = match number(1) {
+                    Some(day) => (day, number(2)?, number(3)?),
+                    None => (number(6)?, number(5)?, number(4)?),
+                };
+                let year = if year < 100 { year + 2000 } else { year };
+                Date::from_calendar_date(
+                    year,
+                    u8::try_from(month).ok()?.try_into().ok()?,
+                    day as u8,
+                )
+                .ok()
+            })
+            .map(|d| d.to_string());
+
+        let last_amount = |line: &&str| AMOUNT.find_iter(line).last().map(|m| cents(m.as_str()));
+        let total = lines
+            .iter()
+            .filter(|l| TOTAL.is_match(l))
+            .find_map(last_amount)
+            .or_else(|| {
+                lines
+                    .iter()
+                    .filter(|l| PAYMENT.is_match(l))
+                    .find_map(last_amount)
+            });
+
+        let mut vat = Vec::new();
+        let mut in_vat_table = false;
+        for line in &lines {
+            match VAT_ROW.captures(line) {
+                Some(c) if in_vat_table || !c[2].is_empty() || VAT_HEADER.is_match(line) => {
+                    let rate = c[1].replace(',', ".").parse::<f64>().unwrap();
+                    let amounts = AMOUNT
+                        .find_iter(&c[3])
+                        .map(|m| cents(m.as_str()))
+                        .collect::<Vec<_>>();
+                    if let Some(line) = VatLine::new(rate, &amounts) {
+                        vat.push(line);
+                    }
+                }
+                _ => in_vat_table = VAT_HEADER.is_match(line) && !AMOUNT.is_match(line),
+            }
+        }
+
+        Self {
+            merchant,
+            date,
+            total,
+            vat,
+        }
+    }
+}
+
+impl VatLine {
+    fn new(rate: f64, amounts: &[i32]) -> Option<Self> {
+        if !VAT_RATES.contains(&rate) {
+            return None;
+        }
+        // Allow a cent of rounding error
+        let close = |a: f64, b: i32| (a - b as f64).abs() <= 1.0;
+
+        let line = |net: Option<i32>, tax, gross: Option<i32>| Self {
+            rate,
+            net,
+            tax,
+            gross,
+        };
+        match *amounts {
+            [net, tax, gross] => Some(line(Some(net), tax, Some(gross))),
+            [a, b] if close(a as f64 * rate / 100.0, b) => Some(line(Some(a), b, Some(a + b))),
+            [a, b] if close(b as f64 * rate / (100.0 + rate), a) => {
+                Some(line(Some(b - a), a, Some(b)))
+            }
+            [a, b] if close(a as f64 * rate / (100.0 + rate), b) => {
+                Some(line(Some(a - b), b, Some(a)))
+            }
+            [tax] => Some(line(None, tax, None)),
+            _ => None,
+        }
+    }
+}
+
+fn cents(amount: &str) -> i32 {
+    let negative = amount.starts_with('-');
+    let cents = amount
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>()
+        .parse::<i32>()
+        .unwrap();
+    if negative {
+        -cents
+    } else {
+        cents
+    }
+}
+
+/// Read the text of a receipt
+///
+/// The text layer of PDFs is used as is, images are read with tesseract.
+/// Tesseract is killed if it doesn't finish in `OCR_TIMEOUT_SECS`.
+pub async fn text(attachment: &InvoiceAttachment) -> Result<String, Error> {
+    match attachment.format {
+        Format::Pdf => {
+            let path = attachment.path().to_path_buf();
+            tokio::task::spawn_blocking(move || pdf_text(&path))
+                .await
+                .map_err(std::io::Error::from)?
+        }
+        Format::Jpg | Format::Png | Format::Gif => {
+            let tesseract = crate::CONFIG
+                .tesseract
+                .as_ref()
+                .ok_or(Error::OcrUnavailable)?;
+            let timeout = crate::CONFIG.ocr_timeout_secs;
+            // NOTE: the process is killed when the timeout drops the future
+            let output = tokio::process::Command::new(tesseract)
+                .arg(attachment.path())
+                .arg("stdout")
+                .arg("-l")
+                .arg(&crate::CONFIG.ocr_languages)
+                .kill_on_drop(true)
+                .output();
+            let output = tokio::time::timeout(std::time::Duration::from_secs(timeout), output)
+                .await
+                .map_err(|_| Error::OcrTimeout(timeout))??;
+            if !output.status.success() {
+                return Err(Error::OcrError(
+                    String::from_utf8_lossy(&output.stderr).into_owned(),
+                ));
+            }
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        _ => Err(Error::UnsupportedFileFormat(attachment.filename.clone())),
+    }
+}
+
+fn pdf_text(path: &std::path::Path) -> Result<String, Error> {
+    let document = lopdf::Document::load(path)?;
+    let pages = document.get_pages().into_keys().collect::<Vec<_>>();
+    Ok(document.extract_text(&pages)?)
+}
+
+/// A total found on an attachment, in cents
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentTotal {
+    pub filename: String,
+    pub total: i32,
+}
+
+/// A possible problem with an invoice, which doesn't prevent submitting it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Warning {
+    /// The totals on the attachments don't add up to the total of the rows
+    TotalMismatch {
+        invoice_total: i64,
+        attachments_total: i64,
+        attachments: Vec<AttachmentTotal>,
+    },
+    /// A previous invoice has the same details or attachments
+    PossibleDuplicate {
+        invoice: Uuid,
+        #[serde(with = "time::serde::rfc3339")]
+        submitted_at: OffsetDateTime,
+        /// The submitter, total, date and subject are the same
+        same_details: bool,
+        /// The attachments that are identical to the attachments of the previous invoice
+        attachments: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TotalMismatch {
+                invoice_total,
+                attachments_total,
+                attachments,
+            } => {
+                write!(
+                    f,
+                    "Liitteiden summa {} € ei vastaa laskun summaa {} € (",
+                    euros(*attachments_total),
+                    euros(*invoice_total)
+                )?;
+                for (i, a) in attachments.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    write!(f, "{separator}{}: {} €", a.filename, euros(a.total.into()))?;
+                }
+                write!(f, ")")
+            }
+            Self::PossibleDuplicate {
+                invoice,
+                submitted_at,
+                same_details,
+                attachments,
+            } => {
+                write!(
+                    f,
+                    "Lasku saattaa olla kaksoiskappale {} lähetetystä laskusta {invoice}",
+                    submitted_at
+                        .format(format_description!(
+                            "[day padding:none].[month padding:none].[year]"
+                        ))
+                        .expect("bug: invalid format description")
+                )?;
+                if *same_details {
+                    write!(f, ", jossa on sama lähettäjä, summa, päivämäärä ja aihe")?;
+                }
+                if !attachments.is_empty() {
+                    write!(f, ", jossa on samat liitteet: {}", attachments.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Format cents as euros with a decimal comma
+fn euros(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{sign}{},{:02}", cents.abs() / 100, cents.abs() % 100)
+}
+
+/// The receipts printed on the PDF attachments, with the filenames of the attachments
+///
+/// The text of each PDF is read once, on a blocking thread. Attachments
+/// whose text can't be read are skipped.
+pub async fn read_receipts(
+    attachments: &[InvoiceAttachment],
+) -> Result<Vec<(String, Receipt)>, Error> {
+    let pdfs = attachments
+        .iter()
+        .filter(|a| a.format == Format::Pdf)
+        .map(|a| (a.filename.clone(), a.path().to_path_buf()))
+        .collect::<Vec<_>>();
+
+    let receipts = tokio::task::spawn_blocking(move || {
+        pdfs.into_iter()
+            .filter_map(|(filename, path)| match pdf_text(&path) {
+                Ok(text) => Some((filename, Receipt::parse(&text))),
+                Err(e) => {
+                    debug!("Couldn't read the text of {filename:?}: {e}");
+                    None
+                }
+            })
+            .collect()
+    })
+    .await
+    .map_err(std::io::Error::from)?;
+    Ok(receipts)
+}
+
+/// The earliest purchase date printed on the receipts, in ISO 8601 format
+pub fn purchase_date(receipts: &[(String, Receipt)]) -> Option<String> {
+    receipts.iter().filter_map(|(_, r)| r.date.clone()).min()
+}
+
+/// Compare the totals printed on the receipts to the total of the invoice
+///
+/// Receipts without a recognizable total are skipped.
+pub fn check_totals(invoice_total: i64, receipts: &[(String, Receipt)]) -> Option<Warning> {
+    let totals = receipts
+        .iter()
+        .filter_map(|(filename, receipt)| {
+            Some(AttachmentTotal {
+                filename: filename.clone(),
+                total: receipt.total?,
+            })
+        })
+        .collect::<Vec<_>>();
+    if totals.is_empty() {
+        return None;
+    }
+
+    let attachments_total = totals.iter().map(|t| t.total as i64).sum::<i64>();
+
+    (invoice_total != attachments_total).then_some(Warning::TotalMismatch {
+        invoice_total,
+        attachments_total,
+        attachments: totals,
+    })
+}
@@ -0,0 +1,599 @@
+use crate::error::Error;
+use crate::merge::pdf_date;
+use crate::state::State;
+
+use axum::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use cms::builder::{SignedDataBuilder, SignerInfoBuilder};
+use cms::cert::{CertificateChoices, IssuerAndSerialNumber};
+use cms::content_info::ContentInfo;
+use cms::signed_data::{EncapsulatedContentInfo, SignedData, SignerIdentifier};
+use der::asn1::{OctetString, SetOfVec};
+use der::oid::ObjectIdentifier;
+use der::referenced::OwnedToRef;
+use der::{Any, Decode, Encode, SliceReader};
+use lopdf::xref::XrefEntry;
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use p12_keystore::KeyStore;
+use pkcs8::{DecodePrivateKey, EncodePublicKey};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::signature::{Keypair, Verifier};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use x509_cert::spki::{
+    AlgorithmIdentifierOwned, DynSignatureAlgorithmIdentifier, SignatureBitStringEncoding,
+};
+use x509_cert::Certificate;
+
+const ID_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+const ID_MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const ID_SIGNING_CERTIFICATE_V2: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.16.2.47");
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const SHA_256_WITH_RSA_ENCRYPTION: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.11");
+const ECDSA_WITH_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+
+/// The space reserved for the signature, which has to be written before
+/// the signed bytes are known
+const SIGNATURE_SIZE: usize = 16 * 1024;
+/// Replaced with the byte range once the document has been written, the
+/// written range is padded with spaces to the same length
+const BYTE_RANGE_PLACEHOLDER: i64 = 9_999_999_999;
+
+/// Signs generated PDFs with the configured certificate, does nothing if
+/// signing isn't configured
+#[derive(Clone, Default)]
+pub struct Signer {
+    credentials: Option<Arc<Credentials>>,
+}
+
+struct Credentials {
+    /// The signing certificate followed by the rest of its chain
+    chain: Vec<Certificate>,
+    key: Key,
+}
+
+enum Key {
+    Rsa(Box<rsa::pkcs1v15::SigningKey<Sha256>>),
+    P256(p256::ecdsa::SigningKey),
+}
+
+/// The result of checking a signature of a PDF
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Verification {
+    /// The subject of the signing certificate
+    pub signer: Option<String>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub signed_at: Option<OffsetDateTime>,
+    /// The signed bytes haven't been modified since signing
+    pub valid: bool,
+    /// The signature covers the whole file, nothing has been appended to it
+    /// after signing
+    pub covers_document: bool,
+    /// The document was signed with the certificate of this service
+    pub trusted: bool,
+    /// Why the signature isn't valid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Signer {
+    /// Load the signing certificate and key configured in `LaskugenConfig`
+    pub fn from_config() -> Result<Self, Error> {
+        let Some(certificate) = &crate::CONFIG.signing_certificate else {
+            return Ok(Self::default());
+        };
+        let certificate = std::fs::read(certificate)?;
+        let key = crate::CONFIG
+            .signing_key
+            .as_ref()
+            .map(std::fs::read)
+            .transpose()?;
+
+        Self::load(
+            &certificate,
+            key.as_deref(),
+            crate::CONFIG.signing_password.as_deref(),
+        )
+    }
+
+    /// Load a PKCS#12 file, or a PEM certificate chain and a PEM private key
+    ///
+    /// The password is used for the PKCS#12 file or an encrypted PKCS#8 key.
+    /// RSA and P-256 keys are supported.
+    pub fn load(
+        certificate: &[u8],
+        key: Option<&[u8]>,
+        password: Option<&str>,
+    ) -> Result<Self, Error> {
+        let invalid = |e: &dyn std::fmt::Display| Error::SigningError(e.to_string());
+
+        let (chain, key) = if certificate.trim_ascii_start().starts_with(b"-----BEGIN") {
+            let chain = Certificate::load_pem_chain(certificate).map_err(|e| invalid(&e))?;
+            let key = key.ok_or_else(|| invalid(&"the private key is missing"))?;
+            (chain, parse_pem_key(key, password)?)
+        } else {
+            let store = KeyStore::from_pkcs12(certificate, password.unwrap_or_default())
+                .map_err(|e| invalid(&e))?;
+            let (_, keychain) = store
+                .private_key_chain()
+                .ok_or_else(|| invalid(&"the PKCS#12 file has no private key"))?;
+            let chain = keychain
+                .chain()
+                .iter()
+                .map(|c| Certificate::from_der(c.as_der()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(&e))?;
+            (chain, parse_pkcs8_key(keychain.key())?)
+        };
+
+        let certificate = chain
+            .first()
+            .ok_or_else(|| invalid(&"the certificate is missing"))?;
+        let public_key = match &key {
+            Key::Rsa(key) => key.verifying_key().to_public_key_der(),
+            Key::P256(key) => key.verifying_key().to_public_key_der(),
+        }
+        .map_err(|e| invalid(&e))?;
+        let certificate_key = certificate
+            .tbs_certificate
+            .subject_public_key_info
+            .to_der()
+            .map_err(|e| invalid(&e))?;
+        if public_key.as_bytes() != certificate_key {
+            return Err(invalid(&"the private key doesn't match the certificate"));
+        }
+
+        Ok(Self {
+            credentials: Some(Arc::new(Credentials { chain, key })),
+        })
+    }
+
+    /// Save the document, signed if signing is configured
+    ///
+    /// An invisible signature field is added to the first page and the whole
+    /// file is signed with a detached CMS signature as in PAdES.
+    pub fn sign(
+        &self,
+        mut document: Document,
+        signed_at: OffsetDateTime,
+    ) -> Result<Vec<u8>, Error> {
+        let Some(credentials) = &self.credentials else {
+            return crate::merge::save(document);
+        };
+
+        let signature_id = add_signature_field(&mut document, signed_at)?;
+        let mut pdf = crate::merge::save(document)?;
+        let missing = || Error::SigningError("the signature placeholder is missing".into());
+
+        // NOTE: the attachments may contain the placeholders too, so they are
+        // only searched from the signature object the writer put in the xref
+        let offset = match Document::load_mem(&pdf)
+            .map_err(|e| Error::SigningError(e.to_string()))?
+            .reference_table
+            .get(signature_id.0)
+        {
+            Some(&XrefEntry::Normal { offset, .. }) => offset as usize,
+            _ => return Err(missing()),
+        };
+        let object = offset..offset + find(&pdf[offset..], b"endobj").ok_or_else(missing)?;
+        let placeholder = format!(
+            "[0 {BYTE_RANGE_PLACEHOLDER} {BYTE_RANGE_PLACEHOLDER} {BYTE_RANGE_PLACEHOLDER}]"
+        );
+        let byte_range = object.start
+            + find(&pdf[object.clone()], placeholder.as_bytes()).ok_or_else(missing)?;
+        let contents = object.start
+            + find(&pdf[object], b"/Contents<0000000000000000").ok_or_else(missing)?
+            + 9;
+        let end = contents + 2 * SIGNATURE_SIZE + 2;
+
+        let range = format!("[0 {contents} {end} {}]", pdf.len() - end);
+        pdf[byte_range..byte_range + placeholder.len()]
+            .copy_from_slice(format!("{range:<width$}", width = placeholder.len()).as_bytes());
+
+        let mut digest = Sha256::new();
+        digest.update(&pdf[..contents]);
+        digest.update(&pdf[end..]);
+        let signature = credentials
+            .cms(&digest.finalize())
+            .map_err(|e| Error::SigningError(e.to_string()))?;
+        if signature.len() > SIGNATURE_SIZE {
+            return Err(Error::SigningError(format!(
+                "the signature doesn't fit in {SIGNATURE_SIZE} bytes"
+            )));
+        }
+        pdf[contents + 1..contents + 1 + 2 * signature.len()]
+            .copy_from_slice(hex::encode_upper(signature).as_bytes());
+
+        Ok(pdf)
+    }
+
+    /// Check the signatures of a PDF
+    pub fn verify(&self, pdf: &[u8]) -> Result<Vec<Verification>, lopdf::Error> {
+        let document = Document::load_mem(pdf)?;
+
+        Ok(document
+            .objects
+            .values()
+            .filter_map(|o| o.as_dict().ok())
+            .filter(|d| d.has(b"ByteRange") && d.has(b"Contents"))
+            .map(|signature| {
+                let signed_at = signature
+                    .get(b"M")
+                    .and_then(Object::as_str)
+                    .ok()
+                    .and_then(parse_pdf_date);
+                let byte_range = signature
+                    .get(b"ByteRange")
+                    .and_then(Object::as_array)
+                    .map(|r| r.iter().filter_map(|i| i.as_i64().ok()).collect::<Vec<_>>())
+                    .unwrap_or_default();
+
+                match check(pdf, &byte_range, signature) {
+                    Ok(certificate) => Verification {
+                        signer: Some(certificate.tbs_certificate.subject.to_string()),
+                        signed_at,
+                        valid: true,
+                        covers_document: byte_range[0] == 0
+                            && byte_range[2].checked_add(byte_range[3])
+                                == i64::try_from(pdf.len()).ok(),
+                        trusted: self
+                            .credentials
+                            .as_ref()
+                            .is_some_and(|c| c.chain[0] == certificate),
+                        error: None,
+                    },
+                    Err(error) => Verification {
+                        signer: None,
+                        signed_at,
+                        valid: false,
+                        covers_document: false,
+                        trusted: false,
+                        error: Some(error),
+                    },
+                }
+            })
+            .collect())
+    }
+}
+
+impl Credentials {
+    /// Create a detached CMS signature of a SHA-256 digest
+    fn cms(&self, digest: &[u8]) -> Result<Vec<u8>, cms::builder::Error> {
+        let certificate = &self.chain[0];
+        let content = EncapsulatedContentInfo {
+            econtent_type: ID_DATA,
+            econtent: None,
+        };
+        let digest_algorithm = AlgorithmIdentifierOwned {
+            oid: ID_SHA_256,
+            parameters: None,
+        };
+
+        let mut builder = SignedDataBuilder::new(&content);
+        builder.add_digest_algorithm(digest_algorithm.clone())?;
+        for certificate in &self.chain {
+            builder.add_certificate(CertificateChoices::Certificate(certificate.clone()))?;
+        }
+
+        // NOTE: the ESS signing-certificate-v2 attribute required by PAdES is
+        // SigningCertificateV2 { certs: [ESSCertIDv2 { certHash }] }, the hash
+        // algorithm defaults to SHA-256 and is left out, see RFC 5035
+        let cert_hash = OctetString::new(Sha256::digest(certificate.to_der()?).to_vec())?;
+        let signing_certificate = vec![vec![vec![cert_hash]]];
+        let attribute = x509_cert::attr::Attribute {
+            oid: ID_SIGNING_CERTIFICATE_V2,
+            values: SetOfVec::try_from(vec![Any::encode_from(&signing_certificate)?])?,
+        };
+        let sid = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: certificate.tbs_certificate.issuer.clone(),
+            serial_number: certificate.tbs_certificate.serial_number.clone(),
+        });
+
+        match &self.key {
+            Key::Rsa(key) => add_signer::<_, rsa::pkcs1v15::Signature>(
+                &mut builder,
+                &**key,
+                sid,
+                digest_algorithm,
+                &content,
+                digest,
+                attribute,
+            )?,
+            Key::P256(key) => add_signer::<_, p256::ecdsa::DerSignature>(
+                &mut builder,
+                key,
+                sid,
+                digest_algorithm,
+                &content,
+                digest,
+                attribute,
+            )?,
+        }
+
+        Ok(builder.build()?.to_der()?)
+    }
+}
+
+fn add_signer<S, Signature>(
+    builder: &mut SignedDataBuilder,
+    key: &S,
+    sid: SignerIdentifier,
+    digest_algorithm: AlgorithmIdentifierOwned,
+    content: &EncapsulatedContentInfo,
+    digest: &[u8],
+    attribute: x509_cert::attr::Attribute,
+) -> Result<(), cms::builder::Error>
+where
+    S: Keypair + DynSignatureAlgorithmIdentifier + rsa::signature::Signer<Signature>,
+    Signature: SignatureBitStringEncoding,
+{
+    let mut signer = SignerInfoBuilder::new(key, sid, digest_algorithm, content, Some(digest))?;
+    signer.add_signed_attribute(attribute)?;
+    builder.add_signer_info::<S, Signature>(signer)?;
+    Ok(())
+}
+
+/// Check a signature against the signed bytes, returns the signing certificate
+fn check(pdf: &[u8], byte_range: &[i64], signature: &Dictionary) -> Result<Certificate, String> {
+    let range = |start: i64, length: i64| {
+        let start = usize::try_from(start).ok()?;
+        let end = start.checked_add(usize::try_from(length).ok()?)?;
+        (end <= pdf.len()).then_some(start..end)
+    };
+    let ranges = match byte_range {
+        &[a, b, c, d] => range(a, b).zip(range(c, d)),
+        _ => None,
+    }
+    .map(|(first, second)| [first, second])
+    .ok_or("the byte range of the signature is invalid")?;
+    let contents = signature
+        .get(b"Contents")
+        .and_then(Object::as_str)
+        .map_err(|_| "the signature has no contents")?;
+
+    let invalid = |e: der::Error| format!("the signature is invalid: {e}");
+    // NOTE: the contents are padded with zeros after the signature
+    let content_info =
+        ContentInfo::decode(&mut SliceReader::new(contents).map_err(invalid)?).map_err(invalid)?;
+    let signed_data = content_info
+        .content
+        .decode_as::<SignedData>()
+        .map_err(invalid)?;
+    let signer = signed_data
+        .signer_infos
+        .0
+        .get(0)
+        .ok_or("the signature has no signers")?;
+
+    let SignerIdentifier::IssuerAndSerialNumber(sid) = &signer.sid else {
+        return Err("the signer is identified by a key identifier".into());
+    };
+    let certificate = signed_data
+        .certificates
+        .iter()
+        .flat_map(|c| c.0.iter())
+        .find_map(|c| match c {
+            CertificateChoices::Certificate(c)
+                if c.tbs_certificate.issuer == sid.issuer
+                    && c.tbs_certificate.serial_number == sid.serial_number =>
+            {
+                Some(c.clone())
+            }
+            _ => None,
+        })
+        .ok_or("the signing certificate is missing")?;
+
+    if signer.digest_alg.oid != ID_SHA_256 {
+        return Err("the digest algorithm isn't supported".into());
+    }
+    let mut digest = Sha256::new();
+    for range in ranges {
+        digest.update(&pdf[range]);
+    }
+
+    let signed_attributes = signer
+        .signed_attrs
+        .as_ref()
+        .ok_or("the signature has no signed attributes")?;
+    let message_digest = signed_attributes
+        .iter()
+        .find(|a| a.oid == ID_MESSAGE_DIGEST)
+        .and_then(|a| a.values.get(0))
+        .and_then(|v| v.decode_as::<OctetString>().ok())
+        .ok_or("the signature has no message digest")?;
+    if message_digest.as_bytes() != digest.finalize().as_slice() {
+        return Err("the document has been modified after signing".into());
+    }
+
+    let signed = signed_attributes.to_der().map_err(invalid)?;
+    let public_key = &certificate.tbs_certificate.subject_public_key_info;
+    let verified = match signer.signature_algorithm.oid {
+        RSA_ENCRYPTION | SHA_256_WITH_RSA_ENCRYPTION => {
+            let key = rsa::RsaPublicKey::try_from(public_key.owned_to_ref())
+                .map_err(|e| e.to_string())?;
+            rsa::pkcs1v15::Signature::try_from(signer.signature.as_bytes())
+                .and_then(|s| rsa::pkcs1v15::VerifyingKey::<Sha256>::new(key).verify(&signed, &s))
+                .is_ok()
+        }
+        ECDSA_WITH_SHA_256 => {
+            let key = p256::ecdsa::VerifyingKey::try_from(public_key.owned_to_ref())
+                .map_err(|e| e.to_string())?;
+            p256::ecdsa::DerSignature::from_bytes(signer.signature.as_bytes())
+                .and_then(|s| key.verify(&signed, &s))
+                .is_ok()
+        }
+        _ => return Err("the signature algorithm isn't supported".into()),
+    };
+
+    match verified {
+        true => Ok(certificate),
+        false => Err("the signature doesn't match the signing certificate".into()),
+    }
+}
+
+/// Add an empty signature and its field, returns the id of the signature
+fn add_signature_field(
+    document: &mut Document,
+    signed_at: OffsetDateTime,
+) -> Result<ObjectId, Error> {
+    let placeholder = Object::Integer(BYTE_RANGE_PLACEHOLDER);
+    let signature_id = document.add_object(dictionary! {
+        "Type" => "Sig",
+        "Filter" => "Adobe.PPKLite",
+        "SubFilter" => "ETSI.CAdES.detached",
+        "ByteRange" => vec![0.into(), placeholder.clone(), placeholder.clone(), placeholder],
+        "Contents" => Object::String(vec![0; SIGNATURE_SIZE], StringFormat::Hexadecimal),
+        "M" => pdf_date(signed_at),
+    });
+    // NOTE: PDF/A requires an appearance for every widget, even an invisible one
+    let appearance_id = document.add_object(Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        },
+        Vec::new(),
+    ));
+
+    let page_id = *document
+        .get_pages()
+        .values()
+        .next()
+        .ok_or_else(|| Error::SigningError("the PDF has no pages".into()))?;
+    let field_id = document.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Sig",
+        "T" => Object::string_literal("allekirjoitus"),
+        "V" => signature_id,
+        "Rect" => vec![0.into(), 0.into(), 0.into(), 0.into()],
+        // NOTE: print and locked
+        "F" => 132,
+        "P" => page_id,
+        "AP" => dictionary! { "N" => appearance_id },
+    });
+    append(document, page_id, b"Annots", field_id)?;
+
+    let catalog_id = document.trailer.get(b"Root")?.as_reference()?;
+    let form_id = match document.get_dictionary(catalog_id)?.get(b"AcroForm") {
+        Ok(Object::Reference(id)) => *id,
+        Ok(Object::Dictionary(form)) => document.add_object(form.clone()),
+        _ => document.add_object(dictionary! { "Fields" => Vec::<Object>::new() }),
+    };
+    document
+        .get_dictionary_mut(catalog_id)?
+        .set("AcroForm", form_id);
+    append(document, form_id, b"Fields", field_id)?;
+    document.get_dictionary_mut(form_id)?.set("SigFlags", 3);
+
+    Ok(signature_id)
+}
+
+/// Append a reference to an array in a dictionary, which may be a reference itself
+fn append(document: &mut Document, id: ObjectId, key: &[u8], value: ObjectId) -> Result<(), Error> {
+    let array = match document.get_dictionary(id)?.get(key) {
+        Ok(Object::Reference(array_id)) => {
+            let array_id = *array_id;
+            document.get_object_mut(array_id)?.as_array_mut()?
+        }
+        _ => {
+            let dictionary = document.get_dictionary_mut(id)?;
+            if !matches!(dictionary.get(key), Ok(Object::Array(_))) {
+                dictionary.set(key.to_vec(), Vec::<Object>::new());
+            }
+            dictionary.get_mut(key)?.as_array_mut()?
+        }
+    };
+    array.push(Object::Reference(value));
+    Ok(())
+}
+
+fn parse_pem_key(key: &[u8], password: Option<&str>) -> Result<Key, Error> {
+    let invalid = |e: &dyn std::fmt::Display| Error::SigningError(e.to_string());
+    let (label, der) = der::pem::decode_vec(key).map_err(|e| invalid(&e))?;
+
+    match label {
+        "PRIVATE KEY" => parse_pkcs8_key(&der),
+        "ENCRYPTED PRIVATE KEY" => {
+            let info = pkcs8::EncryptedPrivateKeyInfo::try_from(der.as_slice())
+                .map_err(|e| invalid(&e))?;
+            let der = info
+                .decrypt(password.unwrap_or_default())
+                .map_err(|e| invalid(&e))?;
+            parse_pkcs8_key(der.as_bytes())
+        }
+        "RSA PRIVATE KEY" => Ok(Key::Rsa(Box::new(rsa::pkcs1v15::SigningKey::new(
+            rsa::RsaPrivateKey::from_pkcs1_der(&der).map_err(|e| invalid(&e))?,
+        )))),
+        "EC PRIVATE KEY" => Ok(Key::P256(
+            p256::SecretKey::from_sec1_der(&der)
+                .map_err(|e| invalid(&e))?
+                .into(),
+        )),
+        label => Err(invalid(&format!("unsupported private key {label}"))),
+    }
+}
+
+fn parse_pkcs8_key(der: &[u8]) -> Result<Key, Error> {
+    if let Ok(key) = rsa::RsaPrivateKey::from_pkcs8_der(der) {
+        return Ok(Key::Rsa(Box::new(rsa::pkcs1v15::SigningKey::new(key))));
+    }
+    p256::ecdsa::SigningKey::from_pkcs8_der(der)
+        .map(Key::P256)
+        .map_err(|_| Error::SigningError("only RSA and P-256 keys are supported".into()))
+}
+
+/// Parse a PDF date, e.g. `D:20241019120000Z` or `D:20241019150000+03'00'`
+fn parse_pdf_date(date: &[u8]) -> Option<OffsetDateTime> {
+    let date = std::str::from_utf8(date).ok()?.strip_prefix("D:")?;
+    let number = |range: std::ops::Range<usize>| date.get(range)?.parse::<u16>().ok();
+
+    let date_time = PrimitiveDateTime::new(
+        Date::from_calendar_date(
+            number(0..4)?.into(),
+            Month::try_from(number(4..6).unwrap_or(1) as u8).ok()?,
+            number(6..8).unwrap_or(1) as u8,
+        )
+        .ok()?,
+        Time::from_hms(
+            number(8..10).unwrap_or(0) as u8,
+            number(10..12).unwrap_or(0) as u8,
+            number(12..14).unwrap_or(0) as u8,
+        )
+        .ok()?,
+    );
+    let offset = match date.get(14..15) {
+        Some(sign @ ("+" | "-")) => {
+            let seconds = number(15..17)? as i32 * 3600 + number(18..20).unwrap_or(0) as i32 * 60;
+            UtcOffset::from_whole_seconds(if sign == "-" { -seconds } else { seconds }).ok()?
+        }
+        _ => UtcOffset::UTC,
+    };
+    Some(date_time.assume_offset(offset))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Signer
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.signer)
+    }
+}
@@ -1,5 +1,13 @@
+use crate::audit::AuditLog;
+use crate::auth::AdminToken;
+use crate::idempotency::Idempotency;
+use crate::jobs::{Jobs, RenderPool};
 #[cfg(feature = "email")]
 use crate::mailgun::MailgunClient;
+use crate::signing::Signer;
+#[cfg(feature = "s3")]
+use crate::storage::S3BlobStore;
+use crate::storage::{BlobBackend, Keyring, Storage};
 
 use axum::extract::FromRef;
 
@@ -7,15 +15,46 @@ use axum::extract::FromRef;
 pub struct State {
     #[cfg(feature = "email")]
     pub mailgun_client: MailgunClient,
+    pub storage: Storage,
+    pub audit: AuditLog,
+    pub signer: Signer,
+    pub admin_token: AdminToken,
+    pub idempotency: Idempotency,
+    pub render_pool: RenderPool,
+    pub jobs: Jobs,
     pub for_garde: (),
 }
 
 pub async fn new() -> State {
     dotenv::dotenv().ok();
 
+    let mut storage = Storage::new(crate::CONFIG.data_dir.clone());
+    match Keyring::from_config().expect("Failed to load the encryption keys") {
+        Some(keyring) => storage = storage.with_keyring(keyring),
+        None => warn!("No encryption keys are configured, the stored data isn't encrypted"),
+    }
+    match crate::CONFIG.blob_store {
+        BlobBackend::Fs => {}
+        #[cfg(feature = "s3")]
+        BlobBackend::S3 => {
+            storage = storage.with_blob_store(
+                S3BlobStore::from_config(&crate::CONFIG.s3)
+                    .expect("Failed to configure the S3 blob store"),
+            )
+        }
+    }
+
+    let idempotency = Idempotency::from_config(&storage);
     State {
         #[cfg(feature = "email")]
         mailgun_client: MailgunClient::from(crate::CONFIG.mailgun.clone()),
+        storage,
+        audit: AuditLog::new(crate::CONFIG.data_dir.clone()),
+        signer: Signer::from_config().expect("Failed to load the signing certificate"),
+        admin_token: AdminToken::new(crate::CONFIG.admin_token.as_deref()),
+        idempotency,
+        render_pool: RenderPool::from_config(),
+        jobs: Jobs::default(),
         for_garde: (),
     }
 }
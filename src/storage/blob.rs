@@ -0,0 +1,110 @@
+use crate::error::Error;
+
+use axum::async_trait;
+use std::path::{Path, PathBuf};
+
+/// The backends files can be stored in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum BlobBackend {
+    /// Files in `<data dir>/blobs/`
+    Fs,
+    /// A bucket of an S3-compatible service
+    #[cfg(feature = "s3")]
+    S3,
+}
+
+/// Storage for the contents of files, addressed by the hex encoded
+/// SHA-256 hash of the contents so that identical files are stored once
+#[async_trait]
+pub trait BlobStore: std::fmt::Debug + Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), Error>;
+    /// Fails with [`Error::NotFound`] if there is no such blob
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error>;
+    async fn exists(&self, key: &str) -> Result<bool, Error>;
+    /// Deleting a blob that doesn't exist is not an error
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+    /// The keys of every blob
+    async fn list(&self) -> Result<Vec<String>, Error>;
+}
+
+/// Check that a key is a hash, so that it is safe to use in paths and urls
+pub(super) fn check_key(key: &str) -> Result<(), Error> {
+    match key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+        true => Ok(()),
+        false => Err(Error::BlobStoreError(format!("invalid blob key {key:?}"))),
+    }
+}
+
+/// Blobs stored as files in `<root>/<first two characters of the key>/<key>`
+#[derive(Clone, Debug)]
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        check_key(key)?;
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), Error> {
+        Ok(super::write_atomic(&self.path(key)?, &data).await?)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, Error> {
+        Ok(tokio::fs::try_exists(self.path(key)?).await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        Ok(super::remove_file(&self.path(key)?).await?)
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let Some(mut dirs) = read_dir(&self.root).await? else {
+            return Ok(keys);
+        };
+        while let Some(dir) = dirs.next_entry().await? {
+            let Some(mut files) = read_dir(&dir.path()).await? else {
+                continue;
+            };
+            while let Some(file) = files.next_entry().await? {
+                let name = file.file_name().to_string_lossy().into_owned();
+                if check_key(&name).is_ok() {
+                    keys.push(name);
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+async fn read_dir(dir: &Path) -> std::io::Result<Option<tokio::fs::ReadDir>> {
+    match tokio::fs::read_dir(dir).await {
+        Ok(entries) => Ok(Some(entries)),
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,168 @@
+use crate::error::Error;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use sha2::{Digest, Sha256};
+
+/// Marks encrypted blobs, followed by the key id and the nonce
+const MAGIC: &[u8; 4] = b"LGE\x01";
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + KEY_ID_LEN + NONCE_LEN;
+/// Prefix of encrypted strings in the stored JSON
+const FIELD_PREFIX: &str = "enc:";
+
+/// AES-256-GCM keys for encrypting stored data
+///
+/// The first key is used for encrypting and every key for decrypting, so keys
+/// are rotated by adding a new key first and re-encrypting the stored data
+/// before removing the old one. Keys are identified by a hash of the key.
+#[derive(Clone)]
+pub struct Keyring {
+    keys: Vec<([u8; KEY_ID_LEN], Aes256Gcm)>,
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.keys.iter().map(|(id, _)| hex::encode(id)))
+            .finish()
+    }
+}
+
+impl Keyring {
+    pub fn new(keys: &[[u8; 32]]) -> Result<Self, Error> {
+        if keys.is_empty() {
+            return Err(Error::EncryptionError("no encryption keys".into()));
+        }
+
+        Ok(Self {
+            keys: keys
+                .iter()
+                .map(|key| {
+                    let mut id = [0; KEY_ID_LEN];
+                    id.copy_from_slice(&Sha256::digest(key)[..KEY_ID_LEN]);
+                    (id, Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)))
+                })
+                .collect(),
+        })
+    }
+
+    /// A keyring with a new random key
+    pub fn generate() -> Self {
+        Self::new(&[Self::generate_key()]).expect("bug: keyring without keys")
+    }
+
+    pub fn generate_key() -> [u8; 32] {
+        Aes256Gcm::generate_key(OsRng).into()
+    }
+
+    /// Parse hex encoded keys separated by commas or whitespace, lines
+    /// starting with `#` are comments
+    pub fn parse(keys: &str) -> Result<Self, Error> {
+        let keys = keys
+            .lines()
+            .filter(|l| !l.trim_start().starts_with('#'))
+            .flat_map(|l| l.split([',', ' ', '\t']))
+            .filter(|k| !k.is_empty())
+            .map(|k| {
+                hex::decode(k)
+                    .ok()
+                    .and_then(|k| <[u8; 32]>::try_from(k).ok())
+                    .ok_or_else(|| {
+                        Error::EncryptionError("keys must be 32 bytes encoded as hex".into())
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Self::new(&keys)
+    }
+
+    /// The keyring configured with `ENCRYPTION_KEYS` or `ENCRYPTION_KEY_FILE`, if any
+    pub fn from_config() -> Result<Option<Self>, Error> {
+        if let Some(keys) = &crate::CONFIG.encryption_keys {
+            return Self::parse(keys).map(Some);
+        }
+        match &crate::CONFIG.encryption_key_file {
+            Some(path) => Self::parse(&std::fs::read_to_string(path)?).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Encrypt with the current key
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let (id, cipher) = &self.keys[0];
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+
+        let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(id);
+        sealed.extend_from_slice(&nonce);
+        // NOTE: the header is authenticated as well
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &sealed,
+                },
+            )
+            .expect("bug: encryption failed");
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Decrypt data encrypted with any of the keys, data that isn't
+    /// encrypted is returned as is
+    pub fn open(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        if !is_sealed(&data) {
+            return Ok(data);
+        }
+
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let id = &header[MAGIC.len()..MAGIC.len() + KEY_ID_LEN];
+        let nonce = Nonce::from_slice(&header[MAGIC.len() + KEY_ID_LEN..]);
+        let (_, cipher) = self
+            .keys
+            .iter()
+            .find(|(key_id, _)| key_id == id)
+            .ok_or_else(|| {
+                Error::EncryptionError(format!("unknown encryption key {}", hex::encode(id)))
+            })?;
+
+        cipher
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| Error::EncryptionError("the data has been modified".into()))
+    }
+
+    /// Encrypt a string of the stored JSON, empty strings are kept empty
+    pub fn seal_field(&self, field: &mut String) {
+        if !field.is_empty() {
+            *field = format!("{FIELD_PREFIX}{}", hex::encode(self.seal(field.as_bytes())));
+        }
+    }
+
+    pub fn open_field(&self, field: &mut String) -> Result<(), Error> {
+        if let Some(sealed) = field.strip_prefix(FIELD_PREFIX) {
+            let sealed = hex::decode(sealed)
+                .map_err(|_| Error::EncryptionError("invalid encrypted field".into()))?;
+            *field = String::from_utf8(self.open(sealed)?)
+                .map_err(|_| Error::EncryptionError("invalid encrypted field".into()))?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn is_sealed(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && data.starts_with(MAGIC)
+}
+
+pub(super) fn is_sealed_field(field: &str) -> bool {
+    field.starts_with(FIELD_PREFIX)
+}
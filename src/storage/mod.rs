@@ -0,0 +1,819 @@
+use crate::api::invoices::{Invoice, InvoiceAttachment};
+use crate::attachments::Format;
+use crate::error::Error;
+use crate::idempotency::Outcome;
+use crate::receipt::Warning;
+use crate::state::State;
+
+use axum::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    http::request::Parts,
+};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::NamedTempFile;
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+mod blob;
+mod crypto;
+#[cfg(feature = "s3")]
+mod s3;
+
+pub use blob::{BlobBackend, BlobStore, FsBlobStore};
+pub use crypto::Keyring;
+#[cfg(feature = "s3")]
+pub use s3::S3BlobStore;
+
+/// An invoice that has been submitted and stored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredInvoice {
+    pub id: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(flatten)]
+    pub invoice: Invoice,
+    #[serde(default)]
+    pub delivery: Delivery,
+    /// Possible problems noticed when the invoice was submitted
+    #[serde(default)]
+    pub warnings: Vec<Warning>,
+    /// The attachments kept for rendering the invoice again
+    #[serde(default)]
+    pub files: Vec<StoredFile>,
+    /// The generated PDFs, the first one is the PDF sent when the invoice was submitted
+    #[serde(default)]
+    pub versions: Vec<Version>,
+    /// When the personal data of the recipient was removed
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub pseudonymized_at: Option<OffsetDateTime>,
+    /// Identifies likely duplicates, see [`crate::duplicates::fingerprint`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+}
+
+impl StoredInvoice {
+    pub fn new(invoice: Invoice) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            created_at: OffsetDateTime::now_utc(),
+            invoice,
+            delivery: Delivery::default(),
+            warnings: Vec::new(),
+            files: Vec::new(),
+            versions: Vec::new(),
+            pseudonymized_at: None,
+            fingerprint: None,
+        }
+    }
+
+    /// Replace the personal data of the recipient with a pseudonym,
+    /// keeping what is needed for bookkeeping
+    pub fn pseudonymize(&mut self, pseudonym: &str) {
+        let invoice = &mut self.invoice;
+        invoice.recipient_name = pseudonym.into();
+        invoice.recipient_email = pseudonym.into();
+        invoice.address.street.clear();
+        invoice.address.city.clear();
+        invoice.address.zip.clear();
+        invoice.bank_account_number.clear();
+        invoice.phone_number.clear();
+        // NOTE: the free text may name people as well
+        invoice.subject.clear();
+        invoice.description.clear();
+        invoice.attachment_descriptions.clear();
+        self.warnings.clear();
+        self.delivery.recipients.clear();
+        for event in &mut self.delivery.events {
+            event.recipient.clear();
+            event.reason = None;
+        }
+        // NOTE: the attachments are removed with the PDFs
+        self.files.clear();
+        self.fingerprint = None;
+        self.pseudonymized_at = Some(OffsetDateTime::now_utc());
+    }
+}
+
+/// A prepared attachment of a stored invoice
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoredFile {
+    pub filename: String,
+    pub format: Format,
+    /// Whether the uncropped image is stored as well
+    pub original: bool,
+    /// The key of the file in the blob store, files stored before the blob
+    /// store was introduced are in the directory of the invoice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_blob: Option<String>,
+}
+
+impl StoredFile {
+    /// The keys of the file and the uncropped image in the blob store
+    pub fn blobs(&self) -> impl Iterator<Item = &String> {
+        self.blob.iter().chain(&self.original_blob)
+    }
+}
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// Approval of an invoice in a board meeting
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Approval {
+    /// The date of the meeting
+    #[serde(with = "iso_date")]
+    pub date: time::Date,
+    /// The number of the meeting in the year of the date
+    pub meeting: String,
+    pub approver: String,
+    /// The account the invoice is booked to
+    pub account: String,
+    /// When the approval was recorded
+    #[serde(with = "time::serde::rfc3339")]
+    pub approved_at: OffsetDateTime,
+}
+
+/// A generated PDF of a stored invoice
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Version {
+    /// Versions are numbered from 1
+    pub number: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// Hex encoded SHA-256 digest of the PDF, which is also its key in the blob store
+    pub sha256: String,
+    pub approval: Option<Approval>,
+}
+
+/// Delivery status of the email sent for an invoice
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Delivery {
+    /// The message id assigned by mailgun, without angle brackets
+    pub message_id: Option<String>,
+    /// The latest status of each recipient
+    pub recipients: BTreeMap<String, DeliveryStatus>,
+    /// Every delivery event received for the message
+    pub events: Vec<DeliveryEvent>,
+}
+
+impl Delivery {
+    pub fn record(&mut self, event: DeliveryEvent) {
+        let is_latest = self
+            .events
+            .iter()
+            .filter(|e| e.recipient == event.recipient)
+            .all(|e| e.timestamp <= event.timestamp);
+
+        if is_latest {
+            self.recipients
+                .insert(event.recipient.clone(), event.status);
+        }
+        self.events.push(event);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Delivered,
+    /// Temporary failure, the delivery will be retried
+    Failed,
+    /// Permanent failure
+    Bounced,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeliveryEvent {
+    pub status: DeliveryStatus,
+    pub recipient: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub reason: Option<String>,
+}
+
+/// An incomplete invoice created from an inbound email
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Draft {
+    pub id: Uuid,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    pub recipient_name: String,
+    pub recipient_email: String,
+    pub subject: String,
+    pub description: String,
+    /// Filenames of the attachments, in the order they are added to the invoice
+    pub attachments: Vec<String>,
+    /// Hex encoded SHA-256 digest of the secret token in the link sent to the
+    /// sender, which is needed for viewing the draft without the admin token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_hash: Option<String>,
+}
+
+impl Draft {
+    /// Whether the token is the one in the link sent to the sender
+    pub fn accepts(&self, token: &str) -> bool {
+        self.token_hash
+            .as_deref()
+            .is_some_and(|hash| hash == hex::encode(Sha256::digest(token)))
+    }
+}
+
+/// Filesystem backed storage for submitted invoices
+///
+/// Every invoice is stored as a JSON file in `<root>/invoices/` and
+/// mailgun message ids are indexed in `<root>/messages/`.
+/// The attachments and generated PDFs of invoices are stored in a
+/// [`BlobStore`], by default in `<root>/blobs/`. Identical files, such as the
+/// same receipt attached to several invoices, are stored once and removed
+/// once no invoice refers to them.
+/// Drafts are stored in `<root>/drafts/` with their attachments in
+/// `<root>/drafts/<id>/`.
+///
+/// With a keyring the attachments, the PDFs and the address, phone number
+/// and bank account of invoices are encrypted. Data stored before the
+/// encryption was enabled is read as is.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    root: PathBuf,
+    lock: Arc<Mutex<()>>,
+    keyring: Option<Arc<Keyring>>,
+    blobs: Arc<dyn BlobStore>,
+}
+
+impl Storage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            blobs: Arc::new(FsBlobStore::new(root.join("blobs"))),
+            root,
+            lock: Arc::new(Mutex::new(())),
+            keyring: None,
+        }
+    }
+
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(Arc::new(keyring));
+        self
+    }
+
+    pub fn with_blob_store(mut self, blobs: impl BlobStore + 'static) -> Self {
+        self.blobs = Arc::new(blobs);
+        self
+    }
+
+    fn invoice_path(&self, id: Uuid) -> PathBuf {
+        self.root.join("invoices").join(format!("{id}.json"))
+    }
+
+    fn invoice_dir(&self, id: Uuid) -> PathBuf {
+        self.root.join("invoices").join(id.to_string())
+    }
+
+    fn version_path(&self, id: Uuid, number: u32) -> PathBuf {
+        self.invoice_dir(id).join(format!("{number}.pdf"))
+    }
+
+    fn message_path(&self, message_id: &str) -> PathBuf {
+        // Message ids may contain characters that are not safe in filenames
+        let digest = Sha256::digest(message_id.as_bytes());
+        self.root.join("messages").join(hex::encode(digest))
+    }
+
+    fn outcome_path(&self, key: &str) -> PathBuf {
+        // Idempotency keys are chosen by the clients
+        let digest = Sha256::digest(key.as_bytes());
+        self.root
+            .join("idempotency")
+            .join(format!("{}.json", hex::encode(digest)))
+    }
+
+    pub async fn insert(&self, invoice: &StoredInvoice) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        self.write(invoice).await
+    }
+
+    pub async fn get(&self, id: Uuid) -> Result<StoredInvoice, Error> {
+        let path = self.invoice_path(id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => self.decode(&bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<StoredInvoice, Error> {
+        let mut invoice: StoredInvoice = serde_json::from_slice(bytes)?;
+        for field in sensitive_fields(&mut invoice) {
+            match &self.keyring {
+                Some(keyring) => keyring.open_field(field)?,
+                None if crypto::is_sealed_field(field) => {
+                    return Err(Error::EncryptionError(
+                        "the invoice is encrypted but no keys are configured".into(),
+                    ))
+                }
+                None => {}
+            }
+        }
+        Ok(invoice)
+    }
+
+    fn encode(&self, invoice: &StoredInvoice) -> Result<Vec<u8>, Error> {
+        let mut invoice = invoice.clone();
+        if let Some(keyring) = &self.keyring {
+            sensitive_fields(&mut invoice)
+                .into_iter()
+                .for_each(|field| keyring.seal_field(field));
+        }
+        Ok(serde_json::to_vec_pretty(&invoice)?)
+    }
+
+    fn seal(&self, contents: &[u8]) -> Vec<u8> {
+        match &self.keyring {
+            Some(keyring) => keyring.seal(contents),
+            None => contents.to_vec(),
+        }
+    }
+
+    fn open(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match &self.keyring {
+            Some(keyring) => keyring.open(data),
+            None if crypto::is_sealed(&data) => Err(Error::EncryptionError(
+                "the file is encrypted but no keys are configured".into(),
+            )),
+            None => Ok(data),
+        }
+    }
+
+    async fn write_blob(&self, path: &Path, contents: &[u8]) -> Result<(), Error> {
+        Ok(write_atomic(path, &self.seal(contents)).await?)
+    }
+
+    async fn read_blob(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        match tokio::fs::read(path).await {
+            Ok(data) => self.open(data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn copy_blob(&self, from: &Path, to: &Path) -> Result<(), Error> {
+        self.write_blob(to, &tokio::fs::read(from).await?).await
+    }
+
+    /// Store a file in the blob store, unless an identical file is already stored
+    ///
+    /// Must be called with the lock held, so that the blob isn't released
+    /// before the invoice referring to it is written.
+    async fn put_blob(&self, path: &Path) -> Result<String, Error> {
+        let contents = tokio::fs::read(path).await?;
+        let key = hex::encode(Sha256::digest(&contents));
+        if !self.blobs.exists(&key).await? {
+            self.blobs.put(&key, self.seal(&contents)).await?;
+        }
+        Ok(key)
+    }
+
+    /// Write the contents of a stored file into a temporary file
+    async fn temp_file(&self, contents: Vec<u8>) -> Result<NamedTempFile, Error> {
+        let file = NamedTempFile::new()?;
+        tokio::fs::write(file.path(), contents).await?;
+        Ok(file)
+    }
+
+    /// Remove the blobs no invoice other than `id` refers to
+    ///
+    /// Must be called with the lock held.
+    async fn release(&self, id: Uuid, keys: BTreeSet<String>) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut referenced = BTreeSet::new();
+        for invoice in self.list().await?.iter().filter(|i| i.id != id) {
+            referenced.extend(invoice.files.iter().flat_map(StoredFile::blobs).cloned());
+            referenced.extend(invoice.versions.iter().map(|v| v.sha256.clone()));
+        }
+        for key in keys.difference(&referenced) {
+            self.blobs.delete(key).await?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt everything with the current key
+    ///
+    /// Run after adding a new key, or enabling the encryption, and before
+    /// removing the old key. Returns the number of rewritten files.
+    pub async fn reencrypt(&self) -> Result<usize, Error> {
+        let _guard = self.lock.lock().await;
+        let mut count = 0;
+        for invoice in self.list().await? {
+            self.write(&invoice).await?;
+            count += 1;
+        }
+        for dir in ["invoices", "drafts"] {
+            for path in blob_paths(&self.root.join(dir)).await? {
+                let data = self.read_blob(&path).await?;
+                self.write_blob(&path, &data).await?;
+                count += 1;
+            }
+        }
+        for key in self.blobs.list().await? {
+            let data = self.open(self.blobs.get(&key).await?)?;
+            self.blobs.put(&key, self.seal(&data)).await?;
+            count += 1;
+        }
+        let mut outcomes = match tokio::fs::read_dir(self.root.join("idempotency")).await {
+            Ok(entries) => Some(entries),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = match &mut outcomes {
+            Some(entries) => entries.next_entry().await?,
+            None => None,
+        } {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let modified = entry.metadata().await?.modified()?;
+            let data = self.read_blob(&path).await?;
+            self.write_blob(&path, &data).await?;
+            // NOTE: the outcomes are purged by their modification time
+            tokio::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .await?
+                .into_std()
+                .await
+                .set_modified(modified)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Atomically read, modify and write back a stored invoice
+    pub async fn update(
+        &self,
+        id: Uuid,
+        f: impl FnOnce(&mut StoredInvoice),
+    ) -> Result<StoredInvoice, Error> {
+        let _guard = self.lock.lock().await;
+        let mut invoice = self.get(id).await?;
+        f(&mut invoice);
+        self.write(&invoice).await?;
+        Ok(invoice)
+    }
+
+    /// Store an invoice with its attachments so that it can be rendered again
+    pub async fn insert_with_files(
+        &self,
+        invoice: &mut StoredInvoice,
+        attachments: &[InvoiceAttachment],
+    ) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let mut files = Vec::with_capacity(attachments.len());
+        for attachment in attachments {
+            let original_blob = match &attachment.original {
+                Some(original) => Some(self.put_blob(original.path()).await?),
+                None => None,
+            };
+            files.push(StoredFile {
+                filename: attachment.filename.clone(),
+                format: attachment.format,
+                original: original_blob.is_some(),
+                blob: Some(self.put_blob(attachment.path()).await?),
+                original_blob,
+            });
+        }
+        invoice.files = files;
+        self.write(invoice).await
+    }
+
+    /// The stored attachments of an invoice, prepared like when it was submitted
+    pub async fn files(&self, invoice: &StoredInvoice) -> Result<Vec<InvoiceAttachment>, Error> {
+        let dir = self.invoice_dir(invoice.id).join("attachments");
+
+        let mut attachments = Vec::with_capacity(invoice.files.len());
+        for (i, stored) in invoice.files.iter().enumerate() {
+            let file = match &stored.blob {
+                Some(key) => self.open(self.blobs.get(key).await?)?,
+                None => self.read_blob(&dir.join(i.to_string())).await?,
+            };
+            let file = Arc::new(self.temp_file(file).await?);
+            let original = match (&stored.original_blob, stored.original) {
+                (Some(key), _) => Some(self.open(self.blobs.get(key).await?)?),
+                (None, true) => Some(self.read_blob(&dir.join(format!("{i}.original"))).await?),
+                (None, false) => None,
+            };
+            let original = match original {
+                Some(original) => Some(Arc::new(self.temp_file(original).await?)),
+                None => None,
+            };
+            attachments.push(InvoiceAttachment {
+                filename: stored.filename.clone(),
+                size: file.as_file().metadata()?.len(),
+                pages: crate::attachments::pages(stored.format, file.path())?,
+                format: stored.format,
+                file,
+                original,
+            });
+        }
+        Ok(attachments)
+    }
+
+    /// Store a generated PDF as the next version of the invoice
+    pub async fn insert_version(
+        &self,
+        id: Uuid,
+        pdf: &[u8],
+        created_at: OffsetDateTime,
+        approval: Option<Approval>,
+    ) -> Result<Version, Error> {
+        let _guard = self.lock.lock().await;
+        let mut invoice = self.get(id).await?;
+        let version = Version {
+            number: invoice.versions.len() as u32 + 1,
+            created_at,
+            sha256: hex::encode(Sha256::digest(pdf)),
+            approval,
+        };
+        self.blobs.put(&version.sha256, self.seal(pdf)).await?;
+        invoice.versions.push(version.clone());
+        self.write(&invoice).await?;
+        Ok(version)
+    }
+
+    pub async fn get_version(&self, id: Uuid, number: u32) -> Result<Vec<u8>, Error> {
+        let invoice = self.get(id).await?;
+        let version = invoice
+            .versions
+            .iter()
+            .find(|v| v.number == number)
+            .ok_or(Error::NotFound)?;
+        // NOTE: PDFs stored before the blob store are in the directory of the invoice
+        match self.blobs.get(&version.sha256).await {
+            Ok(pdf) => self.open(pdf),
+            Err(Error::NotFound) => self.read_blob(&self.version_path(id, number)).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Every stored invoice, in no particular order
+    pub async fn list(&self) -> Result<Vec<StoredInvoice>, Error> {
+        read_json_dir(&self.root.join("invoices"))
+            .await?
+            .iter()
+            .map(|bytes| self.decode(bytes))
+            .collect()
+    }
+
+    /// Pseudonymize an invoice and remove its attachments and PDFs
+    pub async fn pseudonymize(&self, id: Uuid, pseudonym: &str) -> Result<StoredInvoice, Error> {
+        let _guard = self.lock.lock().await;
+        let mut invoice = self.get(id).await?;
+        let blobs = blob_keys(&invoice);
+        invoice.pseudonymize(pseudonym);
+        remove_dir(&self.invoice_dir(id)).await?;
+        self.write(&invoice).await?;
+        self.release(id, blobs).await?;
+        Ok(invoice)
+    }
+
+    /// Remove an invoice with its attachments and PDFs
+    pub async fn remove(&self, id: Uuid) -> Result<(), Error> {
+        let _guard = self.lock.lock().await;
+        let invoice = self.get(id).await?;
+        if let Some(message_id) = &invoice.delivery.message_id {
+            remove_file(&self.message_path(message_id)).await?;
+        }
+        remove_dir(&self.invoice_dir(id)).await?;
+        remove_file(&self.invoice_path(id)).await?;
+        self.release(id, blob_keys(&invoice)).await?;
+        Ok(())
+    }
+
+    pub async fn find_by_message_id(&self, message_id: &str) -> Result<Option<Uuid>, Error> {
+        match tokio::fs::read_to_string(self.message_path(message_id)).await {
+            Ok(id) => Ok(id.trim().parse().ok()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The outcome of the first request with an idempotency key
+    pub async fn get_outcome(&self, key: &str) -> Result<Option<Outcome>, Error> {
+        match self.read_blob(&self.outcome_path(key)).await {
+            Ok(outcome) => Ok(Some(serde_json::from_slice(&outcome)?)),
+            Err(Error::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn insert_outcome(&self, key: &str, outcome: &Outcome) -> Result<(), Error> {
+        self.write_blob(&self.outcome_path(key), &serde_json::to_vec(outcome)?)
+            .await
+    }
+
+    /// Remove the outcomes created before the time
+    pub async fn purge_outcomes(&self, before: OffsetDateTime) -> Result<(), Error> {
+        let mut entries = match tokio::fs::read_dir(self.root.join("idempotency")).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            // NOTE: judged by the modification time, so that the outcomes needn't be decrypted
+            let modified = OffsetDateTime::from(entry.metadata().await?.modified()?);
+            if modified < before {
+                remove_file(&entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draft_path(&self, id: Uuid) -> PathBuf {
+        self.root.join("drafts").join(format!("{id}.json"))
+    }
+
+    fn draft_attachment_dir(&self, id: Uuid) -> PathBuf {
+        self.root.join("drafts").join(id.to_string())
+    }
+
+    pub async fn insert_draft(
+        &self,
+        draft: &Draft,
+        attachments: &[InvoiceAttachment],
+    ) -> Result<(), Error> {
+        let dir = self.draft_attachment_dir(draft.id);
+        // NOTE: attachments are stored by index, the filenames come from the sender.
+        // Cropping is done again when the draft is loaded.
+        for (i, attachment) in attachments.iter().enumerate() {
+            self.copy_blob(attachment.original_path(), &dir.join(i.to_string()))
+                .await?;
+        }
+
+        write_atomic(
+            &self.draft_path(draft.id),
+            &serde_json::to_vec_pretty(draft)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Every stored draft, in no particular order
+    pub async fn list_drafts(&self) -> Result<Vec<Draft>, Error> {
+        read_json_dir(&self.root.join("drafts"))
+            .await?
+            .iter()
+            .map(|bytes| Ok(serde_json::from_slice(bytes)?))
+            .collect()
+    }
+
+    pub async fn get_draft(&self, id: Uuid) -> Result<Draft, Error> {
+        match tokio::fs::read(self.draft_path(id)).await {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(Error::NotFound),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn draft_attachments(&self, id: Uuid) -> Result<Vec<InvoiceAttachment>, Error> {
+        let draft = self.get_draft(id).await?;
+        let dir = self.draft_attachment_dir(id);
+
+        let mut attachments = Vec::with_capacity(draft.attachments.len());
+        for (i, filename) in draft.attachments.into_iter().enumerate() {
+            // NOTE: copied so that the draft can be removed once the invoice is sent
+            let file = self
+                .temp_file(self.read_blob(&dir.join(i.to_string())).await?)
+                .await?;
+            attachments.push(crate::attachments::prepare_blocking(filename, file, None).await?);
+        }
+        Ok(attachments)
+    }
+
+    pub async fn remove_draft(&self, id: Uuid) -> Result<(), Error> {
+        tokio::fs::remove_file(self.draft_path(id)).await?;
+        remove_dir(&self.draft_attachment_dir(id)).await?;
+        Ok(())
+    }
+
+    async fn write(&self, invoice: &StoredInvoice) -> Result<(), Error> {
+        write_atomic(&self.invoice_path(invoice.id), &self.encode(invoice)?).await?;
+
+        if let Some(message_id) = &invoice.delivery.message_id {
+            write_atomic(
+                &self.message_path(message_id),
+                invoice.id.to_string().as_bytes(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The address, phone number and bank account of an invoice
+fn sensitive_fields(invoice: &mut StoredInvoice) -> [&mut String; 5] {
+    let invoice = &mut invoice.invoice;
+    [
+        &mut invoice.address.street,
+        &mut invoice.address.city,
+        &mut invoice.address.zip,
+        &mut invoice.phone_number,
+        &mut invoice.bank_account_number,
+    ]
+}
+
+/// The keys of the attachments and PDFs of an invoice in the blob store
+fn blob_keys(invoice: &StoredInvoice) -> BTreeSet<String> {
+    invoice
+        .files
+        .iter()
+        .flat_map(StoredFile::blobs)
+        .chain(invoice.versions.iter().map(|v| &v.sha256))
+        .cloned()
+        .collect()
+}
+
+/// The files in the subdirectories of a directory, which may not exist
+async fn blob_paths(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    let mut dirs = vec![(dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                dirs.push((path, depth + 1));
+            } else if depth > 0 && path.extension().is_none_or(|e| e != "tmp") {
+                paths.push(path);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Read every JSON file in a directory, which may not exist
+async fn read_json_dir(dir: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut values = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().extension().is_some_and(|e| e == "json") {
+            values.push(tokio::fs::read(entry.path()).await?);
+        }
+    }
+    Ok(values)
+}
+
+async fn remove_file(path: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+async fn remove_dir(path: &Path) -> std::io::Result<()> {
+    match tokio::fs::remove_dir_all(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().expect("bug: storage path without parent");
+    tokio::fs::create_dir_all(dir).await?;
+
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, contents).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Storage
+where
+    S: Send + Sync,
+    State: FromRef<S>,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = State::from_ref(state);
+        Ok(state.storage)
+    }
+}
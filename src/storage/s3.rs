@@ -0,0 +1,235 @@
+use super::blob::{check_key, BlobStore};
+use crate::error::Error;
+
+use axum::async_trait;
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use std::sync::LazyLock;
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// Blobs stored in a bucket of an S3-compatible service, such as MinIO
+///
+/// Requests are signed with AWS Signature Version 4 and use path-style urls.
+#[derive(Clone)]
+pub struct S3BlobStore {
+    client: reqwest::Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+impl std::fmt::Debug for S3BlobStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3BlobStore")
+            .field("endpoint", &self.endpoint.as_str())
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .finish_non_exhaustive()
+    }
+}
+
+impl S3BlobStore {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key_id: &str,
+        secret_access_key: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint
+                .parse()
+                .map_err(|e| Error::BlobStoreError(format!("invalid S3 endpoint: {e}")))?,
+            bucket: bucket.into(),
+            region: region.into(),
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+        })
+    }
+
+    pub fn from_config(config: &crate::S3Config) -> Result<Self, Error> {
+        Self::new(
+            Self::required(&config.endpoint, "S3_ENDPOINT")?,
+            Self::required(&config.bucket, "S3_BUCKET")?,
+            &config.region,
+            Self::required(&config.access_key_id, "S3_ACCESS_KEY_ID")?,
+            Self::required(&config.secret_access_key, "S3_SECRET_ACCESS_KEY")?,
+        )
+    }
+
+    fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, Error> {
+        value
+            .as_deref()
+            .ok_or_else(|| Error::BlobStoreError(format!("{name} is required")))
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, Error> {
+        let path = match key {
+            Some(key) => {
+                check_key(key)?;
+                format!("/{}/{key}", self.bucket)
+            }
+            None => format!("/{}", self.bucket),
+        };
+        let mut query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", uri_encode(k), uri_encode(v)))
+            .collect::<Vec<_>>();
+        query.sort();
+        let query = query.join("&");
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        url.set_query(Some(&query).filter(|q| !q.is_empty()).map(|q| q.as_str()));
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let now = OffsetDateTime::now_utc();
+        let timestamp = now
+            .format(format_description!(
+                "[year][month][day]T[hour][minute][second]Z"
+            ))
+            .expect("bug: invalid format description");
+        let date = &timestamp[..8];
+        let payload_hash = hex::encode(Sha256::digest(&body));
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{path}\n{query}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\n\n{signed_headers}\n{payload_hash}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request))
+        );
+        let signing_key = [date, &self.region, "s3", "aws4_request"].iter().fold(
+            format!("AWS4{}", self.secret_access_key).into_bytes(),
+            |key, part| hmac(&key, part.as_bytes()),
+        );
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+
+        Ok(self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header(
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key_id
+                ),
+            )
+            .body(body)
+            .send()
+            .await?)
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("bug: HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn check_status(response: &reqwest::Response) -> Result<(), Error> {
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(Error::BlobStoreError(format!("S3 responded with {status}"))),
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), Error> {
+        let response = self.request(Method::PUT, Some(key), &[], data).await?;
+        check_status(&response)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, Error> {
+        let response = self
+            .request(Method::GET, Some(key), &[], Vec::new())
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound);
+        }
+        check_status(&response)?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, Error> {
+        let response = self
+            .request(Method::HEAD, Some(key), &[], Vec::new())
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check_status(&response)?;
+        Ok(true)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        let response = self
+            .request(Method::DELETE, Some(key), &[], Vec::new())
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check_status(&response)
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        static KEY: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new("<Key>([0-9a-fA-F]{64})</Key>").unwrap());
+        static NEXT: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new("<NextContinuationToken>([^<]+)</NextContinuationToken>").unwrap()
+        });
+
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2")];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let response = self.request(Method::GET, None, &query, Vec::new()).await?;
+            check_status(&response)?;
+            let body = response.text().await?;
+
+            keys.extend(KEY.captures_iter(&body).map(|c| c[1].to_string()));
+            match NEXT.captures(&body) {
+                Some(next) if body.contains("<IsTruncated>true</IsTruncated>") => {
+                    token = Some(next[1].replace("&amp;", "&"));
+                }
+                _ => return Ok(keys),
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use super::invoices::{invoice_with_attachments, submit};
+use super::{admin_get, body_bytes, invoice_json, json, state, Multipart, ADMIN_TOKEN};
+use crate::api::app;
+use crate::storage::{StoredInvoice, Version};
+use axum::body::Body;
+use axum::http::{request::Request, StatusCode};
+use axum::Router;
+use sha2::{Digest, Sha256};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+fn approve(id: Uuid, token: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder()
+        .method("POST")
+        .uri(format!("/invoices/{id}/approve"))
+        .header("Content-Type", "application/json");
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {token}"));
+    }
+    request
+        .body(Body::from(
+            serde_json::json!({
+                "date": "2024-10-21",
+                "meeting": "37",
+                "approver": "Rahastonhoitaja",
+                "account": "4000",
+            })
+            .to_string(),
+        ))
+        .unwrap()
+}
+
+async fn get(app: &Router, uri: &str) -> axum::response::Response {
+    app.clone().oneshot(admin_get(uri)).await.unwrap()
+}
+
+#[tokio::test]
+async fn approved_invoices_are_stored_as_new_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Kuitti", "Kuva"]))
+        .file(
+            "attachments",
+            "kuitti.pdf",
+            include_bytes!("../../testdata/test.pdf"),
+        )
+        .file(
+            "attachments",
+            "kuva.png",
+            include_bytes!("../../testdata/test.png"),
+        )
+        .request("/invoices");
+    let stored = submit(&app, request).await;
+    assert_eq!(stored.versions.len(), 1);
+    assert!(stored.versions[0].approval.is_none());
+
+    for token in [None, Some("väärä")] {
+        let response = app
+            .clone()
+            .oneshot(approve(stored.id, token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(approve(stored.id, Some(ADMIN_TOKEN)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        response.headers()["Location"],
+        format!("/invoices/{}/versions/2", stored.id)
+    );
+    let version: Version = json(response).await;
+    assert_eq!(version.number, 2);
+    let approval = version.approval.as_ref().unwrap();
+    assert_eq!(approval.meeting, "37");
+    assert_eq!(approval.date, time::macros::date!(2024 - 10 - 21));
+
+    let invoice: StoredInvoice = json(get(&app, &format!("/invoices/{}", stored.id)).await).await;
+    assert_eq!(invoice.versions.len(), 2);
+
+    // The original is kept and the approved version has an audit page at the end
+    let mut pages = Vec::new();
+    for version in &invoice.versions {
+        let response = get(
+            &app,
+            &format!("/invoices/{}/versions/{}", stored.id, version.number),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["Content-Type"], "application/pdf");
+        let pdf = body_bytes(response).await;
+        assert_eq!(hex::encode(Sha256::digest(&pdf)), version.sha256);
+        pages.push(lopdf::Document::load_mem(&pdf).unwrap().get_pages().len());
+    }
+    assert_eq!(pages[1], pages[0] + 1);
+
+    let response = get(&app, &format!("/invoices/{}/versions/3", stored.id)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let uri = format!("/invoices/{}/versions/1", stored.id);
+    let response = app
+        .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn pseudonymized_invoices_cant_be_approved() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let request = Multipart::new()
+        .text("data", &invoice_json().to_string())
+        .request("/invoices");
+    let stored = submit(&app, request).await;
+    storage.pseudonymize(stored.id, "poistettu").await.unwrap();
+
+    let response = app
+        .oneshot(approve(stored.id, Some(ADMIN_TOKEN)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(storage.get(stored.id).await.unwrap().versions.len(), 1);
+}
+
+#[tokio::test]
+async fn invoices_without_stored_attachments_cant_be_approved() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let stored = StoredInvoice::new(serde_json::from_value(invoice_json()).unwrap());
+    state.storage.insert(&stored).await.unwrap();
+    let app = app().with_state(state);
+
+    let response = app
+        .clone()
+        .oneshot(approve(stored.id, Some(ADMIN_TOKEN)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .oneshot(approve(Uuid::new_v4(), Some(ADMIN_TOKEN)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
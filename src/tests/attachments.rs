@@ -0,0 +1,133 @@
+use crate::attachments::{prepare, Format};
+use crate::error::Error;
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use std::io::{Cursor, Write};
+use tempfile::NamedTempFile;
+
+fn encode(image: &DynamicImage, format: ImageOutputFormat) -> Vec<u8> {
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, format).unwrap();
+    data.into_inner()
+}
+
+fn temp_file(contents: &[u8]) -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(contents).unwrap();
+    file
+}
+
+/// An APP1 segment with the given EXIF orientation and a GPS IFD pointer
+fn exif_segment(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2A\0\0\0\x08".to_vec();
+    tiff.extend_from_slice(&2u16.to_be_bytes());
+    // Orientation, SHORT
+    tiff.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    // GPSInfo, LONG, pointing to the empty IFD after this one
+    tiff.extend_from_slice(&[0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 38]);
+    tiff.extend_from_slice(&[0, 0, 0, 0]);
+    tiff.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+    segment.extend_from_slice(b"Exif\0\0");
+    segment.extend_from_slice(&tiff);
+    segment
+}
+
+#[test]
+fn prepare_applies_exif_orientation() {
+    let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(40, 20, [255, 255, 255].into()));
+    let jpeg = encode(&image, ImageOutputFormat::Jpeg(90));
+    // Rotated 90° clockwise, inserted right after the SOI marker
+    let jpeg = [&jpeg[..2], &exif_segment(6), &jpeg[2..]].concat();
+
+    let attachment = prepare("kuitti.jpg".into(), temp_file(&jpeg), None).unwrap();
+    assert_eq!(attachment.filename, "kuitti.jpg");
+    assert_eq!(attachment.format, Format::Jpg);
+
+    let data = std::fs::read(attachment.path()).unwrap();
+    let prepared = image::load_from_memory(&data).unwrap();
+    assert_eq!((prepared.width(), prepared.height()), (20, 40));
+    // The metadata is stripped
+    assert!(!data.windows(6).any(|w| w == b"Exif\0\0"));
+}
+
+#[test]
+fn prepare_downscales_large_images() {
+    let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(3000, 1000, [255, 255, 255].into()));
+    let png = encode(&image, ImageOutputFormat::Png);
+
+    let attachment = prepare("kuitti.png".into(), temp_file(&png), None).unwrap();
+    assert_eq!(attachment.format, Format::Png);
+
+    // Fits a landscape A4 page at 200 DPI
+    let prepared = image::load_from_memory(&std::fs::read(attachment.path()).unwrap()).unwrap();
+    assert_eq!((prepared.width(), prepared.height()), (2338, 779));
+}
+
+#[test]
+fn crop_straightens_receipt_photos() {
+    let photo = image::load_from_memory(include_bytes!("../../testdata/receipt.jpg")).unwrap();
+
+    let receipt = crate::attachments::crop::crop(&photo).unwrap();
+    // The receipt is 500×1150 px with a slight perspective
+    let ratio = receipt.width() as f32 / receipt.height() as f32;
+    assert!((0.40..0.48).contains(&ratio), "{ratio}");
+    assert!((1100..1300).contains(&receipt.height()));
+
+    // The table is cropped away
+    let receipt = receipt.to_rgb8();
+    let table = receipt
+        .pixels()
+        .filter(|p| p[0] as i32 - p[2] as i32 > 30)
+        .count();
+    assert!(table < receipt.pixels().len() / 200, "{table} table pixels");
+}
+
+#[test]
+fn crop_ignores_images_without_receipt() {
+    let screenshot = image::load_from_memory(include_bytes!("../../testdata/test.png")).unwrap();
+    assert!(crate::attachments::crop::crop(&screenshot).is_none());
+
+    let page = DynamicImage::ImageRgb8(RgbImage::from_pixel(600, 800, [250, 250, 250].into()));
+    assert!(crate::attachments::crop::crop(&page).is_none());
+}
+
+/// The text of a prepared PDF attachment
+fn pdf_text(attachment: &crate::api::invoices::InvoiceAttachment) -> String {
+    let document = lopdf::Document::load(attachment.path()).unwrap();
+    assert!(!document.is_encrypted());
+    let pages = document.get_pages().into_keys().collect::<Vec<_>>();
+    document.extract_text(&pages).unwrap()
+}
+
+#[test]
+fn prepare_decrypts_pdfs_with_an_owner_password() {
+    // AES-128 with object streams and RC4
+    for pdf in [
+        &include_bytes!("../../testdata/receipt-owner.pdf")[..],
+        &include_bytes!("../../testdata/receipt-rc4.pdf")[..],
+    ] {
+        let attachment = prepare("kuitti.pdf".into(), temp_file(pdf), None).unwrap();
+        assert_eq!(attachment.format, Format::Pdf);
+        assert!(pdf_text(&attachment).contains("K-Market Otaniemi"));
+    }
+}
+
+#[test]
+fn prepare_decrypts_pdfs_with_a_user_password() {
+    let pdf = include_bytes!("../../testdata/receipt-password.pdf");
+
+    let result = prepare("lippu.pdf".into(), temp_file(pdf), None);
+    assert!(matches!(result, Err(Error::PdfPasswordRequired(name)) if name == "lippu.pdf"));
+    let result = prepare("lippu.pdf".into(), temp_file(pdf), Some("väärä"));
+    assert!(matches!(result, Err(Error::IncorrectPdfPassword(name)) if name == "lippu.pdf"));
+
+    // Both the user and the owner password open the PDF
+    for password in ["salasana", "omistaja"] {
+        let attachment = prepare("lippu.pdf".into(), temp_file(pdf), Some(password)).unwrap();
+        assert!(pdf_text(&attachment).contains("K-Market Otaniemi"));
+    }
+}
@@ -0,0 +1,119 @@
+use super::invoices::{invoice_with_attachments, submit};
+use super::{admin_get, json, state, Multipart, ADMIN_TOKEN};
+use crate::api::app;
+use crate::api::audit::AuditResponse;
+use crate::audit::{verify, Action, AuditLog, ADMIN, ANONYMOUS};
+use axum::body::Body;
+use axum::http::{request::Request, StatusCode};
+use axum::Router;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn query(app: &Router, query: &str) -> AuditResponse {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/audit?{query}"))
+                .header("Authorization", format!("Bearer {ADMIN_TOKEN}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    json(response).await
+}
+
+#[tokio::test]
+async fn invoice_actions_are_logged() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&[]))
+        .request("/invoices");
+    let stored = submit(&app, request).await;
+
+    let uri = format!("/invoices/{}", stored.id);
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app.clone().oneshot(admin_get(&uri)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/audit")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let log = query(&app, &format!("invoice={}", stored.id)).await;
+    assert_eq!(log.broken_at, None);
+    let actions = log.entries.iter().map(|e| e.action).collect::<Vec<_>>();
+    #[cfg(feature = "email")]
+    assert_eq!(
+        actions,
+        [Action::Submitted, Action::EmailSent, Action::Viewed]
+    );
+    // NOTE: submit views the invoice as well
+    #[cfg(not(feature = "email"))]
+    assert_eq!(actions, [Action::Submitted, Action::Viewed, Action::Viewed]);
+
+    let submitted = &log.entries[0];
+    assert_eq!(submitted.actor, crate::audit::subject("matti@example.org"));
+    assert_eq!(submitted.ip, Some([127, 0, 0, 1].into()));
+    assert_eq!(log.head.as_ref(), log.entries.last().map(|e| &e.hash));
+
+    let log = query(&app, &format!("actor={ADMIN}")).await;
+    assert!(!log.entries.is_empty());
+    assert!(log.entries.iter().all(|e| e.action == Action::Viewed));
+
+    let log = query(&app, &format!("invoice={}", Uuid::nil())).await;
+    assert!(log.entries.is_empty());
+}
+
+#[tokio::test]
+async fn tampering_breaks_the_hash_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    let audit = AuditLog::new(dir.path());
+    let id = Uuid::new_v4();
+    for action in [Action::Submitted, Action::Viewed] {
+        audit
+            .record(action, Some(id), ANONYMOUS, None, None)
+            .await
+            .unwrap();
+    }
+    // The chain is continued after a restart
+    let audit = AuditLog::new(dir.path());
+    audit
+        .record(Action::Downloaded, Some(id), ANONYMOUS, None, None)
+        .await
+        .unwrap();
+
+    let entries = audit.read().await.unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[2].seq, 3);
+    assert_eq!(verify(&entries), None);
+
+    let mut modified = entries.clone();
+    modified[1].actor = "admin".into();
+    assert_eq!(verify(&modified), Some(2));
+
+    let mut removed = entries.clone();
+    removed.remove(1);
+    assert_eq!(verify(&removed), Some(3));
+
+    // Rewriting the hash of the modified entry breaks the next one
+    modified[1].hash = modified[1].digest();
+    assert_eq!(verify(&modified), Some(3));
+}
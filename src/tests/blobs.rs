@@ -0,0 +1,251 @@
+use super::invoices::{invoice_with_attachments, submit};
+use super::{invoice_json, state, Multipart};
+use crate::api::app;
+use crate::storage::{BlobStore, FsBlobStore, Storage, StoredInvoice};
+
+async fn blob_count(dir: &tempfile::TempDir) -> usize {
+    FsBlobStore::new(dir.path().join("blobs"))
+        .list()
+        .await
+        .unwrap()
+        .len()
+}
+
+#[tokio::test]
+async fn identical_attachments_are_stored_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let mut invoices = Vec::new();
+    for _ in 0..2 {
+        let request = Multipart::new()
+            .text("data", &invoice_with_attachments(&["Kuitti"]))
+            .file(
+                "attachments",
+                "kuitti.pdf",
+                include_bytes!("../../testdata/test.pdf"),
+            )
+            .request("/invoices");
+        invoices.push(submit(&app, request).await);
+    }
+    assert_eq!(invoices[0].files[0].blob, invoices[1].files[0].blob);
+    // The shared attachment and the PDF of each invoice
+    assert_eq!(blob_count(&dir).await, 3);
+    assert!(!dir
+        .path()
+        .join("invoices")
+        .join(invoices[0].id.to_string())
+        .exists());
+
+    storage.remove(invoices[0].id).await.unwrap();
+    assert_eq!(blob_count(&dir).await, 2);
+    let files = storage
+        .files(&storage.get(invoices[1].id).await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        std::fs::read(files[0].path()).unwrap(),
+        include_bytes!("../../testdata/test.pdf")
+    );
+
+    storage.remove(invoices[1].id).await.unwrap();
+    assert_eq!(blob_count(&dir).await, 0);
+}
+
+#[tokio::test]
+async fn legacy_versions_are_read_from_the_invoice_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::new(dir.path());
+    let invoice = StoredInvoice::new(serde_json::from_value(invoice_json()).unwrap());
+    storage.insert(&invoice).await.unwrap();
+    storage
+        .insert_version(invoice.id, b"%PDF-1.7", invoice.created_at, None)
+        .await
+        .unwrap();
+
+    // Move the PDF where it was stored before the blob store
+    let key = storage.get(invoice.id).await.unwrap().versions[0]
+        .sha256
+        .clone();
+    let legacy = dir.path().join("invoices").join(invoice.id.to_string());
+    std::fs::create_dir_all(&legacy).unwrap();
+    std::fs::write(legacy.join("1.pdf"), b"%PDF-1.7").unwrap();
+    FsBlobStore::new(dir.path().join("blobs"))
+        .delete(&key)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        storage.get_version(invoice.id, 1).await.unwrap(),
+        b"%PDF-1.7"
+    );
+    assert!(matches!(
+        storage.get_version(invoice.id, 2).await,
+        Err(crate::error::Error::NotFound)
+    ));
+}
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use super::*;
+    use crate::storage::{Keyring, S3BlobStore};
+    use axum::body::Bytes;
+    use axum::extract::{Path, Query, State};
+    use axum::http::{HeaderMap, Method, StatusCode};
+    use axum::response::{IntoResponse, Response};
+    use sha2::{Digest, Sha256};
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    type Bucket = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /// Keys listed on each page, to exercise the continuation of listings
+    const PAGE_SIZE: usize = 2;
+
+    fn is_signed(headers: &HeaderMap, body: &[u8]) -> bool {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        header("authorization").is_some_and(|auth| {
+            auth.starts_with("AWS4-HMAC-SHA256 Credential=access/")
+                && auth.contains("/us-east-1/s3/aws4_request")
+                && auth.contains("Signature=")
+        }) && header("x-amz-date").is_some()
+            && header("x-amz-content-sha256") == Some(&hex::encode(Sha256::digest(body)))
+    }
+
+    async fn object(
+        State(bucket): State<Bucket>,
+        method: Method,
+        Path(key): Path<String>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        if !is_signed(&headers, &body) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        let mut objects = bucket.lock().unwrap();
+        match (method, objects.get(&key)) {
+            (Method::PUT, _) => {
+                objects.insert(key, body.to_vec());
+                StatusCode::OK.into_response()
+            }
+            (Method::DELETE, _) => {
+                objects.remove(&key);
+                StatusCode::NO_CONTENT.into_response()
+            }
+            (Method::GET | Method::HEAD, Some(data)) => data.clone().into_response(),
+            _ => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    /// ListObjectsV2, continuing after the key given as the continuation token
+    async fn list_objects(
+        State(bucket): State<Bucket>,
+        Query(query): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+    ) -> Response {
+        if !is_signed(&headers, b"") || query.get("list-type").map(String::as_str) != Some("2") {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+        let objects = bucket.lock().unwrap();
+        let start = query.get("continuation-token").cloned().unwrap_or_default();
+        let keys = objects
+            .keys()
+            .filter(|k| **k > start)
+            .take(PAGE_SIZE + 1)
+            .collect::<Vec<_>>();
+        let page = &keys[..keys.len().min(PAGE_SIZE)];
+
+        let mut xml = String::from("<ListBucketResult>");
+        for key in page {
+            xml += &format!("<Contents><Key>{key}</Key></Contents>");
+        }
+        if keys.len() > PAGE_SIZE {
+            xml += &format!(
+                "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+                page[PAGE_SIZE - 1]
+            );
+        } else {
+            xml += "<IsTruncated>false</IsTruncated>";
+        }
+        xml += "</ListBucketResult>";
+        xml.into_response()
+    }
+
+    /// Start a server that stores objects in a single bucket like an S3-compatible service
+    async fn mock_s3() -> String {
+        let app = axum::Router::new()
+            .route("/laskut/:key", axum::routing::any(object))
+            .route("/laskut", axum::routing::get(list_objects))
+            .with_state(Bucket::default());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    async fn store() -> S3BlobStore {
+        S3BlobStore::new(&mock_s3().await, "laskut", "us-east-1", "access", "secret").unwrap()
+    }
+
+    #[tokio::test]
+    async fn blobs_are_stored_in_the_bucket() {
+        let blobs = store().await;
+        let keys = ["a", "b", "c", "d", "e"]
+            .map(|data| hex::encode(Sha256::digest(data)))
+            .to_vec();
+        for (key, data) in keys.iter().zip(["a", "b", "c", "d", "e"]) {
+            blobs.put(key, data.into()).await.unwrap();
+        }
+
+        assert_eq!(blobs.get(&keys[0]).await.unwrap(), b"a");
+        assert!(blobs.exists(&keys[1]).await.unwrap());
+        let mut listed = blobs.list().await.unwrap();
+        listed.sort();
+        let mut expected = keys.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        blobs.delete(&keys[0]).await.unwrap();
+        blobs.delete(&keys[0]).await.unwrap();
+        assert!(!blobs.exists(&keys[0]).await.unwrap());
+        assert!(matches!(
+            blobs.get(&keys[0]).await,
+            Err(crate::error::Error::NotFound)
+        ));
+        assert!(blobs.put("../secret", Vec::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invoices_are_stored_in_s3() {
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = store().await;
+        let storage = Storage::new(dir.path())
+            .with_keyring(Keyring::generate())
+            .with_blob_store(blobs.clone());
+
+        let invoice = StoredInvoice::new(serde_json::from_value(invoice_json()).unwrap());
+        storage.insert(&invoice).await.unwrap();
+        let version = storage
+            .insert_version(invoice.id, b"%PDF-1.7", invoice.created_at, None)
+            .await
+            .unwrap();
+
+        assert_eq!(blobs.list().await.unwrap(), vec![version.sha256.clone()]);
+        assert!(!blobs
+            .get(&version.sha256)
+            .await
+            .unwrap()
+            .starts_with(b"%PDF"));
+        assert_eq!(
+            storage.get_version(invoice.id, 1).await.unwrap(),
+            b"%PDF-1.7"
+        );
+        assert_eq!(blob_count(&dir).await, 0);
+
+        storage.remove(invoice.id).await.unwrap();
+        assert!(blobs.list().await.unwrap().is_empty());
+    }
+}
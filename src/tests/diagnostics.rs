@@ -0,0 +1,73 @@
+use super::invoice_json;
+use crate::api::invoices::{Invoice, InvoiceAttachment};
+use crate::attachments::Format;
+use crate::error::Error;
+use crate::pdfgen::diagnostic::Location;
+use crate::pdfgen::{compile_template, render, Template};
+use axum::http::StatusCode;
+use std::io::Write;
+use std::sync::Arc;
+use time::macros::datetime;
+use typst::foundations::Value;
+
+#[test]
+fn template_errors_point_to_the_template() {
+    let template = Template {
+        path: "/rikki.typ",
+        source: "= Lasku\n#let total = 1\nYhteensä #summa(total)\n",
+    };
+    let result = compile_template(
+        template,
+        Value::None,
+        None,
+        datetime!(2024-10-19 12:00 UTC),
+        &[],
+    );
+
+    let Err(Error::TypstError(diagnostics)) = result else {
+        panic!("expected a template error, got {result:?}");
+    };
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "unknown variable: summa");
+    assert_eq!(
+        diagnostics[0].location,
+        Some(Location {
+            file: "rikki.typ".into(),
+            line: 3,
+            column: 11,
+        })
+    );
+    assert!(!diagnostics[0].caused_by_input());
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "rikki.typ:3:11: unknown variable: summa"
+    );
+    assert_eq!(
+        Error::TypstError(diagnostics).status(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[test]
+fn attachment_errors_are_reported_without_the_template() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"\x89PNG\r\n\x1a\nrikki").unwrap();
+    let mut invoice: Invoice = serde_json::from_value(invoice_json()).unwrap();
+    invoice.attachments.push(InvoiceAttachment {
+        filename: "rikki.png".into(),
+        size: 13,
+        format: Format::Png,
+        pages: 1,
+        file: Arc::new(file),
+        original: None,
+    });
+
+    let result = render(invoice, datetime!(2024-10-19 12:00 UTC), None);
+    let Err(error @ Error::InvalidRenderInput(_)) = result else {
+        panic!("expected an input error, got {result:?}");
+    };
+    assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    let message = error.to_string();
+    assert!(message.contains("failed to decode image"), "{message}");
+    assert!(!message.contains("invoice.typ"), "{message}");
+}
@@ -0,0 +1,79 @@
+use super::invoices::{invoice_with_attachments, submit};
+use super::{invoice_json, state, Multipart};
+use crate::api::app;
+use crate::receipt::Warning;
+use axum::body::Body;
+use axum::http::request::Request;
+
+fn with_receipt(invoice: &str, receipt: &[u8]) -> Request<Body> {
+    Multipart::new()
+        .text("data", invoice)
+        .file("attachments", "kuitti.pdf", receipt)
+        .request("/invoices")
+}
+
+#[tokio::test]
+async fn resubmitted_receipts_are_flagged() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+    let receipt = include_bytes!("../../testdata/test.pdf");
+
+    let first = submit(
+        &app,
+        with_receipt(&invoice_with_attachments(&["Kuitti"]), receipt),
+    )
+    .await;
+    assert!(first.warnings.is_empty());
+
+    // The same receipt in an otherwise different invoice
+    let mut invoice: serde_json::Value =
+        serde_json::from_str(&invoice_with_attachments(&["Kuitti"])).unwrap();
+    invoice["subject"] = "Kahvia uudestaan".into();
+    let second = submit(&app, with_receipt(&invoice.to_string(), receipt)).await;
+    assert_eq!(
+        second.warnings,
+        vec![Warning::PossibleDuplicate {
+            invoice: first.id,
+            submitted_at: first.created_at,
+            same_details: false,
+            attachments: vec!["kuitti.pdf".into()],
+        }]
+    );
+    assert_ne!(first.fingerprint, second.fingerprint);
+    assert!(second.warnings[0]
+        .to_string()
+        .contains("samat liitteet: kuitti.pdf"));
+}
+
+#[tokio::test]
+async fn invoices_with_the_same_details_are_flagged() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+    let request = |invoice: serde_json::Value| {
+        Multipart::new()
+            .text("data", &invoice.to_string())
+            .request("/invoices")
+    };
+
+    let first = submit(&app, request(invoice_json())).await;
+
+    let mut other = invoice_json();
+    other["rows"][0]["quantity"] = 3.into();
+    assert!(submit(&app, request(other)).await.warnings.is_empty());
+
+    // Case and whitespace don't matter
+    let mut same = invoice_json();
+    same["recipient_email"] = " Matti@Example.org".into();
+    same["subject"] = "kahvia ".into();
+    let duplicate = submit(&app, request(same)).await;
+    assert_eq!(duplicate.fingerprint, first.fingerprint);
+    assert!(matches!(
+        duplicate.warnings.as_slice(),
+        [Warning::PossibleDuplicate {
+            invoice,
+            same_details: true,
+            attachments,
+            ..
+        }] if *invoice == first.id && attachments.is_empty()
+    ));
+}
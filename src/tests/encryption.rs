@@ -0,0 +1,133 @@
+use super::invoices::{invoice_with_attachments, submit};
+use super::{admin_get, body_bytes, invoice_json, state, Multipart};
+use crate::api::app;
+use crate::idempotency::Outcome;
+use crate::storage::{Keyring, Storage, StoredInvoice};
+use axum::http::StatusCode;
+use tower::ServiceExt;
+
+const IBAN: &str = "FI2112345600000785";
+
+/// Every stored file that contains the bytes
+fn files_containing(dir: &std::path::Path, needle: &[u8]) -> Vec<std::path::PathBuf> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(files_containing(&path, needle));
+        } else if std::fs::read(&path)
+            .unwrap()
+            .windows(needle.len())
+            .any(|w| w == needle)
+        {
+            found.push(path);
+        }
+    }
+    found
+}
+
+#[tokio::test]
+async fn stored_data_is_encrypted() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Kuitti"]))
+        .file(
+            "attachments",
+            "kuitti.pdf",
+            include_bytes!("../../testdata/test.pdf"),
+        )
+        .request("/invoices");
+    let stored = submit(&app, request).await;
+    assert_eq!(stored.invoice.bank_account_number, IBAN);
+
+    for needle in [IBAN.as_bytes(), b"+358401234567", b"Konemiehentie", b"%PDF"] {
+        assert_eq!(
+            files_containing(dir.path(), needle),
+            Vec::<std::path::PathBuf>::new()
+        );
+    }
+
+    let response = app
+        .oneshot(admin_get(&format!("/invoices/{}/versions/1", stored.id)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_bytes(response).await.starts_with(b"%PDF"));
+}
+
+#[tokio::test]
+async fn keys_are_rotated_by_reencrypting() {
+    let dir = tempfile::tempdir().unwrap();
+    let (old, new) = (Keyring::generate_key(), Keyring::generate_key());
+    let keyring = |keys: &[[u8; 32]]| Keyring::new(keys).unwrap();
+
+    // Data stored before the encryption was enabled is still read
+    let plain = Storage::new(dir.path());
+    let legacy = StoredInvoice::new(serde_json::from_value(invoice_json()).unwrap());
+    plain.insert(&legacy).await.unwrap();
+
+    let storage = Storage::new(dir.path()).with_keyring(keyring(&[old]));
+    let invoice = StoredInvoice::new(serde_json::from_value(invoice_json()).unwrap());
+    storage.insert(&invoice).await.unwrap();
+    storage
+        .insert_version(invoice.id, b"%PDF-1.7", invoice.created_at, None)
+        .await
+        .unwrap();
+    let outcome = Outcome {
+        request: "00".into(),
+        created_at: invoice.created_at,
+        status: 201,
+        headers: Vec::new(),
+        body: IBAN.as_bytes().to_vec(),
+    };
+    storage.insert_outcome("avain", &outcome).await.unwrap();
+    assert_eq!(
+        storage
+            .get(legacy.id)
+            .await
+            .unwrap()
+            .invoice
+            .bank_account_number,
+        IBAN
+    );
+    assert!(plain.get(invoice.id).await.is_err());
+
+    let rotated = Storage::new(dir.path()).with_keyring(keyring(&[new, old]));
+    assert_eq!(rotated.reencrypt().await.unwrap(), 4);
+    assert_eq!(files_containing(dir.path(), IBAN.as_bytes()).len(), 0);
+
+    let storage = Storage::new(dir.path()).with_keyring(keyring(&[new]));
+    for id in [legacy.id, invoice.id] {
+        let invoice = storage.get(id).await.unwrap();
+        assert_eq!(invoice.invoice.bank_account_number, IBAN);
+        assert_eq!(invoice.invoice.address.city, "Espoo");
+    }
+    assert_eq!(
+        storage.get_version(invoice.id, 1).await.unwrap(),
+        b"%PDF-1.7"
+    );
+    let replayed = storage.get_outcome("avain").await.unwrap().unwrap();
+    assert_eq!(replayed.body, IBAN.as_bytes());
+
+    let storage = Storage::new(dir.path()).with_keyring(keyring(&[old]));
+    assert!(storage.get(invoice.id).await.is_err());
+    assert!(storage.get_outcome("avain").await.is_err());
+    assert!(storage.get_version(invoice.id, 1).await.is_err());
+}
+
+#[test]
+fn modified_data_is_rejected() {
+    let keyring = Keyring::generate();
+    let mut sealed = keyring.seal(b"salaisuus");
+    assert_eq!(keyring.open(sealed.clone()).unwrap(), b"salaisuus");
+
+    let last = sealed.len() - 1;
+    sealed[last] ^= 1;
+    assert!(keyring.open(sealed).is_err());
+
+    assert!(Keyring::parse("# avaimet\n00ff").is_err());
+    let key = hex::encode(Keyring::generate_key());
+    assert!(Keyring::parse(&format!("# avaimet\n{key}\n")).is_ok());
+}
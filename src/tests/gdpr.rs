@@ -0,0 +1,213 @@
+use super::invoices::{invoice_with_attachments, submit};
+use super::{body_bytes, invoice_json, json, state, Multipart, ADMIN_TOKEN};
+use crate::api::app;
+use crate::gdpr::{purge, Erasure, Export, Retention, RetentionAction};
+use crate::storage::{DeliveryEvent, DeliveryStatus, Draft, StoredInvoice};
+use axum::body::Body;
+use axum::http::{request::Request, StatusCode};
+use std::io::Read;
+use time::macros::{date, datetime};
+use time::OffsetDateTime;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+fn stored_invoice(email: &str, created_at: OffsetDateTime) -> StoredInvoice {
+    let mut invoice = invoice_json();
+    invoice["recipient_email"] = email.into();
+    let mut stored = StoredInvoice::new(serde_json::from_value(invoice).unwrap());
+    stored.created_at = created_at;
+    stored
+}
+
+fn draft(email: &str) -> Draft {
+    Draft {
+        id: Uuid::new_v4(),
+        created_at: OffsetDateTime::now_utc(),
+        recipient_name: "Matti Meikäläinen".into(),
+        recipient_email: email.into(),
+        subject: "Kahvia".into(),
+        description: String::new(),
+        attachments: Vec::new(),
+        token_hash: None,
+    }
+}
+
+#[tokio::test]
+async fn export_contains_the_data_of_a_person() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let other = stored_invoice("maija@example.org", OffsetDateTime::now_utc());
+    state.storage.insert(&other).await.unwrap();
+    let app = app().with_state(state);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Kuitti"]))
+        .file(
+            "attachments",
+            "kuitti.pdf",
+            include_bytes!("../../testdata/test.pdf"),
+        )
+        .request("/invoices");
+    let stored = submit(&app, request).await;
+
+    let export = |token: &str| {
+        Request::builder()
+            .uri("/gdpr/export?email=Matti@Example.org")
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(export("väärä")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app.clone().oneshot(export(ADMIN_TOKEN)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/zip");
+    let zip = body_bytes(response).await;
+    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(zip)).unwrap();
+
+    let mut names = zip.file_names().map(String::from).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "data.json".to_string(),
+            format!("invoices/{}/1.pdf", stored.id),
+            format!("invoices/{}/attachments/0-kuitti.pdf", stored.id),
+        ]
+    );
+
+    let mut attachment = Vec::new();
+    zip.by_name(&names[2])
+        .unwrap()
+        .read_to_end(&mut attachment)
+        .unwrap();
+    assert_eq!(attachment, include_bytes!("../../testdata/test.pdf"));
+
+    let data: Export = serde_json::from_reader(zip.by_name("data.json").unwrap()).unwrap();
+    assert_eq!(data.invoices.len(), 1);
+    assert_eq!(data.invoices[0].id, stored.id);
+    assert!(data
+        .audit
+        .iter()
+        .any(|e| e.invoice == Some(stored.id)
+            && e.actor == crate::audit::subject("matti@example.org")));
+}
+
+#[tokio::test]
+async fn erasure_keeps_invoices_within_the_retention_period() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let mut old = stored_invoice("matti@example.org", datetime!(2015-06-01 12:00 UTC));
+    old.delivery.record(DeliveryEvent {
+        status: DeliveryStatus::Bounced,
+        recipient: "matti@example.org".into(),
+        timestamp: datetime!(2015-06-01 12:01 UTC),
+        reason: Some("550 matti@example.org: no such user".into()),
+    });
+    let recent = stored_invoice("matti@example.org", OffsetDateTime::now_utc());
+    let other = stored_invoice("maija@example.org", datetime!(2015-06-01 12:00 UTC));
+    let draft = draft("matti@example.org");
+    for invoice in [&old, &recent, &other] {
+        state.storage.insert(invoice).await.unwrap();
+    }
+    state.storage.insert_draft(&draft, &[]).await.unwrap();
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/gdpr/erase")
+                .header("Authorization", format!("Bearer {ADMIN_TOKEN}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"email":"matti@example.org"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let erasure: Erasure = json(response).await;
+    assert_eq!(erasure.invoices, [old.id]);
+    assert_eq!(erasure.drafts, [draft.id]);
+    assert_eq!(erasure.retained.len(), 1);
+    assert_eq!(erasure.retained[0].id, recent.id);
+    assert_eq!(
+        erasure.retained[0].retained_until.year(),
+        recent.created_at.year() + 7
+    );
+
+    let pseudonymized = storage.get(old.id).await.unwrap();
+    assert!(pseudonymized.pseudonymized_at.is_some());
+    assert!(pseudonymized
+        .invoice
+        .recipient_email
+        .starts_with("poistettu-"));
+    assert_eq!(pseudonymized.invoice.bank_account_number, "");
+    assert_eq!(pseudonymized.invoice.description, "");
+    assert!(pseudonymized.delivery.recipients.is_empty());
+    assert_eq!(pseudonymized.delivery.events[0].recipient, "");
+    assert_eq!(pseudonymized.delivery.events[0].reason, None);
+    assert_eq!(pseudonymized.invoice.rows.len(), 1);
+
+    let other = storage.get(other.id).await.unwrap();
+    assert!(other.pseudonymized_at.is_none());
+    assert!(storage.get_draft(draft.id).await.is_err());
+}
+
+#[tokio::test]
+async fn purge_applies_the_retention_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let (storage, audit) = (&state.storage, &state.audit);
+    let invoices = [
+        stored_invoice("matti@example.org", datetime!(2017-03-01 12:00 UTC)),
+        stored_invoice("Matti@example.org", datetime!(2018-12-31 12:00 UTC)),
+        stored_invoice("matti@example.org", datetime!(2019-01-01 12:00 UTC)),
+    ];
+    for invoice in &invoices {
+        storage.insert(invoice).await.unwrap();
+    }
+
+    let retention = Retention {
+        years: 6,
+        action: RetentionAction::Pseudonymize,
+    };
+    let mut purged = purge(storage, audit, retention, date!(2025 - 01 - 01))
+        .await
+        .unwrap();
+    purged.sort();
+    let mut expected = vec![invoices[0].id, invoices[1].id];
+    expected.sort();
+    assert_eq!(purged, expected);
+
+    let first = storage.get(invoices[0].id).await.unwrap();
+    let second = storage.get(invoices[1].id).await.unwrap();
+    assert_eq!(
+        first.invoice.recipient_email,
+        second.invoice.recipient_email
+    );
+    assert!(storage
+        .get(invoices[2].id)
+        .await
+        .unwrap()
+        .pseudonymized_at
+        .is_none());
+
+    // Pseudonymized invoices aren't purged again
+    let purged = purge(storage, audit, retention, date!(2025 - 01 - 01))
+        .await
+        .unwrap();
+    assert!(purged.is_empty());
+
+    let retention = Retention {
+        years: 6,
+        action: RetentionAction::Erase,
+    };
+    let purged = purge(storage, audit, retention, date!(2026 - 01 - 01))
+        .await
+        .unwrap();
+    assert_eq!(purged, [invoices[2].id]);
+    assert!(storage.get(invoices[2].id).await.is_err());
+}
@@ -0,0 +1,151 @@
+use super::{body_bytes, invoice_json, state, Multipart};
+use crate::api::app;
+use crate::error::Error;
+use crate::idempotency::{Idempotency, MemoryStore};
+use crate::storage::{Keyring, Storage};
+use axum::body::Body;
+use axum::http::{request::Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use time::Duration;
+use tower::ServiceExt;
+
+fn request(key: &str, subject: &str) -> Request<Body> {
+    let mut invoice = invoice_json();
+    invoice["subject"] = subject.into();
+    let mut request = Multipart::new()
+        .text("data", &invoice.to_string())
+        .request("/invoices");
+    request
+        .headers_mut()
+        .insert("Idempotency-Key", key.parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn retries_get_the_first_response() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let first = app
+        .clone()
+        .oneshot(request("avain", "Kahvia"))
+        .await
+        .unwrap();
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert!(first.headers().get("Idempotent-Replayed").is_none());
+    let content_type = first.headers()["Content-Type"].clone();
+    let first = body_bytes(first).await;
+
+    let retry = app
+        .clone()
+        .oneshot(request("avain", "Kahvia"))
+        .await
+        .unwrap();
+    assert_eq!(retry.status(), StatusCode::CREATED);
+    assert_eq!(retry.headers()["Idempotent-Replayed"], "true");
+    assert_eq!(retry.headers()["Content-Type"], content_type);
+    assert_eq!(body_bytes(retry).await, first);
+
+    let conflict = app
+        .clone()
+        .oneshot(request("avain", "Teetä"))
+        .await
+        .unwrap();
+    assert_eq!(conflict.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let too_long = app
+        .oneshot(request(&"x".repeat(256), "Kahvia"))
+        .await
+        .unwrap();
+    assert_eq!(too_long.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Handle a request that responds with the number of times it has been handled
+async fn handle(
+    idempotency: &Idempotency,
+    key: &str,
+    request: &str,
+    count: &AtomicUsize,
+    status: StatusCode,
+) -> Result<Vec<u8>, Error> {
+    let response = idempotency
+        .run(Some(key.into()), request.into(), async {
+            let count = count.fetch_add(1, Ordering::SeqCst) + 1;
+            (status, count.to_string()).into_response()
+        })
+        .await?;
+    Ok(body_bytes(response).await.to_vec())
+}
+
+#[tokio::test]
+async fn outcomes_are_kept_in_the_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::new(dir.path()).with_keyring(Keyring::generate());
+    let count = AtomicUsize::new(0);
+
+    let idempotency = Idempotency::new(storage.clone(), Duration::hours(24));
+    let first = handle(&idempotency, "avain", "a", &count, StatusCode::OK).await;
+    assert_eq!(first.unwrap(), b"1");
+
+    // A restarted server replays the outcome
+    let idempotency = Idempotency::new(storage.clone(), Duration::hours(24));
+    let retry = handle(&idempotency, "avain", "a", &count, StatusCode::OK).await;
+    assert_eq!(retry.unwrap(), b"1");
+    assert!(matches!(
+        handle(&idempotency, "avain", "b", &count, StatusCode::OK).await,
+        Err(Error::IdempotencyKeyConflict)
+    ));
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    // Outcomes aren't replayed after the window
+    let idempotency = Idempotency::new(storage.clone(), Duration::ZERO);
+    let expired = handle(&idempotency, "avain", "b", &count, StatusCode::OK).await;
+    assert_eq!(expired.unwrap(), b"2");
+
+    // and are removed once purged
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    idempotency.purge().await.unwrap();
+    assert!(storage.get_outcome("avain").await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn server_errors_can_be_retried() {
+    let idempotency = Idempotency::new(MemoryStore::default(), Duration::hours(24));
+    let count = AtomicUsize::new(0);
+    let error = StatusCode::BAD_GATEWAY;
+
+    let first = handle(&idempotency, "avain", "a", &count, error).await;
+    assert_eq!(first.unwrap(), b"1");
+    let retry = handle(&idempotency, "avain", "a", &count, StatusCode::OK).await;
+    assert_eq!(retry.unwrap(), b"2");
+    let replay = handle(&idempotency, "avain", "a", &count, StatusCode::OK).await;
+    assert_eq!(replay.unwrap(), b"2");
+}
+
+#[tokio::test]
+async fn concurrent_retries_wait_for_the_first_request() {
+    let idempotency = Idempotency::new(MemoryStore::default(), Duration::hours(24));
+    let count = AtomicUsize::new(0);
+    let slow = async {
+        idempotency
+            .run(Some("avain".into()), "a".into(), async {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                let count = count.fetch_add(1, Ordering::SeqCst) + 1;
+                count.to_string().into_response()
+            })
+            .await
+            .unwrap()
+    };
+    let retry = async {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        handle(&idempotency, "avain", "a", &count, StatusCode::OK)
+            .await
+            .unwrap()
+    };
+
+    let (first, retry): (Response, _) = tokio::join!(slow, retry);
+    assert_eq!(body_bytes(first).await, "1");
+    assert_eq!(retry, b"1");
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
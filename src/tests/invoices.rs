@@ -0,0 +1,422 @@
+use super::{invoice_json, invoice_with_files, json, state, Multipart};
+use crate::api::app;
+use crate::api::invoices::generate_pdf;
+use crate::attachments::Format;
+use crate::receipt::Warning;
+use crate::signing::Signer;
+use crate::storage::StoredInvoice;
+use axum::body::Body;
+use axum::http::{request::Request, StatusCode};
+use axum::Router;
+use time::macros::datetime;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+pub(super) fn invoice_with_attachments(descriptions: &[&str]) -> String {
+    let mut invoice = invoice_json();
+    invoice["attachment_descriptions"] = serde_json::json!(descriptions);
+    invoice.to_string()
+}
+
+/// Submit an invoice and get the stored invoice
+pub(super) async fn submit(app: &Router, request: Request<Body>) -> StoredInvoice {
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    #[cfg(feature = "email")]
+    let stored = json(response).await;
+
+    #[cfg(not(feature = "email"))]
+    let stored = {
+        let location = response.headers()["Location"].to_str().unwrap().to_owned();
+        assert!(super::body_bytes(response).await.starts_with(b"%PDF"));
+
+        let response = app
+            .clone()
+            .oneshot(super::admin_get(&location))
+            .await
+            .unwrap();
+        json(response).await
+    };
+
+    stored
+}
+
+#[tokio::test]
+async fn create_invoice_with_attachments() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Kuitti", "Kuva"]))
+        .file(
+            "attachments",
+            "kuitti.pdf",
+            include_bytes!("../../testdata/test.pdf"),
+        )
+        .file(
+            "attachments",
+            "kuva.png",
+            include_bytes!("../../testdata/test.png"),
+        )
+        .request("/invoices");
+
+    let stored = submit(&app, request).await;
+    #[cfg(feature = "email")]
+    assert_eq!(
+        stored.delivery.message_id.as_deref(),
+        Some(super::MESSAGE_ID)
+    );
+
+    assert_eq!(stored.invoice.subject, "Kahvia");
+}
+
+#[tokio::test]
+async fn create_invoice_rejects_unsupported_attachment() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Kutsu"]))
+        .file("attachments", "kutsu.ics", b"BEGIN:VCALENDAR")
+        .request("/invoices");
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn create_invoice_detects_format_from_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    // A PNG image with the wrong extension
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Kuva"]))
+        .file(
+            "attachments",
+            "kuva.jpg",
+            include_bytes!("../../testdata/test.png"),
+        )
+        .request("/invoices");
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn create_invoice_rejects_corrupt_attachments() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let pdf = include_bytes!("../../testdata/test.pdf");
+    let heic = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+
+    for (filename, contents) in [("kuitti.pdf", &pdf[..pdf.len() / 2]), ("kuitti.jpg", heic)] {
+        let request = Multipart::new()
+            .text("data", &invoice_with_attachments(&["Kuitti"]))
+            .file("attachments", filename, contents)
+            .request("/invoices");
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let error: serde_json::Value = json(response).await;
+        assert!(error["error"].as_str().unwrap().contains(filename));
+    }
+}
+
+#[tokio::test]
+async fn create_invoice_decrypts_attachments() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    for (password, status) in [
+        (None, StatusCode::BAD_REQUEST),
+        (Some("väärä"), StatusCode::BAD_REQUEST),
+        (Some("salasana"), StatusCode::CREATED),
+    ] {
+        let mut invoice = invoice_json();
+        invoice["attachment_descriptions"] = serde_json::json!(["Kuva", "Lippu"]);
+        invoice["attachment_passwords"] = serde_json::json!(["", password.unwrap_or_default()]);
+
+        let request = Multipart::new()
+            .text("data", &invoice.to_string())
+            .file(
+                "attachments",
+                "kuva.png",
+                include_bytes!("../../testdata/test.png"),
+            )
+            .file(
+                "attachments",
+                "lippu.pdf",
+                include_bytes!("../../testdata/receipt-password.pdf"),
+            )
+            .request("/invoices");
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), status, "{password:?}");
+        let body = super::body_bytes(response).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(!body.contains("salasana"));
+        if status == StatusCode::BAD_REQUEST {
+            assert!(body.contains("lippu.pdf"), "{body}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn create_invoice_converts_images() {
+    use image::ImageOutputFormat;
+
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+    let png = image::load_from_memory(include_bytes!("../../testdata/test.png")).unwrap();
+
+    for (filename, format) in [
+        ("kuitti.webp", ImageOutputFormat::WebP),
+        ("kuitti.tiff", ImageOutputFormat::Tiff),
+        ("kuitti.bmp", ImageOutputFormat::Bmp),
+    ] {
+        let mut contents = std::io::Cursor::new(Vec::new());
+        png.write_to(&mut contents, format).unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, contents.get_ref()).unwrap();
+        let attachment = crate::attachments::prepare(filename.into(), file, None).unwrap();
+        assert!([Format::Jpg, Format::Png].contains(&attachment.format));
+        assert_eq!(
+            attachment.filename,
+            format!("kuitti.{}", attachment.format.extension())
+        );
+
+        let request = Multipart::new()
+            .text("data", &invoice_with_attachments(&["Kuitti"]))
+            .file("attachments", filename, contents.get_ref())
+            .request("/invoices");
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED, "{filename}");
+    }
+}
+
+#[tokio::test]
+async fn create_invoice_warns_about_total_mismatch() {
+    // The receipt totals 13,27 €
+    for (unit_price, mismatch) in [(599, true), (1327, false)] {
+        // NOTE: separate data directories so that the receipt isn't a duplicate
+        let dir = tempfile::tempdir().unwrap();
+        let app = app().with_state(state(&dir).await);
+        let mut invoice = invoice_json();
+        invoice["attachment_descriptions"] = serde_json::json!(["Kuitti"]);
+        invoice["rows"] = serde_json::json!([
+            { "product": "Kahvi", "quantity": 1, "unit": "pkt", "unit_price": unit_price }
+        ]);
+
+        let request = Multipart::new()
+            .text("data", &invoice.to_string())
+            .file(
+                "attachments",
+                "kuitti.pdf",
+                include_bytes!("../../testdata/receipt.pdf"),
+            )
+            .request("/invoices");
+
+        let stored = submit(&app, request).await;
+        if mismatch {
+            assert_eq!(
+                stored.warnings,
+                vec![Warning::TotalMismatch {
+                    invoice_total: 599,
+                    attachments_total: 1327,
+                    attachments: vec![crate::receipt::AttachmentTotal {
+                        filename: "kuitti.pdf".into(),
+                        total: 1327,
+                    }],
+                }]
+            );
+        } else {
+            assert!(stored.warnings.is_empty());
+        }
+    }
+}
+
+#[cfg(not(feature = "email"))]
+#[tokio::test]
+async fn warnings_are_returned_in_a_header() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+    let mut invoice = invoice_json();
+    invoice["attachment_descriptions"] = serde_json::json!(["Kuitti"]);
+    invoice["rows"] = serde_json::json!([
+        { "product": "Kahvi", "quantity": 1, "unit": "pkt", "unit_price": 599 }
+    ]);
+    let request = || {
+        Multipart::new()
+            .text("data", &invoice.to_string())
+            .file(
+                "attachments",
+                "kuitti.pdf",
+                include_bytes!("../../testdata/receipt.pdf"),
+            )
+            .request("/invoices")
+    };
+
+    let first = app.clone().oneshot(request()).await.unwrap();
+    assert_eq!(first.status(), StatusCode::CREATED);
+    assert_eq!(
+        first.headers()["Invoice-Warnings"],
+        "total_mismatch; invoice_total=599; attachments_total=1327"
+    );
+    assert!(first.headers().get("Possible-Duplicate-Of").is_none());
+
+    let location = first.headers()["Location"].to_str().unwrap().to_owned();
+    let id = location.trim_start_matches("/invoices/");
+    let second = app.oneshot(request()).await.unwrap();
+    assert_eq!(
+        second.headers()["Invoice-Warnings"],
+        format!(
+            "total_mismatch; invoice_total=599; attachments_total=1327, possible_duplicate; invoice={id}"
+        )
+        .as_str()
+    );
+    assert_eq!(second.headers()["Possible-Duplicate-Of"], id);
+}
+
+#[test]
+fn attachments_with_the_same_filename_are_rendered() {
+    let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10"/></svg>"#;
+    let invoice = invoice_with_files(&[
+        ("kuva.png", include_bytes!("../../testdata/test.png")),
+        ("kuva.png", svg),
+    ]);
+    assert_eq!(
+        invoice.attachments[1].format,
+        crate::attachments::Format::Svg
+    );
+
+    let pdf = generate_pdf(
+        invoice,
+        Uuid::nil(),
+        datetime!(2024-10-19 12:00 UTC),
+        false,
+        &Signer::default(),
+        None,
+    )
+    .unwrap();
+    let document = lopdf::Document::load_mem(&pdf).unwrap();
+    assert_eq!(document.get_pages().len(), 3);
+}
+
+#[test]
+fn originals_are_attached_as_uploaded() {
+    let mut invoice =
+        invoice_with_files(&[("kuitti.png", include_bytes!("../../testdata/test.png"))]);
+    let mut original = tempfile::NamedTempFile::new().unwrap();
+    std::io::Write::write_all(&mut original, include_bytes!("../../testdata/test.jpg")).unwrap();
+    invoice.attachments[0].original = Some(std::sync::Arc::new(original));
+
+    let pdf = generate_pdf(
+        invoice,
+        Uuid::nil(),
+        datetime!(2024-10-19 12:00 UTC),
+        false,
+        &Signer::default(),
+        None,
+    )
+    .unwrap();
+
+    let document = lopdf::Document::load_mem(&pdf).unwrap();
+    let names = document.catalog().unwrap().get(b"Names").unwrap();
+    let names = document.dereference(names).unwrap().1.as_dict().unwrap();
+    let files = names.get(b"EmbeddedFiles").unwrap().as_dict().unwrap();
+    let files = files.get(b"Names").unwrap().as_array().unwrap();
+    assert_eq!(files[0].as_str().unwrap(), b"kuitti.jpg");
+
+    let spec = document
+        .get_dictionary(files[1].as_reference().unwrap())
+        .unwrap();
+    let ef = spec.get(b"EF").unwrap().as_dict().unwrap();
+    let stream = document
+        .get_object(ef.get(b"F").unwrap().as_reference().unwrap())
+        .unwrap()
+        .as_stream()
+        .unwrap();
+    assert_eq!(
+        stream
+            .decompressed_content()
+            .unwrap_or(stream.content.clone()),
+        include_bytes!("../../testdata/test.jpg")
+    );
+    let mime = stream.dict.get(b"Subtype").unwrap().as_name().unwrap();
+    assert_eq!(mime, b"image/jpeg");
+}
+
+#[test]
+fn generated_pdfs_are_reproducible() {
+    let files: &[(&str, &[u8])] = &[
+        ("kuitti.pdf", include_bytes!("../../testdata/test.pdf")),
+        ("kuva.png", include_bytes!("../../testdata/test.png")),
+    ];
+    let id = Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef);
+    let created_at = datetime!(2024-10-19 12:00 UTC);
+
+    for pdf_a in [false, true] {
+        let generate = |id| {
+            generate_pdf(
+                invoice_with_files(files),
+                id,
+                created_at,
+                pdf_a,
+                &Signer::default(),
+                None,
+            )
+            .unwrap()
+        };
+        let pdf = generate(id);
+        assert_eq!(pdf, generate(id));
+        assert_ne!(pdf, generate(Uuid::nil()));
+
+        let document = lopdf::Document::load_mem(&pdf).unwrap();
+        let file_id = document.trailer.get(b"ID").unwrap().as_array().unwrap();
+        assert_eq!(file_id[0].as_str().unwrap(), id.as_bytes());
+
+        let info = document
+            .trailer
+            .get(b"Info")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let info = document.get_dictionary(info).unwrap();
+        for (key, value) in [
+            ("Title", "Kahvia"),
+            ("Subject", "Kahvia kerhohuoneelle"),
+            ("Keywords", "lasku, 01234567-89ab-cdef-0123-456789abcdef"),
+            ("CreationDate", "D:20241019120000Z"),
+        ] {
+            assert_eq!(
+                info.get(key.as_bytes()).unwrap().as_str().unwrap(),
+                value.as_bytes(),
+                "{key}"
+            );
+        }
+    }
+}
+
+#[test]
+fn templates_are_rendered_on_the_creation_date() {
+    let template = crate::pdfgen::Template {
+        path: "/paiva.typ",
+        source:
+            "#assert.eq(datetime.today(), datetime(year: 2024, month: 10, day: 19))\n\
+                 #assert.eq(datetime.today(offset: 14), datetime(year: 2024, month: 10, day: 20))\n",
+    };
+    crate::pdfgen::compile_template(
+        template,
+        typst::foundations::Value::None,
+        None,
+        datetime!(2024-10-19 12:00 UTC),
+        &[],
+    )
+    .unwrap();
+}
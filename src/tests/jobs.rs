@@ -0,0 +1,152 @@
+use super::{body_bytes, invoice_json, json, state, Multipart};
+use crate::api::app;
+use crate::error::Error;
+use crate::jobs::{Job, RenderPool, Stage};
+use axum::body::Body;
+use axum::http::{request::Request, StatusCode};
+use tower::ServiceExt;
+
+fn request(uri: &str) -> Request<Body> {
+    Multipart::new()
+        .text("data", &invoice_json().to_string())
+        .request(uri)
+}
+
+#[tokio::test]
+async fn async_submissions_can_be_followed() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let response = app
+        .clone()
+        .oneshot(request("/invoices?async=true"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let location = response.headers()["Location"].to_str().unwrap().to_owned();
+    let job: Job = json(response).await;
+    assert_eq!(location, format!("/jobs/{}", job.id));
+
+    let mut job = job;
+    for _ in 0..100 {
+        if matches!(job.stage, Stage::Done | Stage::Failed) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(&location)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        job = json(response).await;
+    }
+
+    assert_eq!(job.stage, Stage::Done, "{:?}", job.error);
+    let invoice = job.invoice.unwrap();
+    assert!(storage.get(invoice).await.is_ok());
+
+    // The submitter downloads the PDF with the job id, without the admin token
+    let pdf = job.pdf.unwrap();
+    assert_eq!(pdf, format!("/jobs/{}/pdf", job.id));
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&pdf).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/pdf");
+    assert!(body_bytes(response).await.starts_with(b"%PDF"));
+
+    let missing = app
+        .oneshot(
+            Request::builder()
+                .uri(format!("/jobs/{}", uuid::Uuid::new_v4()))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn full_queue_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut state = state(&dir).await;
+    state.render_pool = RenderPool::new(1, 0);
+    let slot = state.render_pool.reserve().unwrap();
+    let app = app().with_state(state);
+
+    for uri in ["/invoices", "/invoices?async=true"] {
+        let response = app.clone().oneshot(request(uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["Retry-After"], "10");
+    }
+
+    drop(slot);
+    let response = app.oneshot(request("/invoices")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn renders_run_on_the_pool() {
+    let pool = RenderPool::new(1, 1);
+    let first = pool.reserve().unwrap();
+    let second = pool.reserve().unwrap();
+    assert!(matches!(pool.reserve(), Err(Error::RenderQueueFull)));
+
+    let name = first
+        .render(|| Ok(std::thread::current().name().map(String::from)))
+        .await
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("render-0"));
+
+    // A panicking render fails the request but not the thread
+    let panicked = second.render::<(), _>(|| panic!("bug")).await;
+    assert!(matches!(panicked, Err(Error::RenderFailed)));
+    assert_eq!(first.render(|| Ok(1)).await.unwrap(), 1);
+
+    drop(first);
+    assert!(pool.reserve().is_ok());
+}
+
+#[tokio::test]
+async fn async_retries_get_the_same_job() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+    let request = || {
+        let mut request = request("/invoices?async=true");
+        request
+            .headers_mut()
+            .insert("Idempotency-Key", "avain".parse().unwrap());
+        request
+    };
+
+    let first = app.clone().oneshot(request()).await.unwrap();
+    assert_eq!(first.status(), StatusCode::ACCEPTED);
+    let first: Job = json(first).await;
+    let retry = app.oneshot(request()).await.unwrap();
+    assert_eq!(retry.status(), StatusCode::ACCEPTED);
+    assert_eq!(retry.headers()["Idempotent-Replayed"], "true");
+    let retry: Job = json(retry).await;
+    assert_eq!(retry.id, first.id);
+
+    for _ in 0..100 {
+        if !storage.list().await.unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(storage.list().await.unwrap().len(), 1);
+}
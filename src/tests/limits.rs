@@ -0,0 +1,135 @@
+use super::invoices::invoice_with_attachments;
+use super::{invoice_json, json, state, Multipart};
+use crate::api::app;
+use crate::attachments::prepare;
+use crate::error::Error;
+use crate::jobs::RenderPool;
+use axum::http::StatusCode;
+use lopdf::{dictionary, Document, Object};
+use std::io::Write;
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// A document with the given number of empty pages
+fn long_document(pages: usize) -> Vec<u8> {
+    let mut document = Document::with_version("1.7");
+    let pages_id = document.new_object_id();
+    let kids = (0..pages)
+        .map(|_| {
+            document
+                .add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+                })
+                .into()
+        })
+        .collect::<Vec<Object>>();
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids,
+            "Count" => pages as i64,
+        }),
+    );
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+
+    let mut data = Vec::new();
+    document.save_to(&mut data).unwrap();
+    data
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[tokio::test]
+async fn invoices_with_too_many_pages_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .text("data", &invoice_with_attachments(&["Pitkä liite"]))
+        .file("attachments", "pitka.pdf", &long_document(250))
+        .request("/invoices");
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let response: ErrorResponse = json(response).await;
+    assert_eq!(response.error, Error::TooManyPages(200).to_string());
+}
+
+#[tokio::test]
+async fn invoices_with_too_many_rows_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let mut invoice = invoice_json();
+    let row = invoice["rows"][0].clone();
+    invoice["rows"] = serde_json::Value::Array(vec![row; 101]);
+    let request = Multipart::new()
+        .text("data", &invoice.to_string())
+        .request("/invoices");
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn gifs_are_measured_before_decoding() {
+    // NOTE: a 50000x50000 logical screen without any frames
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(b"GIF89a\x50\xc3\x50\xc3\x00\x00\x00\x3b")
+        .unwrap();
+
+    let result = prepare("valtava.gif".into(), file, None);
+    assert!(matches!(
+        result,
+        Err(Error::ImageTooLarge(filename, 100_000_000)) if filename == "valtava.gif"
+    ));
+}
+
+#[test]
+fn images_with_too_many_pixels_are_refused() {
+    // NOTE: only the header of the 50000x50000 image is valid
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(include_bytes!("../../testdata/huge.png"))
+        .unwrap();
+
+    let result = prepare("valtava.png".into(), file, None);
+    assert!(matches!(
+        result,
+        Err(Error::ImageTooLarge(filename, 100_000_000)) if filename == "valtava.png"
+    ));
+}
+
+#[tokio::test]
+async fn slow_renders_time_out() {
+    let pool = RenderPool::new(1, 0).with_timeout(Duration::from_millis(50));
+    let slot = pool.reserve().unwrap();
+
+    let (done, finished) = tokio::sync::oneshot::channel();
+    let result = slot
+        .render(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            let _ = done.send(());
+            Ok(())
+        })
+        .await;
+    assert!(matches!(result, Err(Error::RenderTimeout(0))));
+    assert_eq!(
+        Error::RenderTimeout(0).status(),
+        StatusCode::GATEWAY_TIMEOUT
+    );
+
+    // The render keeps its place until the thread is done with it
+    drop(slot);
+    assert!(matches!(pool.reserve(), Err(Error::RenderQueueFull)));
+    finished.await.unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    assert!(pool.reserve().is_ok());
+}
@@ -0,0 +1,287 @@
+use super::{admin_get, invoice_json, json, state, Multipart, MESSAGE_ID};
+use crate::api::app;
+use crate::attachments::Format;
+use crate::storage::{DeliveryStatus, Draft, Storage, StoredInvoice};
+use axum::body::Body;
+use axum::http::request::Request;
+use axum::http::StatusCode;
+use axum::Router;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+/// A current timestamp and a new token signed with the test signing key
+fn signature() -> serde_json::Value {
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp().to_string();
+    let token = Uuid::new_v4().simple().to_string();
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"test-signing-key").unwrap();
+    mac.update(timestamp.as_bytes());
+    mac.update(token.as_bytes());
+    serde_json::json!({
+        "timestamp": timestamp,
+        "token": token,
+        "signature": hex::encode(mac.finalize().into_bytes()),
+    })
+}
+
+/// The recorded webhook signed again, since its signature has expired
+fn signed(payload: &str) -> String {
+    let mut payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+    payload["signature"] = signature();
+    payload.to_string()
+}
+
+async fn stored_invoice(storage: &Storage) -> StoredInvoice {
+    let invoice = serde_json::from_value(invoice_json()).unwrap();
+
+    let mut stored = StoredInvoice::new(invoice);
+    stored.delivery.message_id = Some(MESSAGE_ID.into());
+    storage.insert(&stored).await.unwrap();
+    stored
+}
+
+async fn replay(app: &Router, payload: &str) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/mailgun/webhooks")
+                .header("Content-Type", "application/json")
+                .body(Body::from(payload.to_owned()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn webhook_records_delivery_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let stored = stored_invoice(&state.storage).await;
+    let app = app().with_state(state);
+
+    for payload in [
+        include_str!("../../testdata/mailgun/delivered.json"),
+        include_str!("../../testdata/mailgun/bounced.json"),
+        // Events may arrive out of order
+        include_str!("../../testdata/mailgun/temporary_failure.json"),
+    ] {
+        assert_eq!(replay(&app, &signed(payload)).await, StatusCode::OK);
+    }
+
+    let response = app
+        .oneshot(admin_get(&format!("/invoices/{}", stored.id)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let invoice: StoredInvoice = json(response).await;
+    assert_eq!(invoice.delivery.events.len(), 3);
+    assert_eq!(
+        invoice.delivery.recipients["rahastonhoitaja@example.com"],
+        DeliveryStatus::Delivered
+    );
+    assert_eq!(
+        invoice.delivery.recipients["matti@example.org"],
+        DeliveryStatus::Bounced
+    );
+}
+
+#[tokio::test]
+async fn webhook_rejects_invalid_signature() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let stored = stored_invoice(&state.storage).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let delivered = include_str!("../../testdata/mailgun/delivered.json");
+    let mut payload: serde_json::Value = serde_json::from_str(&signed(delivered)).unwrap();
+    payload["signature"]["token"] = "0000".into();
+    assert_eq!(
+        replay(&app, &payload.to_string()).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // The recorded signature is valid but too old
+    assert_eq!(replay(&app, delivered).await, StatusCode::UNAUTHORIZED);
+
+    let invoice = storage.get(stored.id).await.unwrap();
+    assert!(invoice.delivery.events.is_empty());
+}
+
+#[tokio::test]
+async fn webhooks_cant_be_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let stored = stored_invoice(&state.storage).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let payload = signed(include_str!("../../testdata/mailgun/delivered.json"));
+    assert_eq!(replay(&app, &payload).await, StatusCode::OK);
+    assert_eq!(replay(&app, &payload).await, StatusCode::UNAUTHORIZED);
+
+    let invoice = storage.get(stored.id).await.unwrap();
+    assert_eq!(invoice.delivery.events.len(), 1);
+}
+
+#[tokio::test]
+async fn inbound_email_creates_draft() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let signature = signature();
+    let request = Multipart::new()
+        .text("timestamp", signature["timestamp"].as_str().unwrap())
+        .text("token", signature["token"].as_str().unwrap())
+        .text("signature", signature["signature"].as_str().unwrap())
+        .text("sender", "matti@example.org")
+        .text("from", "Matti Meikäläinen <matti@example.org>")
+        .text("subject", "Kahvikuitti")
+        .text("body-plain", "Ostin kahvia kerhohuoneelle")
+        .text("attachment-count", "2")
+        .file(
+            "attachment-1",
+            "kuitti.png",
+            include_bytes!("../../testdata/test.png"),
+        )
+        .file("attachment-2", "kutsu.ics", b"BEGIN:VCALENDAR")
+        .request("/mailgun/inbound");
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let entry = std::fs::read_dir(dir.path().join("drafts"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.extension().is_some_and(|e| e == "json"))
+        .unwrap();
+    let id = entry.file_stem().unwrap().to_str().unwrap();
+
+    let uri = format!("/drafts/{id}");
+    let response = app
+        .clone()
+        .oneshot(Request::builder().uri(&uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app.oneshot(admin_get(&uri)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let draft: Draft = json(response).await;
+    assert_eq!(draft.recipient_name, "Matti Meikäläinen");
+    assert_eq!(draft.recipient_email, "matti@example.org");
+    assert_eq!(draft.description, "Ostin kahvia kerhohuoneelle");
+    assert_eq!(draft.attachments, vec!["kuitti.png"]);
+
+    let attachments = storage.draft_attachments(draft.id).await.unwrap();
+    assert_eq!(attachments[0].format, Format::Png);
+    let stored = image::load_from_memory(&std::fs::read(attachments[0].path()).unwrap()).unwrap();
+    let original = image::load_from_memory(include_bytes!("../../testdata/test.png")).unwrap();
+    assert_eq!(stored.to_rgba8(), original.to_rgba8());
+}
+
+#[tokio::test]
+async fn drafts_are_shown_with_their_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let draft = Draft {
+        id: Uuid::new_v4(),
+        created_at: OffsetDateTime::now_utc(),
+        recipient_name: "Matti Meikäläinen".into(),
+        recipient_email: "matti@example.org".into(),
+        subject: "Kahvikuitti".into(),
+        description: String::new(),
+        attachments: Vec::new(),
+        token_hash: Some(hex::encode(Sha256::digest("salainen"))),
+    };
+    state.storage.insert_draft(&draft, &[]).await.unwrap();
+    let app = app().with_state(state);
+
+    let get = |token: &str| {
+        Request::builder()
+            .uri(format!("/drafts/{}?token={token}", draft.id))
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(get("arvaus")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = app.oneshot(get("salainen")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let shown: Draft = json(response).await;
+    assert_eq!(shown.recipient_email, "matti@example.org");
+    assert_eq!(shown.token_hash, None);
+}
+
+#[tokio::test]
+async fn drafts_are_submitted_with_their_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = state(&dir).await;
+    let draft = Draft {
+        id: Uuid::new_v4(),
+        created_at: OffsetDateTime::now_utc(),
+        recipient_name: "Matti Meikäläinen".into(),
+        recipient_email: "matti@example.org".into(),
+        subject: "Kahvikuitti".into(),
+        description: String::new(),
+        attachments: Vec::new(),
+        token_hash: Some(hex::encode(Sha256::digest("salainen"))),
+    };
+    state.storage.insert_draft(&draft, &[]).await.unwrap();
+    let storage = state.storage.clone();
+    let app = app().with_state(state);
+
+    let submit = |token: Option<&str>| {
+        let mut invoice = invoice_json();
+        invoice["draft"] = serde_json::json!(draft.id);
+        if let Some(token) = token {
+            invoice["draft_token"] = serde_json::json!(token);
+        }
+        Multipart::new()
+            .text("data", &invoice.to_string())
+            .request("/invoices")
+    };
+    for token in [None, Some("arvaus")] {
+        let response = app.clone().oneshot(submit(token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(storage.get_draft(draft.id).await.is_ok());
+    }
+
+    let response = app.oneshot(submit(Some("salainen"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let stored: StoredInvoice = json(response).await;
+    assert_eq!(stored.invoice.draft, Some(draft.id));
+    assert!(storage.get_draft(draft.id).await.is_err());
+}
+
+#[tokio::test]
+async fn unsigned_inbound_email_is_rejected_before_its_attachments() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    // The signature after the attachment doesn't count
+    let signature = signature();
+    let request = Multipart::new()
+        .text("sender", "matti@example.org")
+        .file(
+            "attachment-1",
+            "kuitti.png",
+            include_bytes!("../../testdata/test.png"),
+        )
+        .text("timestamp", signature["timestamp"].as_str().unwrap())
+        .text("token", signature["token"].as_str().unwrap())
+        .text("signature", signature["signature"].as_str().unwrap())
+        .request("/mailgun/inbound");
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!dir.path().join("drafts").exists());
+}
@@ -0,0 +1,363 @@
+use crate::error::Error;
+use crate::merge::{embed_files, merge_pdf, save, Bookmark, EmbeddedFile, MergeInput};
+use lopdf::{dictionary, Document, Object};
+
+fn document() -> Document {
+    Document::load_mem(include_bytes!("../../testdata/test.pdf")).unwrap()
+}
+
+fn input(name: &str, document: Document, bookmarks: &[(&str, usize)]) -> MergeInput {
+    MergeInput {
+        name: name.into(),
+        document,
+        bookmarks: bookmarks
+            .iter()
+            .map(|&(title, page)| Bookmark {
+                title: title.into(),
+                page,
+            })
+            .collect(),
+    }
+}
+
+/// A single page document with a text field, a link and an outline
+fn form_document(field: &str) -> Document {
+    let mut document = Document::with_version("1.7");
+    let pages_id = document.new_object_id();
+    let page_id = document.new_object_id();
+
+    let field_id = document.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Widget",
+        "FT" => "Tx",
+        "T" => Object::string_literal(field),
+        "Rect" => vec![10.into(), 10.into(), 100.into(), 30.into()],
+        "P" => page_id,
+    });
+    let link_id = document.add_object(dictionary! {
+        "Type" => "Annot",
+        "Subtype" => "Link",
+        "Rect" => vec![10.into(), 40.into(), 100.into(), 60.into()],
+        "Dest" => vec![page_id.into(), "Fit".into()],
+    });
+    let content_id = document.add_object(lopdf::Stream::new(dictionary! {}, Vec::new()));
+    document.objects.insert(
+        page_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Annots" => vec![field_id.into(), link_id.into()],
+        }),
+    );
+    // NOTE: the media box is inherited from the page tree
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }),
+    );
+
+    let outlines_id = document.new_object_id();
+    let item_id = document.add_object(dictionary! {
+        "Title" => Object::string_literal("Sivu 1"),
+        "Parent" => outlines_id,
+        "Dest" => vec![page_id.into(), "Fit".into()],
+    });
+    document.objects.insert(
+        outlines_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Outlines",
+            "First" => item_id,
+            "Last" => item_id,
+            "Count" => 1,
+        }),
+    );
+
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+        "Outlines" => outlines_id,
+        "AcroForm" => dictionary! {
+            "Fields" => vec![field_id.into()],
+            "NeedAppearances" => true,
+        },
+    });
+    document.trailer.set("Root", catalog_id);
+    document
+}
+
+/// Titles and target pages of the items on one level of an outline
+fn outline_items(document: &Document, parent: &lopdf::Dictionary) -> Vec<(String, u32)> {
+    let pages = document
+        .get_pages()
+        .into_iter()
+        .map(|(number, id)| (id, number))
+        .collect::<std::collections::BTreeMap<_, _>>();
+
+    let mut items = Vec::new();
+    let mut next = parent.get(b"First").and_then(Object::as_reference).ok();
+    while let Some(id) = next {
+        let item = document.get_dictionary(id).unwrap();
+        let title = item.get(b"Title").unwrap().as_str().unwrap();
+        let dest = item.get(b"Dest").unwrap().as_array().unwrap();
+        items.push((
+            String::from_utf8_lossy(title).into_owned(),
+            pages[&dest[0].as_reference().unwrap()],
+        ));
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+    items
+}
+
+#[test]
+fn embedded_files_are_listed_in_the_catalog() {
+    let mut merged = merge_pdf(vec![
+        input("a.pdf", document(), &[]),
+        input("b.pdf", document(), &[]),
+    ])
+    .unwrap();
+    embed_files(
+        &mut merged,
+        vec![EmbeddedFile {
+            name: "kuitti.jpg".into(),
+            description: "Alkuperäinen rajaamaton kuva".into(),
+            mime: "image/jpeg",
+            relationship: "Source",
+            data: include_bytes!("../../testdata/test.jpg").to_vec(),
+        }],
+    )
+    .unwrap();
+
+    let merged = Document::load_mem(&save(merged).unwrap()).unwrap();
+    assert_eq!(merged.get_pages().len(), 2 * document().get_pages().len());
+
+    let names = merged.catalog().unwrap().get(b"Names").unwrap();
+    let names = merged.dereference(names).unwrap().1.as_dict().unwrap();
+    let files = names.get(b"EmbeddedFiles").unwrap().as_dict().unwrap();
+    let files = files.get(b"Names").unwrap().as_array().unwrap();
+    assert_eq!(files[0].as_str().unwrap(), b"kuitti.jpg");
+
+    let spec = merged
+        .get_dictionary(files[1].as_reference().unwrap())
+        .unwrap();
+    let ef = spec.get(b"EF").unwrap().as_dict().unwrap();
+    let stream = merged
+        .get_object(ef.get(b"F").unwrap().as_reference().unwrap())
+        .unwrap()
+        .as_stream()
+        .unwrap();
+    assert_eq!(
+        stream
+            .decompressed_content()
+            .unwrap_or(stream.content.clone()),
+        include_bytes!("../../testdata/test.jpg")
+    );
+}
+
+#[test]
+fn bookmarks_are_added_for_each_document() {
+    let invoice = merge_pdf(vec![
+        input("a.pdf", document(), &[]),
+        input("b.pdf", document(), &[]),
+    ])
+    .unwrap();
+    let invoice = Document::load_mem(&save(invoice).unwrap()).unwrap();
+    let pages = invoice.get_pages().len();
+
+    let merged = merge_pdf(vec![
+        input(
+            "invoice.pdf",
+            invoice,
+            &[("Lasku", 0), ("kuva.png", pages - 1)],
+        ),
+        input("a.pdf", form_document("summa"), &[("a.pdf", 0)]),
+    ])
+    .unwrap();
+    let merged = Document::load_mem(&save(merged).unwrap()).unwrap();
+    assert_eq!(merged.get_pages().len(), pages + 1);
+
+    let catalog = merged.catalog().unwrap();
+    let outlines = merged
+        .get_dictionary(catalog.get(b"Outlines").unwrap().as_reference().unwrap())
+        .unwrap();
+    assert_eq!(
+        outline_items(&merged, outlines),
+        vec![
+            ("Lasku".to_string(), 1),
+            ("kuva.png".to_string(), pages as u32),
+            ("a.pdf".to_string(), pages as u32 + 1),
+        ]
+    );
+
+    // The outline of the attachment is kept under its bookmark
+    let last = outlines.get(b"Last").unwrap().as_reference().unwrap();
+    let attachment = merged.get_dictionary(last).unwrap();
+    assert_eq!(
+        outline_items(&merged, attachment),
+        vec![("Sivu 1".to_string(), pages as u32 + 1)]
+    );
+}
+
+#[test]
+fn form_fields_and_annotations_are_kept() {
+    let merged = merge_pdf(vec![
+        input("a.pdf", form_document("summa"), &[]),
+        input("b.pdf", form_document("summa"), &[]),
+    ])
+    .unwrap();
+    let merged = Document::load_mem(&save(merged).unwrap()).unwrap();
+
+    let form = merged
+        .catalog()
+        .unwrap()
+        .get(b"AcroForm")
+        .unwrap()
+        .as_dict()
+        .unwrap();
+    assert!(form.get(b"NeedAppearances").unwrap().as_bool().unwrap());
+
+    // The fields have the same name, so they are placed under parents of their own
+    let fields = form.get(b"Fields").unwrap().as_array().unwrap();
+    assert_eq!(fields.len(), 2);
+    for (i, field) in fields.iter().enumerate() {
+        let parent_id = field.as_reference().unwrap();
+        let parent = merged.get_dictionary(parent_id).unwrap();
+        assert_eq!(
+            parent.get(b"T").unwrap().as_str().unwrap(),
+            format!("liite{i}").as_bytes()
+        );
+        let kid = parent.get(b"Kids").unwrap().as_array().unwrap()[0]
+            .as_reference()
+            .unwrap();
+        let kid = merged.get_dictionary(kid).unwrap();
+        assert_eq!(
+            kid.get(b"Parent").unwrap().as_reference().unwrap(),
+            parent_id
+        );
+    }
+
+    for (_, page_id) in merged.get_pages() {
+        let page = merged.get_dictionary(page_id).unwrap();
+        assert!(page.get(b"MediaBox").is_ok());
+        let annots = page.get(b"Annots").unwrap().as_array().unwrap();
+        assert_eq!(annots.len(), 2);
+        let link = merged
+            .get_dictionary(annots[1].as_reference().unwrap())
+            .unwrap();
+        let dest = link.get(b"Dest").unwrap().as_array().unwrap();
+        assert_eq!(dest[0].as_reference().unwrap(), page_id);
+    }
+}
+
+/// A form document whose link goes to the named destination `alku`, from the
+/// old style dictionary or the name tree
+fn named_dest_document(name_tree: bool) -> Document {
+    let mut document = form_document("nimi");
+    let page_id = *document.get_pages().values().next().unwrap();
+    let annots = document
+        .get_dictionary(page_id)
+        .unwrap()
+        .get(b"Annots")
+        .unwrap()
+        .as_array()
+        .unwrap()
+        .clone();
+    let dest = Object::Array(vec![page_id.into(), "Fit".into()]);
+    let (link_target, catalog_entry) = if name_tree {
+        (
+            (
+                "A",
+                Object::Dictionary(
+                    dictionary! { "S" => "GoTo", "D" => Object::string_literal("alku") },
+                ),
+            ),
+            (
+                "Names",
+                dictionary! {
+                    "Dests" => dictionary! {
+                        "Names" => vec![Object::string_literal("alku"), dest],
+                    },
+                },
+            ),
+        )
+    } else {
+        (
+            ("Dest", Object::Name(b"alku".to_vec())),
+            ("Dests", dictionary! { "alku" => dest }),
+        )
+    };
+
+    let link = document
+        .get_dictionary_mut(annots[1].as_reference().unwrap())
+        .unwrap();
+    link.remove(b"Dest");
+    link.set(link_target.0, link_target.1);
+    document
+        .catalog_mut()
+        .unwrap()
+        .set(catalog_entry.0, catalog_entry.1);
+    document
+}
+
+#[test]
+fn named_destinations_are_prefixed_per_document() {
+    let merged = merge_pdf(vec![
+        input("a.pdf", named_dest_document(false), &[]),
+        input("b.pdf", named_dest_document(true), &[]),
+    ])
+    .unwrap();
+    let merged = Document::load_mem(&save(merged).unwrap()).unwrap();
+
+    let dests = merged
+        .catalog()
+        .unwrap()
+        .get(b"Dests")
+        .unwrap()
+        .as_dict()
+        .unwrap();
+    assert_eq!(dests.len(), 2);
+    for (i, (_, page_id)) in merged.get_pages().into_iter().enumerate() {
+        let name = format!("liite{i}.alku");
+        let dest = dests.get(name.as_bytes()).unwrap().as_array().unwrap();
+        assert_eq!(dest[0].as_reference().unwrap(), page_id);
+
+        let page = merged.get_dictionary(page_id).unwrap();
+        let annots = page.get(b"Annots").unwrap().as_array().unwrap();
+        let link = merged
+            .get_dictionary(annots[1].as_reference().unwrap())
+            .unwrap();
+        let target = match link.get(b"A") {
+            Ok(action) => action.as_dict().unwrap().get(b"D").unwrap(),
+            Err(_) => link.get(b"Dest").unwrap(),
+        };
+        assert_eq!(target.as_name().unwrap(), name.as_bytes());
+    }
+}
+
+#[test]
+fn invalid_documents_are_reported_by_name() {
+    let mut empty = Document::with_version("1.7");
+    let pages_id = empty.add_object(dictionary! {
+        "Type" => "Pages",
+        "Kids" => Vec::<Object>::new(),
+        "Count" => 0,
+    });
+    let catalog_id = empty.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    empty.trailer.set("Root", catalog_id);
+
+    let result = merge_pdf(vec![
+        input("invoice.pdf", document(), &[]),
+        input("tyhjä.pdf", empty, &[]),
+    ]);
+    assert!(matches!(result, Err(Error::InvalidAttachment(name, _)) if name == "tyhjä.pdf"));
+
+    let result = merge_pdf(vec![input("rikki.pdf", Document::with_version("1.7"), &[])]);
+    assert!(matches!(result, Err(Error::InvalidAttachment(name, _)) if name == "rikki.pdf"));
+}
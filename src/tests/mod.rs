@@ -1,9 +1,189 @@
 use crate::api::app;
+#[cfg(feature = "email")]
+use crate::mailgun::MailgunClient;
+use crate::state::State;
+use crate::storage::Storage;
 use axum::body::Body;
 use axum::http::request::Request;
 use axum::http::StatusCode;
+use axum::response::Response;
 use tower::ServiceExt;
 
+#[cfg(feature = "email")]
+mod mailgun;
+
+mod approvals;
+mod attachments;
+mod audit;
+mod blobs;
+mod diagnostics;
+mod duplicates;
+mod encryption;
+mod gdpr;
+mod idempotency;
+mod invoices;
+mod jobs;
+mod limits;
+mod merge;
+mod pdfa;
+mod receipts;
+mod signing;
+mod template_data;
+
+#[cfg(feature = "email")]
+const MESSAGE_ID: &str = "20241019120000.1a2b3c4d5e6f7a8b@laskutus.example.com";
+
+const ADMIN_TOKEN: &str = "admin-token";
+
+/// State with a temporary data directory and a mock mailgun
+async fn state(dir: &tempfile::TempDir) -> State {
+    State {
+        #[cfg(feature = "email")]
+        mailgun_client: MailgunClient::from(crate::MailgunConfig {
+            url: mock_mailgun().await,
+            user: "api".into(),
+            password: "password".into(),
+            to: "Rahastonhoitaja <rahastonhoitaja@example.com>".into(),
+            from: "noreply@laskutus.example.com".into(),
+            webhook_signing_key: Some("test-signing-key".into()),
+        }),
+        storage: Storage::new(dir.path()).with_keyring(crate::storage::Keyring::generate()),
+        audit: crate::audit::AuditLog::new(dir.path()),
+        signer: crate::signing::Signer::default(),
+        admin_token: crate::auth::AdminToken::new(Some(ADMIN_TOKEN)),
+        idempotency: crate::idempotency::Idempotency::new(
+            crate::idempotency::MemoryStore::default(),
+            time::Duration::hours(24),
+        ),
+        render_pool: crate::jobs::RenderPool::new(2, 8),
+        jobs: Default::default(),
+        for_garde: (),
+    }
+}
+
+/// Start a server that accepts every message like the mailgun API
+#[cfg(feature = "email")]
+async fn mock_mailgun() -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = axum::Router::new().route(
+        "/messages",
+        axum::routing::post(|| async {
+            axum::Json(serde_json::json!({
+                "id": format!("<{MESSAGE_ID}>"),
+                "message": "Queued. Thank you.",
+            }))
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}/messages")
+}
+
+fn invoice_json() -> serde_json::Value {
+    serde_json::json!({
+        "recipient_name": "Matti Meikäläinen",
+        "recipient_email": "matti@example.org",
+        "address": { "street": "Konemiehentie 2", "city": "Espoo", "zip": "02150" },
+        "bank_account_number": "FI2112345600000785",
+        "subject": "Kahvia",
+        "description": "Kahvia kerhohuoneelle",
+        "phone_number": "+358401234567",
+        "attachment_descriptions": [],
+        "rows": [{ "product": "Kahvi", "quantity": 2, "unit": "pkt", "unit_price": 599 }]
+    })
+}
+
+/// An invoice with the given files prepared as its attachments
+fn invoice_with_files(files: &[(&str, &[u8])]) -> crate::api::invoices::Invoice {
+    let mut invoice: crate::api::invoices::Invoice =
+        serde_json::from_value(invoice_json()).unwrap();
+    for (filename, contents) in files {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, contents).unwrap();
+        invoice
+            .attachments
+            .push(crate::attachments::prepare(filename.to_string(), file, None).unwrap());
+        invoice.attachment_descriptions.push("Kuitti".into());
+    }
+    invoice
+}
+
+/// Builder for multipart/form-data request bodies
+struct Multipart {
+    body: Vec<u8>,
+}
+
+impl Multipart {
+    const BOUNDARY: &'static str = "laskugeneraattori";
+
+    fn new() -> Self {
+        Self { body: Vec::new() }
+    }
+
+    fn text(mut self, name: &str, value: &str) -> Self {
+        self.body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n",
+                Self::BOUNDARY
+            )
+            .as_bytes(),
+        );
+        self
+    }
+
+    fn file(mut self, name: &str, filename: &str, contents: &[u8]) -> Self {
+        self.body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\n\r\n",
+                Self::BOUNDARY
+            )
+            .as_bytes(),
+        );
+        self.body.extend_from_slice(contents);
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    fn request(mut self, uri: &str) -> Request<Body> {
+        self.body
+            .extend_from_slice(format!("--{}--\r\n", Self::BOUNDARY).as_bytes());
+
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", Self::BOUNDARY),
+            )
+            // Needed by the rate limiter
+            .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                [127, 0, 0, 1],
+                1234,
+            ))))
+            .body(Body::from(self.body))
+            .unwrap()
+    }
+}
+
+/// A GET request authorized with the admin token
+fn admin_get(uri: &str) -> Request<Body> {
+    Request::builder()
+        .uri(uri)
+        .header("Authorization", format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn body_bytes(response: Response) -> axum::body::Bytes {
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+}
+
+async fn json<T: serde::de::DeserializeOwned>(response: Response) -> T {
+    serde_json::from_slice(&body_bytes(response).await).unwrap()
+}
+
 #[tokio::test]
 async fn health() {
     let app = app().with_state(crate::state::new().await);
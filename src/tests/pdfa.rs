@@ -0,0 +1,98 @@
+use super::invoice_with_files;
+use crate::api::invoices::generate_pdf;
+use crate::pdfa::check;
+use crate::signing::Signer;
+use lopdf::{Document, Object};
+use time::macros::datetime;
+use uuid::Uuid;
+
+/// Generate a PDF/A document of an invoice with the given attachments
+fn generate(files: &[(&str, &[u8])]) -> Document {
+    let pdf = generate_pdf(
+        invoice_with_files(files),
+        Uuid::nil(),
+        datetime!(2024-10-19 12:00 UTC),
+        true,
+        &Signer::default(),
+        None,
+    )
+    .unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.7\n%"));
+    assert!(pdf[10..14].iter().all(|b| *b > 127));
+    assert_eq!(pdf[14], b'\n');
+    Document::load_mem(&pdf).unwrap()
+}
+
+#[test]
+fn invoices_conform_to_pdf_a() {
+    let document = generate(&[
+        ("kuitti.pdf", include_bytes!("../../testdata/test.pdf")),
+        ("kuva.png", include_bytes!("../../testdata/test.png")),
+    ]);
+    assert_eq!(check(&document), Vec::<String>::new());
+
+    let catalog = document.catalog().unwrap();
+    let xmp = catalog.get(b"Metadata").unwrap().as_reference().unwrap();
+    let xmp = document.get_object(xmp).unwrap().as_stream().unwrap();
+    let xmp = String::from_utf8(xmp.content.clone()).unwrap();
+    assert!(xmp.contains("Kahvia"));
+    assert!(xmp.contains("Matti Meikäläinen"));
+    assert!(xmp.contains("2024-10-19T12:00:00Z"));
+
+    let info = document
+        .trailer
+        .get(b"Info")
+        .unwrap()
+        .as_reference()
+        .unwrap();
+    let info = document.get_dictionary(info).unwrap();
+    assert_eq!(info.get(b"Title").unwrap().as_str().unwrap(), b"Kahvia");
+}
+
+#[test]
+fn invoice_data_is_embedded() {
+    let document = generate(&[]);
+
+    let catalog = document.catalog().unwrap();
+    let af = catalog.get(b"AF").unwrap().as_array().unwrap();
+    assert_eq!(af.len(), 1);
+    let spec = document
+        .get_dictionary(af[0].as_reference().unwrap())
+        .unwrap();
+    assert_eq!(spec.get(b"UF").unwrap().as_str().unwrap(), b"invoice.json");
+    assert_eq!(
+        spec.get(b"AFRelationship").unwrap().as_name().unwrap(),
+        b"Alternative"
+    );
+
+    let ef = spec.get(b"EF").unwrap().as_dict().unwrap();
+    let Object::Stream(stream) = document
+        .get_object(ef.get(b"F").unwrap().as_reference().unwrap())
+        .unwrap()
+    else {
+        panic!("the embedded file isn't a stream");
+    };
+    let data = stream
+        .decompressed_content()
+        .unwrap_or(stream.content.clone());
+    let invoice: serde_json::Value = serde_json::from_slice(&data).unwrap();
+    assert_eq!(invoice["subject"], "Kahvia");
+    assert_eq!(invoice["recipient_name"], "Matti Meikäläinen");
+}
+
+#[test]
+fn attachments_without_embedded_fonts_arent_claimed_to_conform() {
+    let document = generate(&[("kuitti.pdf", include_bytes!("../../testdata/receipt.pdf"))]);
+
+    let problems = check(&document);
+    assert!(
+        problems.iter().any(|p| p.contains("isn't embedded")),
+        "{problems:?}"
+    );
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.contains("doesn't identify the document as PDF/A-3b")),
+        "{problems:?}"
+    );
+}
@@ -0,0 +1,114 @@
+use super::{json, state, Multipart};
+use crate::api::app;
+use crate::api::receipts::Prefill;
+use crate::receipt::{Receipt, VatLine};
+use axum::http::StatusCode;
+use tower::ServiceExt;
+
+#[test]
+fn parse_finnish_receipt() {
+    let text = "\
+        Tervetuloa!\n\
+        S-market Leppävaara\n\
+        Leppävaarankatu 3-9, 02600 ESPOO\n\
+        Y-tunnus 0116323-1\n\
+        \n\
+        RUISLEIPÄ 500G        2,49\n\
+        BANAANI               1,05\n\
+        OLUT 0,33L 4 KPL      7,96\n\
+        YHT. 11,50 EUR\n\
+        ALV 14%   3,54  0,43\n\
+        ALV 25,5% 7,96  1,62\n\
+        Pankkikortti 11,50\n\
+        5.3.24 16:42 K2 M123\n";
+
+    assert_eq!(
+        Receipt::parse(text),
+        Receipt {
+            merchant: Some("S-market Leppävaara".into()),
+            date: Some("2024-03-05".into()),
+            total: Some(1150),
+            vat: vec![
+                VatLine {
+                    rate: 14.0,
+                    net: Some(311),
+                    tax: 43,
+                    gross: Some(354),
+                },
+                VatLine {
+                    rate: 25.5,
+                    net: Some(634),
+                    tax: 162,
+                    gross: Some(796),
+                },
+            ],
+        }
+    );
+}
+
+#[test]
+fn parse_text_without_receipt() {
+    assert_eq!(
+        Receipt::parse("Lorem ipsum 12"),
+        Receipt {
+            merchant: Some("Lorem ipsum 12".into()),
+            ..Default::default()
+        }
+    );
+}
+
+#[tokio::test]
+async fn prefill_from_pdf_text_layer() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .file(
+            "attachment",
+            "kuitti.pdf",
+            include_bytes!("../../testdata/receipt.pdf"),
+        )
+        .request("/receipts/prefill");
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let prefill: Prefill = json(response).await;
+    assert_eq!(
+        prefill.receipt.merchant.as_deref(),
+        Some("K-Market Otaniemi")
+    );
+    assert_eq!(prefill.receipt.date.as_deref(), Some("2024-10-19"));
+    assert_eq!(prefill.receipt.total, Some(1327));
+    assert_eq!(
+        prefill.receipt.vat,
+        vec![VatLine {
+            rate: 14.0,
+            net: Some(1164),
+            tax: 163,
+            gross: Some(1327),
+        }]
+    );
+
+    assert_eq!(prefill.invoice.subject, "K-Market Otaniemi");
+    assert_eq!(prefill.invoice.description, "K-Market Otaniemi 19.10.2024");
+    assert_eq!(prefill.invoice.rows.len(), 1);
+    assert_eq!(prefill.invoice.rows[0].unit_price, 1327);
+}
+
+#[tokio::test]
+async fn prefill_from_image_requires_tesseract() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = app().with_state(state(&dir).await);
+
+    let request = Multipart::new()
+        .file(
+            "attachment",
+            "kuitti.jpg",
+            include_bytes!("../../testdata/receipt.jpg"),
+        )
+        .request("/receipts/prefill");
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_IMPLEMENTED);
+}
@@ -0,0 +1,247 @@
+use super::{invoice_with_files, json, state, Multipart};
+use crate::api::app;
+use crate::api::invoices::generate_pdf;
+use crate::api::signatures::VerifyResponse;
+use crate::signing::Signer;
+use axum::http::StatusCode;
+use lopdf::{dictionary, Document, Object};
+use p12_keystore::{KeyStore, KeyStoreEntry, PrivateKeyChain};
+use p256::ecdsa::{DerSignature, SigningKey};
+use p256::pkcs8::{EncodePrivateKey, LineEnding};
+use std::str::FromStr;
+use std::time::Duration;
+use time::macros::datetime;
+use tower::ServiceExt;
+use uuid::Uuid;
+use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+use x509_cert::der::{Encode, EncodePem};
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::Validity;
+use x509_cert::Certificate;
+
+/// A self-signed certificate and its private key
+fn self_signed(seed: u8) -> (Certificate, SigningKey) {
+    let key = SigningKey::from_bytes(&[seed; 32].into()).unwrap();
+    let certificate = CertificateBuilder::new(
+        Profile::Root,
+        SerialNumber::from(seed as u32),
+        Validity::from_now(Duration::from_secs(3600)).unwrap(),
+        Name::from_str("CN=Laskugeneraattori,O=Testi").unwrap(),
+        SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()).unwrap(),
+        &key,
+    )
+    .unwrap()
+    .build::<DerSignature>()
+    .unwrap();
+
+    (certificate, key)
+}
+
+fn pem_signer((certificate, key): &(Certificate, SigningKey)) -> Signer {
+    Signer::load(
+        certificate.to_pem(LineEnding::LF).unwrap().as_bytes(),
+        Some(key.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()),
+        None,
+    )
+    .unwrap()
+}
+
+fn signed_pdf(signer: &Signer) -> Vec<u8> {
+    generate_pdf(
+        invoice_with_files(&[("kuitti.pdf", include_bytes!("../../testdata/test.pdf"))]),
+        Uuid::nil(),
+        datetime!(2024-10-19 12:00 UTC),
+        true,
+        signer,
+        None,
+    )
+    .unwrap()
+}
+
+#[test]
+fn signed_pdfs_are_verified() {
+    let signer = pem_signer(&self_signed(1));
+    let pdf = signed_pdf(&signer);
+    assert_eq!(pdf, signed_pdf(&signer));
+
+    let document = Document::load_mem(&pdf).unwrap();
+    assert_eq!(crate::pdfa::check(&document), Vec::<String>::new());
+
+    let verifications = signer.verify(&pdf).unwrap();
+    assert_eq!(verifications.len(), 1);
+    let verification = &verifications[0];
+    assert!(verification.valid, "{:?}", verification.error);
+    assert!(verification.covers_document);
+    assert!(verification.trusted);
+    assert_eq!(
+        verification.signer.as_deref(),
+        Some("CN=Laskugeneraattori,O=Testi")
+    );
+    assert_eq!(
+        verification.signed_at,
+        Some(datetime!(2024-10-19 12:00 UTC))
+    );
+
+    // Signed by someone else
+    for other in [pem_signer(&self_signed(2)), Signer::default()] {
+        let verification = &other.verify(&pdf).unwrap()[0];
+        assert!(verification.valid);
+        assert!(!verification.trusted);
+    }
+}
+
+#[test]
+fn modified_pdfs_are_detected() {
+    let signer = pem_signer(&self_signed(1));
+    let pdf = signed_pdf(&signer);
+
+    let title = pdf.windows(8).position(|w| w == b"(Kahvia)").unwrap();
+    let mut modified = pdf.clone();
+    modified[title + 5] = b'j';
+    let verification = &signer.verify(&modified).unwrap()[0];
+    assert!(!verification.valid);
+    assert!(!verification.trusted);
+    assert!(verification.error.as_ref().unwrap().contains("modified"));
+
+    let mut appended = pdf.clone();
+    appended.extend_from_slice(b"% Appended after signing\n");
+    let verification = &signer.verify(&appended).unwrap()[0];
+    assert!(verification.valid);
+    assert!(!verification.covers_document);
+}
+
+#[test]
+fn attachments_containing_the_placeholders_are_signed() {
+    let signer = pem_signer(&self_signed(1));
+    let mut attachment = Document::load_mem(include_bytes!("../../testdata/test.pdf")).unwrap();
+    let page_id = *attachment.get_pages().values().next().unwrap();
+    attachment.get_dictionary_mut(page_id).unwrap().set(
+        "Decoy",
+        Object::string_literal("[0 9999999999 9999999999 9999999999] /Contents<0000000000000000"),
+    );
+    let mut data = Vec::new();
+    attachment.save_to(&mut data).unwrap();
+
+    let pdf = generate_pdf(
+        invoice_with_files(&[("kuitti.pdf", &data)]),
+        Uuid::nil(),
+        datetime!(2024-10-19 12:00 UTC),
+        true,
+        &signer,
+        None,
+    )
+    .unwrap();
+    assert!(pdf.windows(13).any(|w| w == b"[0 9999999999"));
+    let verification = &signer.verify(&pdf).unwrap()[0];
+    assert!(verification.valid, "{:?}", verification.error);
+    assert!(verification.covers_document);
+}
+
+#[test]
+fn overflowing_byte_ranges_are_invalid() {
+    let mut document = Document::with_version("1.7");
+    document.add_object(dictionary! {
+        "Type" => "Sig",
+        "ByteRange" => vec![i64::MAX.into(), i64::MAX.into(), 1.into(), i64::MAX.into()],
+        "Contents" => Object::string_literal(""),
+    });
+    let mut pdf = Vec::new();
+    document.save_to(&mut pdf).unwrap();
+
+    let verification = &Signer::default().verify(&pdf).unwrap()[0];
+    assert!(!verification.valid);
+    assert!(!verification.covers_document);
+    assert!(verification.error.as_ref().unwrap().contains("byte range"));
+}
+
+#[test]
+fn certificates_are_loaded_from_pkcs12() {
+    let credentials = self_signed(1);
+    let (certificate, key) = &credentials;
+    let mut store = KeyStore::new();
+    store.add_entry(
+        "laskugeneraattori",
+        KeyStoreEntry::PrivateKeyChain(PrivateKeyChain::new(
+            key.to_pkcs8_der().unwrap().as_bytes(),
+            [1],
+            [p12_keystore::Certificate::from_der(&certificate.to_der().unwrap()).unwrap()],
+        )),
+    );
+    let pkcs12 = store.writer("salasana").write().unwrap();
+
+    let signer = Signer::load(&pkcs12, None, Some("salasana")).unwrap();
+    let verification = &pem_signer(&credentials)
+        .verify(&signed_pdf(&signer))
+        .unwrap()[0];
+    assert!(verification.valid);
+    assert!(verification.trusted);
+
+    assert!(Signer::load(&pkcs12, None, Some("väärä")).is_err());
+
+    // The key of another certificate
+    let (_, other) = self_signed(2);
+    assert!(Signer::load(
+        certificate.to_pem(LineEnding::LF).unwrap().as_bytes(),
+        Some(other.to_pkcs8_pem(LineEnding::LF).unwrap().as_bytes()),
+        None,
+    )
+    .is_err());
+}
+
+#[test]
+fn rsa_keys_are_supported() {
+    use rsa::pkcs1::EncodeRsaPrivateKey;
+
+    let key = rsa::RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap();
+    let signing_key = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(key.clone());
+    let certificate = CertificateBuilder::new(
+        Profile::Root,
+        SerialNumber::from(1u32),
+        Validity::from_now(Duration::from_secs(3600)).unwrap(),
+        Name::from_str("CN=Laskugeneraattori,O=Testi").unwrap(),
+        SubjectPublicKeyInfoOwned::from_key(key.to_public_key()).unwrap(),
+        &signing_key,
+    )
+    .unwrap()
+    .build::<rsa::pkcs1v15::Signature>()
+    .unwrap();
+
+    let signer = Signer::load(
+        certificate.to_pem(LineEnding::LF).unwrap().as_bytes(),
+        Some(key.to_pkcs1_pem(LineEnding::LF).unwrap().as_bytes()),
+        None,
+    )
+    .unwrap();
+    let verification = &signer.verify(&signed_pdf(&signer)).unwrap()[0];
+    assert!(verification.valid, "{:?}", verification.error);
+    assert!(verification.trusted);
+}
+
+#[tokio::test]
+async fn verify_signatures_of_uploaded_pdfs() {
+    let dir = tempfile::tempdir().unwrap();
+    let signer = pem_signer(&self_signed(1));
+    let mut state = state(&dir).await;
+    state.signer = signer.clone();
+    let app = app().with_state(state);
+
+    for (pdf, signed) in [
+        (signed_pdf(&signer), true),
+        (include_bytes!("../../testdata/test.pdf").to_vec(), false),
+    ] {
+        let request = Multipart::new()
+            .file("pdf", "lasku.pdf", &pdf)
+            .request("/signatures/verify");
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response: VerifyResponse = json(response).await;
+        assert_eq!(response.signatures.len(), signed as usize);
+        if signed {
+            assert!(response.signatures[0].valid);
+            assert!(response.signatures[0].trusted);
+        }
+    }
+}
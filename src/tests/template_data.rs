@@ -0,0 +1,57 @@
+use super::invoice_with_files;
+use typst::foundations::{Dict, IntoValue, Value};
+
+fn field(dict: &Dict, key: &str) -> Value {
+    dict.get(key).unwrap().clone()
+}
+
+#[test]
+fn invoices_are_converted_without_the_attachment_contents() {
+    let mut invoice = invoice_with_files(&[
+        ("kuitti.pdf", include_bytes!("../../testdata/test.pdf")),
+        ("kuva.png", include_bytes!("../../testdata/test.png")),
+    ]);
+    invoice.attachment_passwords = vec!["salasana".into()];
+    let total = invoice.total();
+    let sizes = invoice
+        .attachments
+        .iter()
+        .map(|a| a.size)
+        .collect::<Vec<_>>();
+
+    let Value::Dict(data) = invoice.into_value() else {
+        panic!("the invoice isn't a dictionary");
+    };
+    assert_eq!(field(&data, "total"), Value::Int(total));
+    assert!(data.get("attachment_passwords").is_err());
+    assert_eq!(field(&data, "address").cast::<Dict>().unwrap().len(), 3);
+
+    let rows = field(&data, "rows").cast::<Vec<Dict>>().unwrap();
+    let row_total = rows
+        .iter()
+        .map(|r| field(r, "total").cast::<i64>().unwrap())
+        .sum::<i64>();
+    assert_eq!(row_total, total);
+
+    let attachments = field(&data, "attachments").cast::<Vec<Dict>>().unwrap();
+    let expected = [
+        ("kuitti.pdf", "pdf", "application/pdf"),
+        ("kuva.png", "png", "image/png"),
+    ];
+    for ((attachment, (filename, format, mime)), size) in
+        attachments.iter().zip(expected).zip(sizes)
+    {
+        assert_eq!(
+            attachment
+                .iter()
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>(),
+            ["filename", "size", "format", "mime", "pages"]
+        );
+        assert_eq!(field(attachment, "filename"), filename.into_value());
+        assert_eq!(field(attachment, "size"), size.into_value());
+        assert_eq!(field(attachment, "format"), format.into_value());
+        assert_eq!(field(attachment, "mime"), mime.into_value());
+        assert_eq!(field(attachment, "pages"), Value::Int(1));
+    }
+}
@@ -0,0 +1,38 @@
+#let price(number) = {
+  let num_as_str = str(number)
+  let whole_nums="0"
+  if num_as_str.len() > 2 {
+    whole_nums = num_as_str.slice(0, -2)
+  }
+  let rem = "00"
+  if num_as_str.len() == 1 {
+    rem = "0" + num_as_str
+  } else if num_as_str.len() >= 2 {
+    rem = num_as_str.slice(-2)
+  }
+  whole_nums+","+rem
+}
+
+#set page(
+  footer: [
+    #align(right)[Laskugeneraattori #VERSION #link("https://github.com/Tietokilta/laskugeneraattori/commit/" + COMMIT_HASH)[#COMMIT_HASH.slice(0, 7)]]
+  ],
+)
+#set text(lang: "fi")
+
+= HYVÄKSYNTÄ
+
+#table(columns: (1fr, 2fr),
+  [*Lasku*], [#data.id],
+  [*Aihe*], [#data.subject],
+  [*Laskuttaja*], [#data.recipient_name],
+  [*Summa*], [#price(data.total) €],
+  [*Lähetetty*], [#data.created_at],
+  [*Hyväksytty*], [#approval.day\.#approval.month\.#approval.year TiKH:n kokouksessa #approval.meeting/#approval.year],
+  [*Tili*], [#approval.account],
+  [*Hyväksyjä*], [#approval.approver],
+  [*Kirjattu*], [#approval.approved_at],
+)
+
+Tämä sivu on lisätty laskuun sen hyväksymisen yhteydessä. Alkuperäinen lasku
+säilytetään muuttamattomana.
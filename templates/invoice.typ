@@ -33,6 +33,10 @@
   line(length: length, start: (0pt, 1em))
 }
 
+#let filled(value) = {
+  box(width: 5em, stroke: (bottom: 0.5pt), align(center)[#value])
+}
+
 #move(dx: -10%, dy: -5%, box(
   width: 120%,
   inset: 1em,
@@ -40,17 +44,32 @@
 )[
   #let year = datetime.today().year()
   == Rahastonhoitajan merkintöjä:
-  #stack(dir: ltr)[Hyväksytty][
-    #writeline(5em)
-  ][.][
-    #writeline(5em)
-  ][.#year][
-    #h(1em) TiKH:n kokouksessa
-  ][
-    #writeline(5em)
-  ][/#year kohdistettavaksi tilille][
-    #writeline(5em)
-  ]
+  #if approval == none {
+    stack(dir: ltr)[Hyväksytty][
+      #writeline(5em)
+    ][.][
+      #writeline(5em)
+    ][.#year][
+      #h(1em) TiKH:n kokouksessa
+    ][
+      #writeline(5em)
+    ][/#year kohdistettavaksi tilille][
+      #writeline(5em)
+    ]
+  } else {
+    stack(dir: ltr)[Hyväksytty][
+      #filled(approval.day)
+    ][.][
+      #filled(approval.month)
+    ][.#approval.year][
+      #h(1em) TiKH:n kokouksessa
+    ][
+      #filled(approval.meeting)
+    ][/#approval.year kohdistettavaksi tilille][
+      #filled(approval.account)
+    ]
+    [Hyväksyjä: #approval.approver]
+  }
   #stack(dir: ltr)[Maksettu][
     #writeline(5em)
   ][.][
@@ -83,12 +102,12 @@
 
 === Erittely
 #let rows = data.rows.map(it => ([#it.product], [#it.quantity #it.unit],
-      [#price(it.unit_price) €], [#price(it.quantity*it.unit_price) €]))
+      [#price(it.unit_price) €], [#price(it.total) €]))
 #table(columns: (55%, 15%, 15%, 15%),
   align: (left, right, right, right),
   table.header([*Tuote*], [*Määrä*],  [*Hinta per*], [*Yhteensä*]),
   ..rows.flatten(),
-  ..([], [], [], [*#price(data.rows.map(r => r.unit_price*r.quantity).sum()) €*])
+  ..([], [], [], [*#price(data.total) €*])
 )
 
 *IBAN-tilinumero*: #data.bank_account_number \
@@ -106,9 +125,9 @@
     ).flatten()
 )
 
-#for file in data.attachments {
-  if regex("(?i)\.(jpg|jpeg|png|gif|svg)$") in file.filename {
+#for (i, file) in data.attachments.enumerate() {
+  if file.format != "pdf" {
     pagebreak()
-    image("/attachments/" + file.filename)
+    image("/attachments/" + str(i), format: file.format)
   }
 }